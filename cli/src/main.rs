@@ -0,0 +1,1574 @@
+//! `prophecy` — operate Prophecy prediction market streams from the shell.
+
+#![recursion_limit = "256"]
+
+mod output;
+
+use std::rc::Rc;
+
+use anchor_client::anchor_lang::prelude::Pubkey;
+use anchor_client::solana_sdk::commitment_config::CommitmentConfig;
+use anchor_client::solana_sdk::instruction::Instruction;
+use anchor_client::solana_sdk::signature::{read_keypair_file, Keypair, Signer};
+use anchor_client::{Client, Cluster, Program};
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use prophecy_sdk::instructions::Trader;
+use prophecy_sdk::pda::{
+    batch_order_pda, challenge_pda, commit_batch_pda, commitment_pda, config_pda, limit_order_pda,
+    order_batch_pda, position_trigger_pda, promo_credit_pda, referral_pda, session_token_pda,
+    stream_pda, user_position_pda, user_profile_pda,
+};
+use prophecy_sdk::{
+    auction_batch_end, commit_window, commitment_hash, instructions, quote, Challenge, Commitment,
+    LimitOrder, PositionTrigger, Referral, Stream, UserPosition, UserProfile, PROGRAM_ID,
+};
+use serde_json::json;
+use solana_rpc_client_api::filter::{Memcmp, RpcFilterType};
+
+const MATCH_ORDERS_PER_TX: usize = 5;
+
+#[derive(Parser)]
+#[command(
+    name = "prophecy",
+    version,
+    about = "Operate Prophecy prediction market streams"
+)]
+struct Cli {
+    /// Cluster moniker (localnet, devnet, mainnet) or RPC URL
+    #[arg(
+        long,
+        short = 'u',
+        global = true,
+        env = "PROPHECY_CLUSTER",
+        default_value = "localnet"
+    )]
+    cluster: String,
+
+    /// Keypair used to sign and pay for transactions
+    #[arg(long, short = 'k', global = true, env = "PROPHECY_KEYPAIR")]
+    keypair: Option<String>,
+
+    /// Print results as JSON
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Trade for this wallet, signing with the keypair as its session key
+    #[arg(long, global = true)]
+    session_for: Option<Pubkey>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a new stream market (signer becomes the authority)
+    Create {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        team_a: String,
+        #[arg(long)]
+        team_b: String,
+        /// Total virtual liquidity in lamports, split evenly between teams
+        #[arg(long)]
+        liquidity: u64,
+        /// Trading window in seconds
+        #[arg(long)]
+        duration: i64,
+        #[arg(long, default_value = "")]
+        link: String,
+    },
+    /// Buy shares of a team
+    Buy {
+        #[arg(long)]
+        stream_id: u64,
+        /// 1 for team A, 2 for team B
+        #[arg(long)]
+        team: u8,
+        #[arg(long)]
+        lamports: u64,
+        /// Pay from the signer's promo credit for this stream id (0 for a global credit)
+        #[arg(long)]
+        credit: Option<u64>,
+        /// Registered referrer who earns a share of the trading fee
+        #[arg(long, conflicts_with = "credit")]
+        referrer: Option<Pubkey>,
+    },
+    /// Sell shares of a team back to the curve
+    Sell {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        team: u8,
+        #[arg(long)]
+        shares: u64,
+    },
+    /// Sell all, or a percentage, of the signer's shares
+    CashOut {
+        #[arg(long)]
+        stream_id: u64,
+        /// Only sell this team's shares; both teams when omitted
+        #[arg(long)]
+        team: Option<u8>,
+        /// Portion to sell in basis points
+        #[arg(long, default_value_t = 10_000)]
+        pct_bps: u16,
+    },
+    /// Move shares from one team to the other without leaving the pool
+    Swap {
+        #[arg(long)]
+        stream_id: u64,
+        /// Team whose shares are given up
+        #[arg(long)]
+        from_team: u8,
+        #[arg(long)]
+        shares: u64,
+        /// Fail if fewer shares of the other team would come back
+        #[arg(long, default_value_t = 0)]
+        min_out: u64,
+    },
+    /// Set bet limits on a stream; zero disables a limit (authority only)
+    Limits {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long, default_value_t = 0)]
+        min_bet: u64,
+        #[arg(long, default_value_t = 0)]
+        max_bet: u64,
+        #[arg(long, default_value_t = 0)]
+        max_position: u64,
+        #[arg(long, default_value_t = 0)]
+        max_impact_bps: u16,
+    },
+    /// Configure the circuit breaker; zero threshold disables it and any
+    /// call lifts an active halt (authority only)
+    Breaker {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long, default_value_t = 0)]
+        threshold_bps: u16,
+        #[arg(long, default_value_t = 0)]
+        window_slots: u64,
+        #[arg(long, default_value_t = 0)]
+        cooldown_slots: u64,
+    },
+    /// Set the purchase fee and the referrer's share of it (authority only)
+    Fees {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long, default_value_t = 0)]
+        fee_bps: u16,
+        /// Portion of the fee paid to the buyer's referrer, in basis points
+        #[arg(long, default_value_t = 0)]
+        referral_share_bps: u16,
+    },
+    /// Withdraw the protocol's fees once the stream has ended, paying the
+    /// streamer's cut first if still owed (authority only)
+    WithdrawFees {
+        #[arg(long)]
+        stream_id: u64,
+    },
+    /// End a stream and declare the winner (authority only)
+    End {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        winner: u8,
+    },
+    /// Call a stream off instead of declaring a winner (authority only)
+    Void {
+        #[arg(long)]
+        stream_id: u64,
+    },
+    /// Reclaim the signer's net invested lamports from a voided stream
+    Refund {
+        #[arg(long)]
+        stream_id: u64,
+    },
+    /// Claim winnings from an ended stream
+    Claim {
+        #[arg(long)]
+        stream_id: u64,
+        /// Relay the claim for another wallet; the payout goes to that wallet
+        #[arg(long)]
+        owner: Option<Pubkey>,
+    },
+    /// Bring a stream, or one wallet's position in it, up to the current account layout
+    Migrate {
+        #[arg(long)]
+        stream_id: u64,
+        /// Migrate this wallet's position instead of the stream
+        #[arg(long)]
+        owner: Option<Pubkey>,
+    },
+    /// Drain an ended stream's vault to the authority
+    Withdraw {
+        #[arg(long)]
+        stream_id: u64,
+    },
+    /// Show a single stream
+    Stream {
+        #[arg(long)]
+        stream_id: u64,
+    },
+    /// List all streams
+    Streams,
+    /// List positions held by a wallet (defaults to the signer)
+    Positions {
+        #[arg(long)]
+        owner: Option<Pubkey>,
+        #[arg(long)]
+        stream_id: Option<u64>,
+    },
+    /// Show a wallet's lifetime profile (defaults to the signer)
+    Profile {
+        #[arg(long)]
+        owner: Option<Pubkey>,
+    },
+    /// Record a finished position's win or loss in its owner's profile
+    SettleProfile {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        owner: Pubkey,
+    },
+    /// Quote a trade or claim without sending a transaction
+    Quote {
+        #[command(subcommand)]
+        kind: QuoteKind,
+    },
+    /// Commit-reveal betting
+    Commit {
+        #[command(subcommand)]
+        kind: CommitKind,
+    },
+    /// Frequent batch auction trading
+    Auction {
+        #[command(subcommand)]
+        kind: AuctionKind,
+    },
+    /// Limit orders filled against the curve by keepers
+    Limit {
+        #[command(subcommand)]
+        kind: LimitKind,
+    },
+    /// Stop-loss / take-profit triggers executed by keepers
+    Trigger {
+        #[command(subcommand)]
+        kind: TriggerKind,
+    },
+    /// Name the wallet allowed to issue global promo credits (upgrade authority only)
+    SetAdmin {
+        #[arg(long)]
+        admin: Pubkey,
+    },
+    /// Free-bet promo credits
+    Promo {
+        #[command(subcommand)]
+        kind: PromoKind,
+    },
+    /// Session keys that trade on a wallet's behalf without a prompt per bet
+    Session {
+        #[command(subcommand)]
+        kind: SessionKind,
+    },
+    /// Referral fee sharing
+    Referral {
+        #[command(subcommand)]
+        kind: ReferralKind,
+    },
+    /// Streamer payouts and tips
+    Streamer {
+        #[command(subcommand)]
+        kind: StreamerKind,
+    },
+    /// Head-to-head bets against another wallet at fixed odds
+    Challenge {
+        #[command(subcommand)]
+        kind: ChallengeKind,
+    },
+}
+
+#[derive(Subcommand)]
+enum ChallengeKind {
+    /// Offer a challenge, escrowing the signer's stake
+    Create {
+        #[arg(long)]
+        stream_id: u64,
+        /// Any id not already used by one of the signer's open challenges on this stream
+        #[arg(long)]
+        challenge_id: u64,
+        /// Team the signer backs
+        #[arg(long)]
+        team: u8,
+        /// Lamports the signer puts up
+        #[arg(long)]
+        stake: u64,
+        /// Lamports the counterparty must put up; sets the odds
+        #[arg(long)]
+        counter_stake: u64,
+        /// Only this wallet may accept; open to anyone when omitted
+        #[arg(long)]
+        counterparty: Option<Pubkey>,
+    },
+    /// Accept a challenge, escrowing the counterparty stake
+    Accept {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        creator: Pubkey,
+        #[arg(long)]
+        challenge_id: u64,
+    },
+    /// Withdraw one of the signer's unaccepted challenges
+    Cancel {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        challenge_id: u64,
+    },
+    /// Settle every accepted challenge on a resolved or voided stream (any keeper)
+    Settle {
+        #[arg(long)]
+        stream_id: u64,
+    },
+    /// List challenges on a stream
+    List {
+        #[arg(long)]
+        stream_id: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum StreamerKind {
+    /// Set the streamer payout key and their cut of protocol fees (authority only)
+    Set {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        streamer: Pubkey,
+        #[arg(long, default_value_t = 0)]
+        cut_bps: u16,
+    },
+    /// Tip a stream's streamer
+    Tip {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        lamports: u64,
+    },
+    /// Pay an ended stream's streamer their cut of protocol fees
+    Pay {
+        #[arg(long)]
+        stream_id: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum ReferralKind {
+    /// Register the signer as a referrer
+    Register,
+    /// Withdraw the signer's earned referral fees
+    Claim,
+    /// Show a referrer's earnings (defaults to the signer)
+    Show {
+        #[arg(long)]
+        referrer: Option<Pubkey>,
+    },
+}
+
+#[derive(Subcommand)]
+enum PromoKind {
+    /// Fund a free-bet credit for a wallet
+    Issue {
+        #[arg(long)]
+        user: Pubkey,
+        /// Stream the credit is limited to (signer must be its authority); 0 for any
+        /// stream (signer must be the program admin)
+        #[arg(long, default_value_t = 0)]
+        stream_id: u64,
+        #[arg(long)]
+        lamports: u64,
+    },
+    /// Close a credit the signer issued and reclaim its unspent balance
+    Revoke {
+        #[arg(long)]
+        user: Pubkey,
+        #[arg(long, default_value_t = 0)]
+        stream_id: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionKind {
+    /// Authorize a session key for the signer, escrowing its spending limit
+    Create {
+        /// Public key of the ephemeral signer
+        #[arg(long)]
+        session_signer: Pubkey,
+        /// Seconds from now until the session expires
+        #[arg(long, default_value_t = 3600)]
+        duration_secs: i64,
+        /// Lamports the session key may bet in total
+        #[arg(long)]
+        limit: u64,
+    },
+    /// Revoke the signer's session and reclaim the unspent budget
+    Revoke,
+}
+
+#[derive(Subcommand)]
+enum TriggerKind {
+    /// Attach a trigger to the signer's shares of one team
+    Set {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        team: u8,
+        /// Sell at or below this price, zero disables (1e9 = 1 lamport per share)
+        #[arg(long, default_value_t = 0)]
+        stop_loss: u64,
+        /// Sell at or above this price, zero disables
+        #[arg(long, default_value_t = 0)]
+        take_profit: u64,
+        /// Worst average fill allowed versus the crossed stop-loss or take-profit price
+        #[arg(long, default_value_t = 100)]
+        slippage_bps: u16,
+        /// Lamports paid to the keeper that executes the trigger
+        #[arg(long, default_value_t = 0)]
+        tip: u64,
+    },
+    /// Remove one of the signer's triggers and reclaim its tip
+    Cancel {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        team: u8,
+    },
+    /// Execute every trigger on a stream whose threshold has been crossed (any keeper)
+    Run {
+        #[arg(long)]
+        stream_id: u64,
+    },
+    /// List triggers on a stream
+    List {
+        #[arg(long)]
+        stream_id: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum LimitKind {
+    /// Place a limit order
+    Place {
+        #[arg(long)]
+        stream_id: u64,
+        /// Any id not already used by one of the signer's open orders on this stream
+        #[arg(long)]
+        order_id: u64,
+        #[arg(long)]
+        team: u8,
+        /// Sell shares instead of buying
+        #[arg(long)]
+        sell: bool,
+        /// Lamports for a buy, shares for a sell
+        #[arg(long)]
+        amount: u64,
+        /// Price on the calculate_price scale (1e9 = 1 lamport per share)
+        #[arg(long)]
+        price: u64,
+    },
+    /// Cancel one of the signer's limit orders
+    Cancel {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        order_id: u64,
+    },
+    /// Fill every open order on a stream whose limit has been crossed (any keeper)
+    Match {
+        #[arg(long)]
+        stream_id: u64,
+    },
+    /// List open limit orders on a stream
+    List {
+        #[arg(long)]
+        stream_id: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuctionKind {
+    /// Set the auction interval; zero restores continuous trading (authority only)
+    Config {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long, default_value_t = 0)]
+        interval_secs: i64,
+    },
+    /// Place an order into the open batch
+    Order {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        team: u8,
+        /// Sell shares instead of buying
+        #[arg(long)]
+        sell: bool,
+        /// Lamports for a buy, shares for a sell
+        #[arg(long)]
+        amount: u64,
+    },
+    /// Fill a closed batch (any keeper)
+    Clear {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        batch_end: i64,
+    },
+    /// Pay out a cleared order (any keeper; defaults to the signer's order)
+    Settle {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        batch_end: i64,
+        #[arg(long)]
+        owner: Option<Pubkey>,
+    },
+    /// Withdraw an order from an open or never-cleared batch
+    Cancel {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        batch_end: i64,
+    },
+}
+
+#[derive(Subcommand)]
+enum CommitKind {
+    /// Set commit and reveal phase lengths; zeros disable the mode (authority only)
+    Config {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long, default_value_t = 0)]
+        commit_secs: i64,
+        #[arg(long, default_value_t = 0)]
+        reveal_secs: i64,
+    },
+    /// Commit to a hidden bet in the current commit phase
+    Bet {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        team: u8,
+        #[arg(long)]
+        lamports: u64,
+        /// Lamports to escrow, at least `lamports`; defaults to `lamports`
+        #[arg(long)]
+        escrow: Option<u64>,
+        /// 32-byte hex salt; keep it, the reveal needs it
+        #[arg(long, value_parser = parse_salt)]
+        salt: [u8; 32],
+    },
+    /// Reveal a committed bet during its reveal window
+    Reveal {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        reveal_end: i64,
+        #[arg(long)]
+        team: u8,
+        #[arg(long)]
+        lamports: u64,
+        #[arg(long, value_parser = parse_salt)]
+        salt: [u8; 32],
+    },
+    /// Fill a batch once its reveal window has closed (any keeper)
+    Clear {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        reveal_end: i64,
+    },
+    /// Move a cleared commitment's shares into the signer's position
+    Settle {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        reveal_end: i64,
+    },
+    /// Refund an unrevealed or never-cleared commitment
+    Cancel {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        reveal_end: i64,
+    },
+}
+
+#[derive(Subcommand)]
+enum QuoteKind {
+    Buy {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        team: u8,
+        #[arg(long)]
+        lamports: u64,
+    },
+    Sell {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        team: u8,
+        #[arg(long)]
+        shares: u64,
+    },
+    Swap {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        from_team: u8,
+        #[arg(long)]
+        shares: u64,
+    },
+    Claim {
+        #[arg(long)]
+        stream_id: u64,
+        #[arg(long)]
+        owner: Option<Pubkey>,
+    },
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let keypair_path = match &cli.keypair {
+        Some(path) => path.clone(),
+        None => {
+            let home = std::env::var("HOME").map_err(|_| anyhow!("HOME is not set"))?;
+            format!("{home}/.config/solana/id.json")
+        }
+    };
+    let payer = Rc::new(
+        read_keypair_file(&keypair_path)
+            .map_err(|e| anyhow!("failed to read keypair {keypair_path}: {e}"))?,
+    );
+    let cluster: Cluster = cli.cluster.parse()?;
+    let client = Client::new_with_options(cluster, payer.clone(), CommitmentConfig::confirmed());
+    let program = client.program(PROGRAM_ID)?;
+    let signer = payer.pubkey();
+    let trader = match cli.session_for {
+        Some(user) => Trader::Session {
+            user,
+            session_signer: signer,
+        },
+        None => Trader::Owner(signer),
+    };
+    let user = trader.user();
+
+    let value = match cli.command {
+        Command::Create {
+            stream_id,
+            team_a,
+            team_b,
+            liquidity,
+            duration,
+            link,
+        } => send(
+            &program,
+            instructions::initialize_stream(
+                signer, stream_id, team_a, team_b, liquidity, duration, link,
+            ),
+            json!({ "stream": stream_pda(stream_id).0.to_string() }),
+        )?,
+        Command::Buy {
+            stream_id,
+            team,
+            lamports,
+            credit,
+            referrer,
+        } => {
+            if credit.is_some() && matches!(trader, Trader::Session { .. }) {
+                return Err(anyhow!("a session key cannot spend promo credit"));
+            }
+            let referrer = referrer.or(position_referrer(&program, stream_id, &user));
+            send(
+                &program,
+                match (credit, referrer) {
+                    (Some(credit_stream_id), referrer) => instructions::purchase_with_promo_credit(
+                        signer,
+                        stream_id,
+                        team,
+                        lamports,
+                        credit_stream_id,
+                        referrer,
+                    ),
+                    (None, Some(referrer)) => instructions::referred_purchase_shares(
+                        trader, referrer, stream_id, team, lamports,
+                    ),
+                    (None, None) => {
+                        instructions::purchase_shares(trader, stream_id, team, lamports)
+                    }
+                },
+                json!({ "position": user_position_pda(stream_id, &user).0.to_string() }),
+            )?
+        }
+        Command::Sell {
+            stream_id,
+            team,
+            shares,
+        } => send(
+            &program,
+            instructions::sell_shares(trader, stream_id, team, shares),
+            json!({ "position": user_position_pda(stream_id, &user).0.to_string() }),
+        )?,
+        Command::CashOut {
+            stream_id,
+            team,
+            pct_bps,
+        } => send(
+            &program,
+            instructions::cash_out(trader, stream_id, team.unwrap_or(0), pct_bps),
+            json!({ "position": user_position_pda(stream_id, &user).0.to_string() }),
+        )?,
+        Command::Swap {
+            stream_id,
+            from_team,
+            shares,
+            min_out,
+        } => send(
+            &program,
+            instructions::swap_sides(
+                trader,
+                stream_id,
+                from_team,
+                shares,
+                min_out,
+                position_referrer(&program, stream_id, &user),
+            ),
+            json!({ "position": user_position_pda(stream_id, &user).0.to_string() }),
+        )?,
+        Command::Limits {
+            stream_id,
+            min_bet,
+            max_bet,
+            max_position,
+            max_impact_bps,
+        } => send(
+            &program,
+            instructions::set_bet_limits(
+                signer,
+                stream_id,
+                min_bet,
+                max_bet,
+                max_position,
+                max_impact_bps,
+            ),
+            json!({ "stream": stream_pda(stream_id).0.to_string() }),
+        )?,
+        Command::Breaker {
+            stream_id,
+            threshold_bps,
+            window_slots,
+            cooldown_slots,
+        } => send(
+            &program,
+            instructions::set_circuit_breaker(
+                signer,
+                stream_id,
+                threshold_bps,
+                window_slots,
+                cooldown_slots,
+            ),
+            json!({ "stream": stream_pda(stream_id).0.to_string() }),
+        )?,
+        Command::Migrate { stream_id, owner } => match owner {
+            Some(owner) => send(
+                &program,
+                instructions::migrate_position(signer, stream_id, owner),
+                json!({ "position": user_position_pda(stream_id, &owner).0.to_string() }),
+            )?,
+            None => send(
+                &program,
+                instructions::migrate_stream(signer, stream_id),
+                json!({ "stream": stream_pda(stream_id).0.to_string() }),
+            )?,
+        },
+        Command::Fees {
+            stream_id,
+            fee_bps,
+            referral_share_bps,
+        } => send(
+            &program,
+            instructions::set_fees(signer, stream_id, fee_bps, referral_share_bps),
+            json!({ "stream": stream_pda(stream_id).0.to_string() }),
+        )?,
+        Command::WithdrawFees { stream_id } => {
+            let stream: Stream = program.account(stream_pda(stream_id).0)?;
+            send(
+                &program,
+                instructions::withdraw_fees(signer, stream_id, stream.streamer),
+                json!({ "stream": stream_pda(stream_id).0.to_string() }),
+            )?
+        }
+        Command::End { stream_id, winner } => send(
+            &program,
+            instructions::end_stream(signer, stream_id, winner),
+            json!({ "stream": stream_pda(stream_id).0.to_string() }),
+        )?,
+        Command::Void { stream_id } => send(
+            &program,
+            instructions::void_stream(signer, stream_id),
+            json!({ "stream": stream_pda(stream_id).0.to_string() }),
+        )?,
+        Command::Refund { stream_id } => send(
+            &program,
+            instructions::claim_refund(signer, stream_id),
+            json!({ "position": user_position_pda(stream_id, &signer).0.to_string() }),
+        )?,
+        Command::Claim { stream_id, owner } => match owner {
+            Some(owner) if owner != signer => send(
+                &program,
+                instructions::relay_claim(signer, stream_id, owner),
+                json!({ "position": user_position_pda(stream_id, &owner).0.to_string() }),
+            )?,
+            _ => send(
+                &program,
+                instructions::claim_winnings(signer, stream_id),
+                json!({ "position": user_position_pda(stream_id, &signer).0.to_string() }),
+            )?,
+        },
+        Command::Withdraw { stream_id } => send(
+            &program,
+            instructions::emergency_withdraw(signer, stream_id),
+            json!({ "stream": stream_pda(stream_id).0.to_string() }),
+        )?,
+        Command::Stream { stream_id } => {
+            let address = stream_pda(stream_id).0;
+            let stream: Stream = program.account(address)?;
+            output::stream(&address, &stream)
+        }
+        Command::Streams => {
+            let mut streams = program.accounts::<Stream>(vec![])?;
+            streams.sort_by_key(|(_, stream)| stream.stream_id);
+            streams
+                .iter()
+                .map(|(address, stream)| output::stream(address, stream))
+                .collect()
+        }
+        Command::Positions { owner, stream_id } => {
+            let owner = owner.unwrap_or(signer);
+            // UserPosition layout: discriminator (8) | user (32) | stream_id (8) | ...
+            let mut filters = vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                8,
+                owner.as_ref(),
+            ))];
+            if let Some(stream_id) = stream_id {
+                filters.push(RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                    40,
+                    &stream_id.to_le_bytes(),
+                )));
+            }
+            let mut positions = program.accounts::<UserPosition>(filters)?;
+            positions.sort_by_key(|(_, position)| position.stream_id);
+            positions
+                .iter()
+                .map(|(address, position)| output::position(address, position))
+                .collect()
+        }
+        Command::Profile { owner } => {
+            let address = user_profile_pda(&owner.unwrap_or(signer)).0;
+            let profile: UserProfile = program.account(address)?;
+            output::profile(&address, &profile)
+        }
+        Command::SettleProfile { stream_id, owner } => send(
+            &program,
+            instructions::settle_profile(signer, stream_id, owner),
+            json!({ "profile": user_profile_pda(&owner).0.to_string() }),
+        )?,
+        Command::Quote { kind } => match kind {
+            QuoteKind::Buy {
+                stream_id,
+                team,
+                lamports,
+            } => {
+                let stream: Stream = program.account(stream_pda(stream_id).0)?;
+                output::buy_quote(&quote::quote_buy(&stream, team, lamports)?)
+            }
+            QuoteKind::Sell {
+                stream_id,
+                team,
+                shares,
+            } => {
+                let stream: Stream = program.account(stream_pda(stream_id).0)?;
+                output::sell_quote(&quote::quote_sell(&stream, team, shares)?)
+            }
+            QuoteKind::Swap {
+                stream_id,
+                from_team,
+                shares,
+            } => {
+                let stream: Stream = program.account(stream_pda(stream_id).0)?;
+                let (sol_value, fee, shares_out) =
+                    quote::calculate_swap_out(&stream, from_team, shares)?;
+                json!({ "sol_value": sol_value, "fee": fee, "shares_out": shares_out })
+            }
+            QuoteKind::Claim { stream_id, owner } => {
+                let owner = owner.unwrap_or(signer);
+                let stream: Stream = program.account(stream_pda(stream_id).0)?;
+                let position: UserPosition =
+                    program.account(user_position_pda(stream_id, &owner).0)?;
+                output::claim_quote(&quote::quote_claim(&stream, &position)?)
+            }
+        },
+        Command::Commit { kind } => match kind {
+            CommitKind::Config {
+                stream_id,
+                commit_secs,
+                reveal_secs,
+            } => send(
+                &program,
+                instructions::set_commit_reveal(signer, stream_id, commit_secs, reveal_secs),
+                json!({ "stream": stream_pda(stream_id).0.to_string() }),
+            )?,
+            CommitKind::Bet {
+                stream_id,
+                team,
+                lamports,
+                escrow,
+                salt,
+            } => {
+                let stream: Stream = program.account(stream_pda(stream_id).0)?;
+                let rpc = program.rpc();
+                let now = rpc.get_block_time(rpc.get_slot()?)?;
+                let (_, reveal_end) = commit_window(&stream, now)?;
+                let hash = commitment_hash(stream_id, &signer, team, lamports, &salt);
+                send(
+                    &program,
+                    instructions::commit_bet(
+                        signer,
+                        stream_id,
+                        reveal_end,
+                        hash,
+                        escrow.unwrap_or(lamports),
+                    ),
+                    json!({
+                        "commitment": commitment_pda(stream_id, &signer, reveal_end).0.to_string(),
+                        "reveal_end": reveal_end,
+                    }),
+                )?
+            }
+            CommitKind::Reveal {
+                stream_id,
+                reveal_end,
+                team,
+                lamports,
+                salt,
+            } => send(
+                &program,
+                instructions::reveal_bet(signer, stream_id, reveal_end, team, lamports, salt),
+                json!({ "batch": commit_batch_pda(stream_id, reveal_end).0.to_string() }),
+            )?,
+            CommitKind::Clear {
+                stream_id,
+                reveal_end,
+            } => send(
+                &program,
+                instructions::clear_commit_batch(signer, stream_id, reveal_end),
+                json!({ "batch": commit_batch_pda(stream_id, reveal_end).0.to_string() }),
+            )?,
+            CommitKind::Settle {
+                stream_id,
+                reveal_end,
+            } => send(
+                &program,
+                instructions::settle_commitment(signer, stream_id, reveal_end),
+                json!({ "position": user_position_pda(stream_id, &signer).0.to_string() }),
+            )?,
+            CommitKind::Cancel {
+                stream_id,
+                reveal_end,
+            } => {
+                let commitment: Commitment =
+                    program.account(commitment_pda(stream_id, &signer, reveal_end).0)?;
+                send(
+                    &program,
+                    instructions::cancel_commitment(
+                        signer,
+                        stream_id,
+                        reveal_end,
+                        commitment.team_id != 0,
+                    ),
+                    json!({ "refunded": commitment.escrow }),
+                )?
+            }
+        },
+        Command::Auction { kind } => match kind {
+            AuctionKind::Config {
+                stream_id,
+                interval_secs,
+            } => send(
+                &program,
+                instructions::set_auction_mode(signer, stream_id, interval_secs),
+                json!({ "stream": stream_pda(stream_id).0.to_string() }),
+            )?,
+            AuctionKind::Order {
+                stream_id,
+                team,
+                sell,
+                amount,
+            } => {
+                let stream: Stream = program.account(stream_pda(stream_id).0)?;
+                let rpc = program.rpc();
+                let now = rpc.get_block_time(rpc.get_slot()?)?;
+                let batch_end = auction_batch_end(&stream, now)?;
+                send(
+                    &program,
+                    instructions::submit_order(trader, stream_id, batch_end, team, !sell, amount),
+                    json!({
+                        "order": batch_order_pda(stream_id, &user, batch_end).0.to_string(),
+                        "batch_end": batch_end,
+                    }),
+                )?
+            }
+            AuctionKind::Clear {
+                stream_id,
+                batch_end,
+            } => send(
+                &program,
+                instructions::clear_batch(signer, stream_id, batch_end),
+                json!({ "batch": order_batch_pda(stream_id, batch_end).0.to_string() }),
+            )?,
+            AuctionKind::Settle {
+                stream_id,
+                batch_end,
+                owner,
+            } => {
+                let owner = owner.unwrap_or(signer);
+                send(
+                    &program,
+                    instructions::settle_order(signer, stream_id, batch_end, owner),
+                    json!({ "position": user_position_pda(stream_id, &owner).0.to_string() }),
+                )?
+            }
+            AuctionKind::Cancel {
+                stream_id,
+                batch_end,
+            } => send(
+                &program,
+                instructions::cancel_order(trader, stream_id, batch_end),
+                json!({ "order": batch_order_pda(stream_id, &user, batch_end).0.to_string() }),
+            )?,
+        },
+        Command::Limit { kind } => match kind {
+            LimitKind::Place {
+                stream_id,
+                order_id,
+                team,
+                sell,
+                amount,
+                price,
+            } => send(
+                &program,
+                instructions::place_limit_order(
+                    trader, stream_id, order_id, team, !sell, amount, price,
+                ),
+                json!({ "order": limit_order_pda(stream_id, &user, order_id).0.to_string() }),
+            )?,
+            LimitKind::Cancel {
+                stream_id,
+                order_id,
+            } => send(
+                &program,
+                instructions::cancel_limit_order(trader, stream_id, order_id),
+                json!({ "order": limit_order_pda(stream_id, &user, order_id).0.to_string() }),
+            )?,
+            LimitKind::Match { stream_id } => {
+                let orders: Vec<(Pubkey, u64)> = limit_orders(&program, stream_id)?
+                    .iter()
+                    .map(|(_, order)| (order.user, order.order_id))
+                    .collect();
+                // Four accounts per order; keep each transaction under the size limit
+                orders
+                    .chunks(MATCH_ORDERS_PER_TX)
+                    .map(|chunk| {
+                        send(
+                            &program,
+                            instructions::match_orders(signer, stream_id, chunk),
+                            json!({ "orders": chunk.len() }),
+                        )
+                    })
+                    .collect::<Result<Vec<_>>>()?
+                    .into()
+            }
+            LimitKind::List { stream_id } => limit_orders(&program, stream_id)?
+                .iter()
+                .map(|(address, order)| output::limit_order(address, order))
+                .collect(),
+        },
+        Command::SetAdmin { admin } => send(
+            &program,
+            instructions::set_admin(signer, admin),
+            json!({ "config": config_pda().0.to_string() }),
+        )?,
+        Command::Promo { kind } => match kind {
+            PromoKind::Issue {
+                user,
+                stream_id,
+                lamports,
+            } => send(
+                &program,
+                instructions::issue_promo_credit(signer, user, stream_id, lamports),
+                json!({ "credit": promo_credit_pda(&user, stream_id).0.to_string() }),
+            )?,
+            PromoKind::Revoke { user, stream_id } => send(
+                &program,
+                instructions::revoke_promo_credit(signer, user, stream_id),
+                json!({ "credit": promo_credit_pda(&user, stream_id).0.to_string() }),
+            )?,
+        },
+        Command::Referral { kind } => match kind {
+            ReferralKind::Register => send(
+                &program,
+                instructions::register_referrer(signer),
+                json!({ "referral": referral_pda(&signer).0.to_string() }),
+            )?,
+            ReferralKind::Claim => send(
+                &program,
+                instructions::claim_referral_fees(signer),
+                json!({ "referral": referral_pda(&signer).0.to_string() }),
+            )?,
+            ReferralKind::Show { referrer } => {
+                let address = referral_pda(&referrer.unwrap_or(signer)).0;
+                let referral: Referral = program.account(address)?;
+                output::referral(&address, &referral)
+            }
+        },
+        Command::Streamer { kind } => match kind {
+            StreamerKind::Set {
+                stream_id,
+                streamer,
+                cut_bps,
+            } => send(
+                &program,
+                instructions::set_streamer(signer, stream_id, streamer, cut_bps),
+                json!({ "stream": stream_pda(stream_id).0.to_string() }),
+            )?,
+            StreamerKind::Tip {
+                stream_id,
+                lamports,
+            } => {
+                let stream: Stream = program.account(stream_pda(stream_id).0)?;
+                send(
+                    &program,
+                    instructions::tip_streamer(signer, stream_id, stream.streamer, lamports),
+                    json!({ "streamer": stream.streamer.to_string() }),
+                )?
+            }
+            StreamerKind::Pay { stream_id } => {
+                let stream: Stream = program.account(stream_pda(stream_id).0)?;
+                send(
+                    &program,
+                    instructions::pay_streamer(signer, stream_id, stream.streamer),
+                    json!({ "streamer": stream.streamer.to_string() }),
+                )?
+            }
+        },
+        Command::Challenge { kind } => match kind {
+            ChallengeKind::Create {
+                stream_id,
+                challenge_id,
+                team,
+                stake,
+                counter_stake,
+                counterparty,
+            } => send(
+                &program,
+                instructions::create_challenge(
+                    signer,
+                    stream_id,
+                    challenge_id,
+                    team,
+                    stake,
+                    counter_stake,
+                    counterparty,
+                ),
+                json!({ "challenge": challenge_pda(stream_id, &signer, challenge_id).0.to_string() }),
+            )?,
+            ChallengeKind::Accept {
+                stream_id,
+                creator,
+                challenge_id,
+            } => send(
+                &program,
+                instructions::accept_challenge(signer, stream_id, creator, challenge_id),
+                json!({ "challenge": challenge_pda(stream_id, &creator, challenge_id).0.to_string() }),
+            )?,
+            ChallengeKind::Cancel {
+                stream_id,
+                challenge_id,
+            } => send(
+                &program,
+                instructions::cancel_challenge(signer, stream_id, challenge_id),
+                json!({ "challenge": challenge_pda(stream_id, &signer, challenge_id).0.to_string() }),
+            )?,
+            ChallengeKind::Settle { stream_id } => challenges(&program, stream_id)?
+                .iter()
+                .filter(|(_, challenge)| challenge.acceptor != Pubkey::default())
+                .map(|(address, challenge)| {
+                    send(
+                        &program,
+                        instructions::settle_challenge(
+                            signer,
+                            stream_id,
+                            challenge.creator,
+                            challenge.challenge_id,
+                            challenge.acceptor,
+                        ),
+                        json!({ "challenge": address.to_string() }),
+                    )
+                })
+                .collect::<Result<Vec<_>>>()?
+                .into(),
+            ChallengeKind::List { stream_id } => challenges(&program, stream_id)?
+                .iter()
+                .map(|(address, challenge)| output::challenge(address, challenge))
+                .collect(),
+        },
+        Command::Session { kind } => match kind {
+            SessionKind::Create {
+                session_signer,
+                duration_secs,
+                limit,
+            } => {
+                let rpc = program.rpc();
+                let now = rpc.get_block_time(rpc.get_slot()?)?;
+                let expires_at = now + duration_secs;
+                let mut value = send(
+                    &program,
+                    instructions::create_session(signer, session_signer, expires_at, limit),
+                    json!({ "session": session_token_pda(&signer).0.to_string() }),
+                )?;
+                value["expires_at"] = json!(expires_at);
+                value
+            }
+            SessionKind::Revoke => send(
+                &program,
+                instructions::revoke_session(signer),
+                json!({ "session": session_token_pda(&signer).0.to_string() }),
+            )?,
+        },
+        Command::Trigger { kind } => match kind {
+            TriggerKind::Set {
+                stream_id,
+                team,
+                stop_loss,
+                take_profit,
+                slippage_bps,
+                tip,
+            } => send(
+                &program,
+                instructions::set_trigger(
+                    trader,
+                    stream_id,
+                    team,
+                    stop_loss,
+                    take_profit,
+                    slippage_bps,
+                    tip,
+                ),
+                json!({ "trigger": position_trigger_pda(stream_id, &user, team).0.to_string() }),
+            )?,
+            TriggerKind::Cancel { stream_id, team } => send(
+                &program,
+                instructions::cancel_trigger(trader, stream_id, team),
+                json!({ "trigger": position_trigger_pda(stream_id, &user, team).0.to_string() }),
+            )?,
+            TriggerKind::Run { stream_id } => {
+                let stream: Stream = program.account(stream_pda(stream_id).0)?;
+                let price_a = quote::calculate_price(stream.team_a_reserve, stream.team_b_reserve)?;
+                let price_b = quote::calculate_price(stream.team_b_reserve, stream.team_a_reserve)?;
+                position_triggers(&program, stream_id)?
+                    .iter()
+                    .filter(|(_, trigger)| {
+                        let price = if trigger.team_id == 1 {
+                            price_a
+                        } else {
+                            price_b
+                        };
+                        (trigger.stop_loss_price > 0 && price <= trigger.stop_loss_price)
+                            || (trigger.take_profit_price > 0 && price >= trigger.take_profit_price)
+                    })
+                    .map(|(address, trigger)| {
+                        send(
+                            &program,
+                            instructions::execute_trigger(
+                                signer,
+                                stream_id,
+                                trigger.user,
+                                trigger.team_id,
+                            ),
+                            json!({ "trigger": address.to_string() }),
+                        )
+                    })
+                    .collect::<Result<Vec<_>>>()?
+                    .into()
+            }
+            TriggerKind::List { stream_id } => position_triggers(&program, stream_id)?
+                .iter()
+                .map(|(address, trigger)| output::position_trigger(address, trigger))
+                .collect(),
+        },
+    };
+
+    output::print(&value, cli.json);
+    Ok(())
+}
+
+/// Open challenges on a stream, oldest first
+fn challenges(program: &Program<Rc<Keypair>>, stream_id: u64) -> Result<Vec<(Pubkey, Challenge)>> {
+    // Challenge layout: discriminator (8) | stream_id (8) | ...
+    let filters = vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+        8,
+        &stream_id.to_le_bytes(),
+    ))];
+    let mut challenges = program.accounts::<Challenge>(filters)?;
+    challenges.sort_by_key(|(_, challenge)| challenge.created_at);
+    Ok(challenges)
+}
+
+/// Open limit orders on a stream, oldest first
+fn limit_orders(
+    program: &Program<Rc<Keypair>>,
+    stream_id: u64,
+) -> Result<Vec<(Pubkey, LimitOrder)>> {
+    // LimitOrder layout: discriminator (8) | user (32) | stream_id (8) | ...
+    let filters = vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+        40,
+        &stream_id.to_le_bytes(),
+    ))];
+    let mut orders = program.accounts::<LimitOrder>(filters)?;
+    orders.sort_by_key(|(_, order)| order.created_at);
+    Ok(orders)
+}
+
+/// Triggers on a stream, oldest first
+fn position_triggers(
+    program: &Program<Rc<Keypair>>,
+    stream_id: u64,
+) -> Result<Vec<(Pubkey, PositionTrigger)>> {
+    // PositionTrigger layout: discriminator (8) | user (32) | stream_id (8) | ...
+    let filters = vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+        40,
+        &stream_id.to_le_bytes(),
+    ))];
+    let mut triggers = program.accounts::<PositionTrigger>(filters)?;
+    triggers.sort_by_key(|(_, trigger)| trigger.created_at);
+    Ok(triggers)
+}
+
+/// Sign with the payer, send, and merge the signature into `extra`
+/// The referrer `user`'s position is tied to, which every later buy must carry
+fn position_referrer(
+    program: &Program<Rc<Keypair>>,
+    stream_id: u64,
+    user: &Pubkey,
+) -> Option<Pubkey> {
+    program
+        .account::<UserPosition>(user_position_pda(stream_id, user).0)
+        .ok()
+        .map(|position| position.referrer)
+        .filter(|referrer| *referrer != Pubkey::default())
+}
+
+fn send(
+    program: &Program<Rc<Keypair>>,
+    ix: Instruction,
+    mut extra: serde_json::Value,
+) -> Result<serde_json::Value> {
+    let signature = program.request().instruction(ix).send()?;
+    extra["signature"] = json!(signature.to_string());
+    Ok(extra)
+}
+
+/// Parse a 32-byte salt from 64 hex characters
+fn parse_salt(hex: &str) -> std::result::Result<[u8; 32], String> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err("salt must be 64 hex characters".to_string());
+    }
+    let mut salt = [0u8; 32];
+    for (byte, pair) in salt.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|e| e.to_string())?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|e| e.to_string())?;
+    }
+    Ok(salt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    fn parse(args: &[&str]) -> std::result::Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("prophecy").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn command_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn global_flags_follow_the_subcommand() {
+        let cli = parse(&[
+            "buy",
+            "--stream-id",
+            "7",
+            "--team",
+            "2",
+            "--lamports",
+            "1000",
+            "--json",
+            "--cluster",
+            "devnet",
+            "--keypair",
+            "/tmp/id.json",
+        ])
+        .unwrap();
+
+        assert!(cli.json);
+        assert_eq!(cli.cluster, "devnet");
+        assert_eq!(cli.keypair.as_deref(), Some("/tmp/id.json"));
+        assert!(matches!(
+            cli.command,
+            Command::Buy {
+                stream_id: 7,
+                team: 2,
+                lamports: 1000,
+                credit: None,
+                referrer: None,
+            }
+        ));
+    }
+
+    #[test]
+    fn buy_rejects_a_credit_with_a_referrer() {
+        let referrer = Pubkey::new_unique().to_string();
+        let result = parse(&[
+            "buy",
+            "--stream-id",
+            "1",
+            "--team",
+            "1",
+            "--lamports",
+            "1",
+            "--credit",
+            "0",
+            "--referrer",
+            &referrer,
+        ]);
+
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(clap::error::ErrorKind::ArgumentConflict)
+        );
+    }
+
+    #[test]
+    fn missing_required_argument_is_an_error() {
+        let result = parse(&["end", "--stream-id", "1"]);
+
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(clap::error::ErrorKind::MissingRequiredArgument)
+        );
+    }
+
+    #[test]
+    fn void_and_refund_take_a_stream_id() {
+        let cli = parse(&["void", "--stream-id", "9"]).unwrap();
+        assert!(matches!(cli.command, Command::Void { stream_id: 9 }));
+
+        let cli = parse(&["refund", "--stream-id", "9"]).unwrap();
+        assert!(matches!(cli.command, Command::Refund { stream_id: 9 }));
+
+        assert!(parse(&["void"]).is_err());
+    }
+
+    #[test]
+    fn defaults_fill_optional_arguments() {
+        let cli = parse(&["cash-out", "--stream-id", "3"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::CashOut {
+                stream_id: 3,
+                team: None,
+                pct_bps: 10_000,
+            }
+        ));
+
+        let user = Pubkey::new_unique();
+        let cli = parse(&[
+            "promo",
+            "issue",
+            "--user",
+            &user.to_string(),
+            "--lamports",
+            "5",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Promo {
+                kind: PromoKind::Issue {
+                    stream_id: 0,
+                    lamports: 5,
+                    ..
+                }
+            }
+        ));
+    }
+
+    #[test]
+    fn pubkey_arguments_are_parsed() {
+        let owner = Pubkey::new_unique();
+        let cli = parse(&[
+            "quote",
+            "claim",
+            "--stream-id",
+            "4",
+            "--owner",
+            &owner.to_string(),
+        ])
+        .unwrap();
+
+        match cli.command {
+            Command::Quote {
+                kind:
+                    QuoteKind::Claim {
+                        stream_id,
+                        owner: parsed,
+                    },
+            } => {
+                assert_eq!(stream_id, 4);
+                assert_eq!(parsed, Some(owner));
+            }
+            _ => panic!("expected quote claim"),
+        }
+
+        assert!(parse(&["quote", "claim", "--stream-id", "4", "--owner", "nope"]).is_err());
+    }
+
+    #[test]
+    fn salt_must_be_64_hex_characters() {
+        let salt = parse_salt(&"0f".repeat(32)).unwrap();
+        assert_eq!(salt, [0x0f; 32]);
+
+        assert!(parse_salt(&"0f".repeat(31)).is_err());
+        assert!(parse_salt(&"zz".repeat(32)).is_err());
+        assert!(parse_salt(&"é".repeat(32)).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use anchor_client::anchor_lang::prelude::Pubkey;
+use prophecy_sdk::{
+    BuyQuote, Challenge, ClaimQuote, LimitOrder, PositionTrigger, Referral, SellQuote, Stream,
+    UserPosition, UserProfile,
+};
+use serde_json::{json, Value};
+
+/// Print a result either as JSON or as aligned `key: value` lines
+pub fn print(value: &Value, as_json: bool) {
+    if as_json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(value).unwrap_or_default()
+        );
+        return;
+    }
+
+    match value {
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                print(item, false);
+            }
+        }
+        Value::Object(map) => {
+            let width = map.keys().map(|k| k.len()).max().unwrap_or(0);
+            for (key, field) in map {
+                match field {
+                    Value::String(s) => println!("{key:width$}  {s}"),
+                    other => println!("{key:width$}  {other}"),
+                }
+            }
+        }
+        other => println!("{other}"),
+    }
+}
+
+pub fn stream(address: &Pubkey, stream: &Stream) -> Value {
+    json!({
+        "address": address.to_string(),
+        "stream_id": stream.stream_id,
+        "authority": stream.authority.to_string(),
+        "team_a_name": stream.team_a_name,
+        "team_b_name": stream.team_b_name,
+        "team_a_reserve": stream.team_a_reserve,
+        "team_b_reserve": stream.team_b_reserve,
+        "team_a_shares_sold": stream.team_a_shares_sold,
+        "team_b_shares_sold": stream.team_b_shares_sold,
+        "total_pool": stream.total_pool,
+        "trade_seq": stream.trade_seq,
+        "team_a_price_cumulative": stream.team_a_price_cumulative.to_string(),
+        "team_b_price_cumulative": stream.team_b_price_cumulative.to_string(),
+        "last_price_update": stream.last_price_update,
+        "team_a_buy_volume": stream.team_a_buy_volume,
+        "team_b_buy_volume": stream.team_b_buy_volume,
+        "team_a_sell_volume": stream.team_a_sell_volume,
+        "team_b_sell_volume": stream.team_b_sell_volume,
+        "unique_bettors": stream.unique_bettors,
+        "largest_bet": stream.largest_bet,
+        "last_trade_time": stream.last_trade_time,
+        "min_bet": stream.min_bet,
+        "max_bet": stream.max_bet,
+        "max_position": stream.max_position,
+        "max_price_impact_bps": stream.max_price_impact_bps,
+        "fee_bps": stream.fee_bps,
+        "referral_share_bps": stream.referral_share_bps,
+        "fees_collected": stream.fees_collected,
+        "breaker_threshold_bps": stream.breaker_threshold_bps,
+        "breaker_window_slots": stream.breaker_window_slots,
+        "breaker_cooldown_slots": stream.breaker_cooldown_slots,
+        "halted_until_slot": stream.halted_until_slot,
+        "commit_secs": stream.commit_secs,
+        "reveal_secs": stream.reveal_secs,
+        "auction_interval_secs": stream.auction_interval_secs,
+        "start_time": stream.start_time,
+        "end_time": stream.end_time,
+        "is_active": stream.is_active,
+        "winning_team": stream.winning_team,
+        "is_voided": stream.is_voided,
+        "stream_link": stream.stream_link,
+        "layout_version": stream.layout_version,
+        "streamer": stream.streamer.to_string(),
+        "streamer_cut_bps": stream.streamer_cut_bps,
+        "streamer_tips": stream.streamer_tips,
+        "streamer_paid": stream.streamer_paid,
+        "streamer_owed": stream.streamer_owed,
+    })
+}
+
+pub fn position(address: &Pubkey, position: &UserPosition) -> Value {
+    json!({
+        "address": address.to_string(),
+        "user": position.user.to_string(),
+        "stream_id": position.stream_id,
+        "team_a_shares": position.team_a_shares,
+        "team_b_shares": position.team_b_shares,
+        "promo_shares_a": position.promo_shares_a,
+        "promo_shares_b": position.promo_shares_b,
+        "referrer": position.referrer.to_string(),
+        "pending_invested": position.pending_invested,
+        "open_orders": position.open_orders,
+        "reserved_shares_a": position.reserved_shares_a,
+        "reserved_shares_b": position.reserved_shares_b,
+        "rent_payer": position.rent_payer.to_string(),
+        "total_invested": position.total_invested,
+        "promo_invested": position.promo_invested,
+        "has_claimed": position.has_claimed,
+        "profile_settled": position.profile_settled,
+    })
+}
+
+pub fn profile(address: &Pubkey, profile: &UserProfile) -> Value {
+    json!({
+        "address": address.to_string(),
+        "user": profile.user.to_string(),
+        "lifetime_volume": profile.lifetime_volume,
+        "streams_entered": profile.streams_entered,
+        "wins": profile.wins,
+        "losses": profile.losses,
+        "realized_pnl": profile.realized_pnl,
+    })
+}
+
+pub fn buy_quote(quote: &BuyQuote) -> Value {
+    json!({
+        "shares_out": quote.shares_out,
+        "price_before": quote.price_before,
+        "price_after": quote.price_after,
+        "price_impact_bps": quote.price_impact_bps,
+        "fee": quote.fee,
+    })
+}
+
+pub fn sell_quote(quote: &SellQuote) -> Value {
+    json!({
+        "sol_out": quote.sol_out,
+        "price_before": quote.price_before,
+        "price_after": quote.price_after,
+        "price_impact_bps": quote.price_impact_bps,
+        "fee": quote.fee,
+    })
+}
+
+pub fn claim_quote(quote: &ClaimQuote) -> Value {
+    json!({
+        "winning_team": quote.winning_team,
+        "winning_shares": quote.winning_shares,
+        "payout": quote.payout,
+        "has_claimed": quote.has_claimed,
+        "root_posted": quote.root_posted,
+    })
+}
+
+pub fn limit_order(address: &Pubkey, order: &LimitOrder) -> Value {
+    json!({
+        "address": address.to_string(),
+        "user": order.user.to_string(),
+        "stream_id": order.stream_id,
+        "order_id": order.order_id,
+        "team_id": order.team_id,
+        "side": if order.is_buy { "buy" } else { "sell" },
+        "amount": order.amount,
+        "limit_price": order.limit_price,
+        "created_at": order.created_at,
+    })
+}
+
+pub fn position_trigger(address: &Pubkey, trigger: &PositionTrigger) -> Value {
+    json!({
+        "address": address.to_string(),
+        "user": trigger.user.to_string(),
+        "stream_id": trigger.stream_id,
+        "team_id": trigger.team_id,
+        "stop_loss_price": trigger.stop_loss_price,
+        "take_profit_price": trigger.take_profit_price,
+        "max_slippage_bps": trigger.max_slippage_bps,
+        "tip": trigger.tip,
+        "created_at": trigger.created_at,
+    })
+}
+
+pub fn referral(address: &Pubkey, referral: &Referral) -> Value {
+    json!({
+        "address": address.to_string(),
+        "referrer": referral.referrer.to_string(),
+        "referred_volume": referral.referred_volume,
+        "total_earned": referral.total_earned,
+        "total_claimed": referral.total_claimed,
+        "unclaimed": referral.total_earned - referral.total_claimed,
+    })
+}
+
+pub fn challenge(address: &Pubkey, challenge: &Challenge) -> Value {
+    let open = |key: &Pubkey| (*key != Pubkey::default()).then(|| key.to_string());
+    json!({
+        "address": address.to_string(),
+        "stream_id": challenge.stream_id,
+        "challenge_id": challenge.challenge_id,
+        "creator": challenge.creator.to_string(),
+        "counterparty": open(&challenge.counterparty),
+        "acceptor": open(&challenge.acceptor),
+        "team_id": challenge.team_id,
+        "creator_stake": challenge.creator_stake,
+        "counterparty_stake": challenge.counterparty_stake,
+        "created_at": challenge.created_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position_reports_addresses_as_strings() {
+        let address = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let value = position(
+            &address,
+            &UserPosition {
+                user,
+                stream_id: 9,
+                team_a_shares: 100,
+                team_b_shares: 0,
+                total_invested: 50,
+                has_claimed: false,
+                bump: 255,
+                profile_settled: false,
+                promo_shares_a: 10,
+                promo_shares_b: 0,
+                referrer: Pubkey::default(),
+                pending_invested: 0,
+                open_orders: 0,
+                reserved_shares_a: 0,
+                reserved_shares_b: 0,
+                rent_payer: Pubkey::default(),
+                promo_invested: 0,
+            },
+        );
+
+        assert_eq!(value["address"], address.to_string());
+        assert_eq!(value["user"], user.to_string());
+        assert_eq!(value["referrer"], Pubkey::default().to_string());
+        assert_eq!(value["team_a_shares"], 100);
+        assert_eq!(value["promo_shares_a"], 10);
+        assert_eq!(value["has_claimed"], false);
+    }
+
+    #[test]
+    fn quotes_carry_every_field() {
+        let value = buy_quote(&BuyQuote {
+            shares_out: 1,
+            price_before: 2,
+            price_after: 3,
+            price_impact_bps: 4,
+            fee: 5,
+        });
+        assert_eq!(
+            value,
+            json!({
+                "shares_out": 1,
+                "price_before": 2,
+                "price_after": 3,
+                "price_impact_bps": 4,
+                "fee": 5,
+            })
+        );
+
+        let value = claim_quote(&ClaimQuote {
+            winning_team: 1,
+            winning_shares: 6,
+            payout: 7,
+            has_claimed: true,
+            root_posted: false,
+        });
+        assert_eq!(value["payout"], 7);
+        assert_eq!(value["has_claimed"], true);
+        assert_eq!(value["root_posted"], false);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::state::*;
 use anchor_lang::prelude::*;
 
+#[event_cpi]
 #[derive(Accounts)]
 #[instruction(stream_id: u64)]
 pub struct InitializeStream<'info> {
@@ -20,12 +21,70 @@ pub struct InitializeStream<'info> {
     /// CHECK: This is a PDA used as a vault
     pub stream_vault: AccountInfo<'info>,
 
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + std::mem::size_of::<PriceHistory>(),
+        seeds = [b"price_history", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub price_history: AccountLoader<'info, PriceHistory>,
+
     #[account(mut)]
     pub authority: Signer<'info>,
 
     pub system_program: Program<'info, System>,
 }
 
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct MigrateStream<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: Still on an older layout, so it cannot be loaded as a Stream until grown
+    pub stream: UncheckedAccount<'info>,
+
+    /// Streams created before price history existed have none yet
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PriceHistory>(),
+        seeds = [b"price_history", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub price_history: AccountLoader<'info, PriceHistory>,
+
+    /// Anyone may pay to migrate a stream
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, user: Pubkey)]
+pub struct MigratePosition<'info> {
+    #[account(
+        mut,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.as_ref()],
+        bump
+    )]
+    /// CHECK: Still on an older layout, so it cannot be loaded as a UserPosition until grown
+    pub user_position: UncheckedAccount<'info>,
+
+    /// Anyone may pay to migrate a position
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
 #[derive(Accounts)]
 #[instruction(stream_id: u64)]
 pub struct PurchaseShares<'info> {
@@ -38,13 +97,22 @@ pub struct PurchaseShares<'info> {
 
     #[account(
         init_if_needed,
-        payer = user,
+        payer = payer,
         space = 8 + UserPosition::INIT_SPACE,
         seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
         bump
     )]
     pub user_position: Account<'info, UserPosition>,
 
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + UserProfile::INIT_SPACE,
+        seeds = [b"user_profile", user.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
     #[account(
         mut,
         seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
@@ -53,12 +121,43 @@ pub struct PurchaseShares<'info> {
     /// CHECK: This is a PDA used as a vault
     pub stream_vault: AccountInfo<'info>,
 
+    #[account(
+        mut,
+        seeds = [b"price_history", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub price_history: AccountLoader<'info, PriceHistory>,
+
+    /// Pays the bet from this credit instead of the user's wallet when present
     #[account(mut)]
-    pub user: Signer<'info>,
+    pub promo_credit: Option<Account<'info, PromoCredit>>,
+
+    /// Receives the referrer's share of the trading fee; required once the
+    /// position has a referrer
+    #[account(mut)]
+    pub referral: Option<Account<'info, Referral>>,
+
+    /// Pays rent for a new position or profile; may be a sponsor other than the user
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Position owner and source of the bet; signs unless a session key
+    /// trades for them
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    /// The user, or the session key trading for them
+    pub signer: Signer<'info>,
+
+    /// Authorizes a session key to trade for the user and funds its spending
+    /// from the session budget
+    #[account(mut)]
+    pub session_token: Option<Account<'info, SessionToken>>,
 
     pub system_program: Program<'info, System>,
 }
 
+#[event_cpi]
 #[derive(Accounts)]
 #[instruction(stream_id: u64)]
 pub struct SellShares<'info> {
@@ -76,6 +175,13 @@ pub struct SellShares<'info> {
     )]
     pub user_position: Account<'info, UserPosition>,
 
+    #[account(
+        mut,
+        seeds = [b"user_profile", user.key().as_ref()],
+        bump = user_profile.bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
     #[account(
         mut,
         seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
@@ -84,14 +190,167 @@ pub struct SellShares<'info> {
     /// CHECK: This is a PDA used as a vault
     pub stream_vault: AccountInfo<'info>,
 
+    #[account(
+        mut,
+        seeds = [b"price_history", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub price_history: AccountLoader<'info, PriceHistory>,
+
+    /// CHECK: Position owner, who receives the proceeds; signs unless a session
+    /// key trades for them
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    /// The user, or the session key trading for them
+    pub signer: Signer<'info>,
+
+    /// Authorizes a session key to trade for the user
+    pub session_token: Option<Account<'info, SessionToken>>,
+
+    pub system_program: Program<'info, System>,
+}
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetAdmin<'info> {
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + Config::INIT_SPACE,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+
+    /// This program's ProgramData, which names the upgrade authority
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = anchor_lang::solana_program::bpf_loader_upgradeable::ID
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(user: Pubkey, stream_id: u64)]
+pub struct IssuePromoCredit<'info> {
+    #[account(
+        init_if_needed,
+        payer = issuer,
+        space = 8 + PromoCredit::INIT_SPACE,
+        seeds = [b"promo_credit", user.as_ref(), stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub promo_credit: Account<'info, PromoCredit>,
+
+    /// Required for a stream-scoped credit, whose issuer must be the stream authority
+    #[account(
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Option<Account<'info, Stream>>,
+
+    /// Required for a global credit, whose issuer must be the program admin
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Option<Account<'info, Config>>,
+
+    #[account(mut)]
+    pub issuer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(user: Pubkey, stream_id: u64)]
+pub struct RevokePromoCredit<'info> {
+    #[account(
+        mut,
+        close = issuer,
+        seeds = [b"promo_credit", user.as_ref(), stream_id.to_le_bytes().as_ref()],
+        bump = promo_credit.bump
+    )]
+    pub promo_credit: Account<'info, PromoCredit>,
+
+    #[account(mut)]
+    pub issuer: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RegisterReferrer<'info> {
+    #[account(
+        init,
+        payer = referrer,
+        space = 8 + Referral::INIT_SPACE,
+        seeds = [b"referral", referrer.key().as_ref()],
+        bump
+    )]
+    pub referral: Account<'info, Referral>,
+
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClaimReferralFees<'info> {
+    #[account(
+        mut,
+        seeds = [b"referral", referrer.key().as_ref()],
+        bump = referral.bump
+    )]
+    pub referral: Account<'info, Referral>,
+
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CreateSession<'info> {
+    #[account(
+        init,
+        payer = user,
+        space = 8 + SessionToken::INIT_SPACE,
+        seeds = [b"session_token", user.key().as_ref()],
+        bump
+    )]
+    pub session_token: Account<'info, SessionToken>,
+
     #[account(mut)]
     pub user: Signer<'info>,
 
     pub system_program: Program<'info, System>,
 }
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RevokeSession<'info> {
+    #[account(
+        mut,
+        close = user,
+        seeds = [b"session_token", user.key().as_ref()],
+        bump = session_token.bump
+    )]
+    pub session_token: Account<'info, SessionToken>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+#[event_cpi]
 #[derive(Accounts)]
 #[instruction(stream_id: u64)]
-pub struct EndStream<'info> {
+pub struct CashOut<'info> {
     #[account(
         mut,
         seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
@@ -99,12 +358,55 @@ pub struct EndStream<'info> {
     )]
     pub stream: Account<'info, Stream>,
 
-    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", user.key().as_ref()],
+        bump = user_profile.bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"price_history", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub price_history: AccountLoader<'info, PriceHistory>,
+
+    /// CHECK: Position owner, who receives the proceeds and rent; signs unless
+    /// a session key trades for them
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    /// The user, or the session key trading for them
+    pub signer: Signer<'info>,
+
+    /// Authorizes a session key to trade for the user
+    pub session_token: Option<Account<'info, SessionToken>>,
+
+    pub system_program: Program<'info, System>,
 }
+
+#[event_cpi]
 #[derive(Accounts)]
 #[instruction(stream_id: u64)]
-pub struct ClaimWinnings<'info> {
+pub struct SwapSides<'info> {
     #[account(
+        mut,
         seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
         bump = stream.bump
     )]
@@ -117,6 +419,20 @@ pub struct ClaimWinnings<'info> {
     )]
     pub user_position: Account<'info, UserPosition>,
 
+    #[account(
+        mut,
+        seeds = [b"user_profile", user.key().as_ref()],
+        bump = user_profile.bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        mut,
+        seeds = [b"price_history", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub price_history: AccountLoader<'info, PriceHistory>,
+
     #[account(
         mut,
         seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
@@ -125,15 +441,90 @@ pub struct ClaimWinnings<'info> {
     /// CHECK: This is a PDA used as a vault
     pub stream_vault: AccountInfo<'info>,
 
+    /// Receives the referrer's share of the buy leg's fee; required once the
+    /// position has a referrer
     #[account(mut)]
-    pub user: Signer<'info>,
+    pub referral: Option<Account<'info, Referral>>,
+
+    /// CHECK: Position owner; signs unless a session key trades for them
+    pub user: UncheckedAccount<'info>,
+
+    /// The user, or the session key trading for them
+    pub signer: Signer<'info>,
+
+    /// Authorizes a session key to trade for the user
+    pub session_token: Option<Account<'info, SessionToken>>,
 
     pub system_program: Program<'info, System>,
 }
 
+#[event_cpi]
 #[derive(Accounts)]
 #[instruction(stream_id: u64)]
-pub struct EmergencyWithdraw<'info> {
+pub struct SetBetLimits<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct SetFees<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct SetStreamer<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct TipStreamer<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(mut)]
+    /// CHECK: Must match stream.streamer, checked in the handler
+    pub streamer: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub tipper: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct PayStreamer<'info> {
     #[account(
         mut,
         seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
@@ -150,7 +541,1245 @@ pub struct EmergencyWithdraw<'info> {
     pub stream_vault: AccountInfo<'info>,
 
     #[account(mut)]
-    pub authority: Signer<'info>,
+    /// CHECK: Must match stream.streamer, checked in the handler
+    pub streamer: UncheckedAccount<'info>,
+
+    /// Anyone may pay out the streamer's cut once the stream has ended
+    pub keeper: Signer<'info>,
 
     pub system_program: Program<'info, System>,
-}
\ No newline at end of file
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct WithdrawFees<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    /// CHECK: Must match stream.streamer, checked in the handler
+    pub streamer: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct SetCircuitBreaker<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct SetCommitReveal<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, reveal_end: i64)]
+pub struct CommitBet<'info> {
+    #[account(
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + Commitment::INIT_SPACE,
+        seeds = [
+            b"commitment",
+            stream_id.to_le_bytes().as_ref(),
+            user.key().as_ref(),
+            reveal_end.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub commitment: Account<'info, Commitment>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, reveal_end: i64)]
+pub struct RevealBet<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"commitment",
+            stream_id.to_le_bytes().as_ref(),
+            user.key().as_ref(),
+            reveal_end.to_le_bytes().as_ref()
+        ],
+        bump = commitment.bump
+    )]
+    pub commitment: Account<'info, Commitment>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + CommitBatch::INIT_SPACE,
+        seeds = [
+            b"commit_batch",
+            stream_id.to_le_bytes().as_ref(),
+            reveal_end.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub commit_batch: Account<'info, CommitBatch>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    /// Opened on the first reveal so the position limit sees every bet
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserProfile::INIT_SPACE,
+        seeds = [b"user_profile", user.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, reveal_end: i64)]
+pub struct ClearCommitBatch<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"commit_batch",
+            stream_id.to_le_bytes().as_ref(),
+            reveal_end.to_le_bytes().as_ref()
+        ],
+        bump = commit_batch.bump
+    )]
+    pub commit_batch: Account<'info, CommitBatch>,
+
+    #[account(
+        mut,
+        seeds = [b"price_history", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub price_history: AccountLoader<'info, PriceHistory>,
+
+    pub keeper: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, reveal_end: i64)]
+pub struct SettleCommitment<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        seeds = [
+            b"commit_batch",
+            stream_id.to_le_bytes().as_ref(),
+            reveal_end.to_le_bytes().as_ref()
+        ],
+        bump = commit_batch.bump
+    )]
+    pub commit_batch: Account<'info, CommitBatch>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [
+            b"commitment",
+            stream_id.to_le_bytes().as_ref(),
+            user.key().as_ref(),
+            reveal_end.to_le_bytes().as_ref()
+        ],
+        bump = commitment.bump
+    )]
+    pub commitment: Account<'info, Commitment>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserProfile::INIT_SPACE,
+        seeds = [b"user_profile", user.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, reveal_end: i64)]
+pub struct CancelCommitment<'info> {
+    #[account(
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [
+            b"commitment",
+            stream_id.to_le_bytes().as_ref(),
+            user.key().as_ref(),
+            reveal_end.to_le_bytes().as_ref()
+        ],
+        bump = commitment.bump
+    )]
+    pub commitment: Account<'info, Commitment>,
+
+    /// Only needed to refund a revealed commitment
+    #[account(
+        seeds = [
+            b"commit_batch",
+            stream_id.to_le_bytes().as_ref(),
+            reveal_end.to_le_bytes().as_ref()
+        ],
+        bump = commit_batch.bump
+    )]
+    pub commit_batch: Option<Account<'info, CommitBatch>>,
+
+    /// Only needed to refund a revealed commitment
+    #[account(
+        mut,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump = user_position.bump
+    )]
+    pub user_position: Option<Account<'info, UserPosition>>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct SetAuctionMode<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, batch_end: i64)]
+pub struct SubmitOrder<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + OrderBatch::INIT_SPACE,
+        seeds = [
+            b"order_batch",
+            stream_id.to_le_bytes().as_ref(),
+            batch_end.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub order_batch: Account<'info, OrderBatch>,
+
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + BatchOrder::INIT_SPACE,
+        seeds = [
+            b"batch_order",
+            stream_id.to_le_bytes().as_ref(),
+            user.key().as_ref(),
+            batch_end.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub order: Account<'info, BatchOrder>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserProfile::INIT_SPACE,
+        seeds = [b"user_profile", user.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    /// CHECK: Position owner and source of a buy's escrow; signs unless a
+    /// session key trades for them
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    /// The user, or the session key trading for them; pays rent for new accounts
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    /// Authorizes a session key to trade for the user and funds its spending
+    /// from the session budget
+    #[account(mut)]
+    pub session_token: Option<Account<'info, SessionToken>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, batch_end: i64)]
+pub struct ClearBatch<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"order_batch",
+            stream_id.to_le_bytes().as_ref(),
+            batch_end.to_le_bytes().as_ref()
+        ],
+        bump = order_batch.bump
+    )]
+    pub order_batch: Account<'info, OrderBatch>,
+
+    #[account(
+        mut,
+        seeds = [b"price_history", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub price_history: AccountLoader<'info, PriceHistory>,
+
+    pub keeper: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, batch_end: i64)]
+pub struct SettleOrder<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        seeds = [
+            b"order_batch",
+            stream_id.to_le_bytes().as_ref(),
+            batch_end.to_le_bytes().as_ref()
+        ],
+        bump = order_batch.bump
+    )]
+    pub order_batch: Account<'info, OrderBatch>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [
+            b"batch_order",
+            stream_id.to_le_bytes().as_ref(),
+            user.key().as_ref(),
+            batch_end.to_le_bytes().as_ref()
+        ],
+        bump = order.bump
+    )]
+    pub order: Account<'info, BatchOrder>,
+
+    #[account(
+        mut,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", user.key().as_ref()],
+        bump = user_profile.bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    /// CHECK: Order owner, bound by the order seeds; receives proceeds and rent
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    pub keeper: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, batch_end: i64)]
+pub struct CancelOrder<'info> {
+    #[account(
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"order_batch",
+            stream_id.to_le_bytes().as_ref(),
+            batch_end.to_le_bytes().as_ref()
+        ],
+        bump = order_batch.bump
+    )]
+    pub order_batch: Account<'info, OrderBatch>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [
+            b"batch_order",
+            stream_id.to_le_bytes().as_ref(),
+            user.key().as_ref(),
+            batch_end.to_le_bytes().as_ref()
+        ],
+        bump = order.bump
+    )]
+    pub order: Account<'info, BatchOrder>,
+
+    #[account(
+        mut,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    /// CHECK: Position owner, who receives the refund and rent; signs unless a
+    /// session key trades for them
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    /// The user, or the session key trading for them
+    pub signer: Signer<'info>,
+
+    /// Authorizes a session key to trade for the user
+    pub session_token: Option<Account<'info, SessionToken>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, order_id: u64)]
+pub struct PlaceLimitOrder<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + LimitOrder::INIT_SPACE,
+        seeds = [
+            b"limit_order",
+            stream_id.to_le_bytes().as_ref(),
+            user.key().as_ref(),
+            order_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub limit_order: Account<'info, LimitOrder>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserPosition::INIT_SPACE,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + UserProfile::INIT_SPACE,
+        seeds = [b"user_profile", user.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    /// CHECK: Position owner and source of a buy's escrow; signs unless a
+    /// session key trades for them
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    /// The user, or the session key trading for them; pays rent for new accounts
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    /// Authorizes a session key to trade for the user and funds its spending
+    /// from the session budget
+    #[account(mut)]
+    pub session_token: Option<Account<'info, SessionToken>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct MatchOrders<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"price_history", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub price_history: AccountLoader<'info, PriceHistory>,
+
+    pub keeper: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, order_id: u64)]
+pub struct CancelLimitOrder<'info> {
+    #[account(
+        mut,
+        close = user,
+        seeds = [
+            b"limit_order",
+            stream_id.to_le_bytes().as_ref(),
+            user.key().as_ref(),
+            order_id.to_le_bytes().as_ref()
+        ],
+        bump = limit_order.bump
+    )]
+    pub limit_order: Account<'info, LimitOrder>,
+
+    #[account(
+        mut,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    /// CHECK: Position owner, who receives the escrow and rent; signs unless a
+    /// session key trades for them
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    /// The user, or the session key trading for them
+    pub signer: Signer<'info>,
+
+    /// Authorizes a session key to trade for the user
+    pub session_token: Option<Account<'info, SessionToken>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, challenge_id: u64)]
+pub struct CreateChallenge<'info> {
+    #[account(
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + Challenge::INIT_SPACE,
+        seeds = [
+            b"challenge",
+            stream_id.to_le_bytes().as_ref(),
+            creator.key().as_ref(),
+            challenge_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub challenge: Account<'info, Challenge>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, challenge_id: u64)]
+pub struct AcceptChallenge<'info> {
+    #[account(
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"challenge",
+            stream_id.to_le_bytes().as_ref(),
+            challenge.creator.as_ref(),
+            challenge_id.to_le_bytes().as_ref()
+        ],
+        bump = challenge.bump
+    )]
+    pub challenge: Account<'info, Challenge>,
+
+    #[account(mut)]
+    pub acceptor: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, challenge_id: u64)]
+pub struct CancelChallenge<'info> {
+    #[account(
+        mut,
+        close = creator,
+        seeds = [
+            b"challenge",
+            stream_id.to_le_bytes().as_ref(),
+            creator.key().as_ref(),
+            challenge_id.to_le_bytes().as_ref()
+        ],
+        bump = challenge.bump
+    )]
+    pub challenge: Account<'info, Challenge>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, challenge_id: u64)]
+pub struct SettleChallenge<'info> {
+    #[account(
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        close = creator,
+        seeds = [
+            b"challenge",
+            stream_id.to_le_bytes().as_ref(),
+            creator.key().as_ref(),
+            challenge_id.to_le_bytes().as_ref()
+        ],
+        bump = challenge.bump
+    )]
+    pub challenge: Account<'info, Challenge>,
+
+    #[account(mut)]
+    /// CHECK: Seeds tie the challenge to this key; receives the rent and any creator payout
+    pub creator: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    /// CHECK: Must match challenge.acceptor, checked in the handler
+    pub acceptor: UncheckedAccount<'info>,
+
+    /// Anyone may settle an accepted challenge
+    pub keeper: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, team_id: u8)]
+pub struct SetTrigger<'info> {
+    #[account(
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + PositionTrigger::INIT_SPACE,
+        seeds = [
+            b"position_trigger",
+            stream_id.to_le_bytes().as_ref(),
+            user.key().as_ref(),
+            &[team_id]
+        ],
+        bump
+    )]
+    pub position_trigger: Account<'info, PositionTrigger>,
+
+    #[account(
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    /// CHECK: Position owner and source of the tip; signs unless a session key
+    /// trades for them
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    /// The user, or the session key trading for them; pays rent for new accounts
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    /// Authorizes a session key to trade for the user and funds its spending
+    /// from the session budget
+    #[account(mut)]
+    pub session_token: Option<Account<'info, SessionToken>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, team_id: u8)]
+pub struct ExecuteTrigger<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [
+            b"position_trigger",
+            stream_id.to_le_bytes().as_ref(),
+            user.key().as_ref(),
+            &[team_id]
+        ],
+        bump = position_trigger.bump
+    )]
+    pub position_trigger: Account<'info, PositionTrigger>,
+
+    #[account(
+        mut,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", user.key().as_ref()],
+        bump = user_profile.bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"price_history", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub price_history: AccountLoader<'info, PriceHistory>,
+
+    /// CHECK: Position owner, bound by the trigger seeds; receives proceeds and rent
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, team_id: u8)]
+pub struct CancelTrigger<'info> {
+    #[account(
+        mut,
+        close = user,
+        seeds = [
+            b"position_trigger",
+            stream_id.to_le_bytes().as_ref(),
+            user.key().as_ref(),
+            &[team_id]
+        ],
+        bump = position_trigger.bump
+    )]
+    pub position_trigger: Account<'info, PositionTrigger>,
+
+    /// CHECK: Position owner, who receives the tip and rent; signs unless a
+    /// session key trades for them
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    /// The user, or the session key trading for them
+    pub signer: Signer<'info>,
+
+    /// Authorizes a session key to trade for the user
+    pub session_token: Option<Account<'info, SessionToken>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct EndStream<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    pub authority: Signer<'info>,
+}
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct ClaimRefund<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct ClaimWinnings<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", user.key().as_ref()],
+        bump = user_profile.bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct RelayClaim<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", user.key().as_ref()],
+        bump = user_profile.bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    /// CHECK: Position owner, bound by the position seeds; receives the payout
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    /// Pays the transaction fees; receives nothing
+    pub relayer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct EmergencyWithdraw<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct SettleBatch<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    /// Anyone may crank settlement; payouts always go to the position owner
+    pub keeper: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct PostPayoutRoot<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct ClaimWithProof<'info> {
+    #[account(
+        mut,
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    /// Created on the first claim, so a second one fails
+    #[account(
+        init,
+        payer = user,
+        space = 8 + PayoutReceipt::INIT_SPACE,
+        seeds = [b"payout_receipt", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub payout_receipt: Account<'info, PayoutReceipt>,
+
+    #[account(
+        mut,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.key().as_ref()],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(
+        mut,
+        seeds = [b"stream_vault", stream_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    /// CHECK: This is a PDA used as a vault
+    pub stream_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct QuoteTrade<'info> {
+    #[account(
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, user: Pubkey)]
+pub struct QuoteClaim<'info> {
+    #[account(
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.as_ref()],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(stream_id: u64, user: Pubkey)]
+pub struct SettleProfile<'info> {
+    #[account(
+        seeds = [b"stream", stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump
+    )]
+    pub stream: Account<'info, Stream>,
+
+    #[account(
+        mut,
+        seeds = [b"user_position", stream_id.to_le_bytes().as_ref(), user.as_ref()],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(
+        mut,
+        seeds = [b"user_profile", user.as_ref()],
+        bump = user_profile.bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    /// Anyone may record a finished position's result
+    pub keeper: Signer<'info>,
+}
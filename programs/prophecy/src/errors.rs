@@ -34,4 +34,118 @@ pub enum ErrorCode {
     NoPayout,
     #[msg("Insufficient shares to sell")]
     InsufficientShares,
+    #[msg("Remaining accounts must be (user_position, user) pairs")]
+    InvalidRemainingAccounts,
+    #[msg("User position does not belong to this stream")]
+    InvalidPosition,
+    #[msg("No payout root posted for this stream")]
+    PayoutRootNotSet,
+    #[msg("Invalid Merkle proof")]
+    InvalidMerkleProof,
+    #[msg("Payout exceeds the total committed by the payout root")]
+    PayoutExceedsRoot,
+    #[msg("Position result already recorded in profile")]
+    ProfileAlreadySettled,
+    #[msg("Winning position must be claimed before recording its result")]
+    WinningsNotClaimed,
+    #[msg("Bet is below the stream minimum")]
+    BetBelowMinimum,
+    #[msg("Bet is above the stream maximum")]
+    BetAboveMaximum,
+    #[msg("Bet would exceed the per-user position cap")]
+    PositionLimitExceeded,
+    #[msg("Bet would move the price more than allowed")]
+    PriceImpactTooHigh,
+    #[msg("Invalid bet limits")]
+    InvalidLimits,
+    #[msg("Trading is halted by the circuit breaker")]
+    TradingHalted,
+    #[msg("Invalid circuit breaker configuration")]
+    InvalidCircuitBreaker,
+    #[msg("Commit-reveal mode is not enabled on this stream")]
+    CommitRevealDisabled,
+    #[msg("This stream only accepts bets through commit-reveal")]
+    CommitRevealRequired,
+    #[msg("Invalid commit-reveal configuration")]
+    InvalidCommitReveal,
+    #[msg("Commit phase is not open")]
+    NotInCommitPhase,
+    #[msg("Reveal window is not open")]
+    NotInRevealPhase,
+    #[msg("Reveal does not match the commitment")]
+    CommitmentMismatch,
+    #[msg("Commitment already revealed")]
+    AlreadyRevealed,
+    #[msg("Commitment has not been revealed")]
+    CommitmentNotRevealed,
+    #[msg("Reveal window has not closed yet")]
+    RevealWindowOpen,
+    #[msg("Commit batch already cleared")]
+    BatchAlreadyCleared,
+    #[msg("Commit batch has not been cleared")]
+    BatchNotCleared,
+    #[msg("Batch auction mode is not enabled on this stream")]
+    AuctionModeDisabled,
+    #[msg("This stream only trades through batch auctions")]
+    AuctionModeActive,
+    #[msg("Commit-reveal and batch auction modes cannot both be enabled")]
+    TradingModeConflict,
+    #[msg("Order is not for the open auction batch")]
+    InvalidBatch,
+    #[msg("Auction batch has closed")]
+    BatchClosed,
+    #[msg("Auction batch is still collecting orders")]
+    BatchStillOpen,
+    #[msg("Limit order does not belong to this stream")]
+    InvalidOrder,
+    #[msg("Invalid stop-loss / take-profit trigger")]
+    InvalidTrigger,
+    #[msg("Price has not crossed a trigger threshold")]
+    TriggerNotCrossed,
+    #[msg("Fill would slip past the allowed bound")]
+    SlippageExceeded,
+    #[msg("Invalid session key")]
+    InvalidSession,
+    #[msg("Session key has expired")]
+    SessionExpired,
+    #[msg("Bet would exceed the session spending limit")]
+    SessionLimitExceeded,
+    #[msg("Promo credit cannot be used here")]
+    InvalidPromoCredit,
+    #[msg("Promo credit balance is too low")]
+    InsufficientPromoCredit,
+    #[msg("Invalid fee configuration")]
+    InvalidFees,
+    #[msg("Users cannot refer themselves")]
+    SelfReferral,
+    #[msg("Position is already tied to a different referrer")]
+    ReferrerMismatch,
+    #[msg("No referral fees to claim")]
+    NoReferralFees,
+    #[msg("Account is not this stream's streamer")]
+    InvalidStreamer,
+    #[msg("Streamer cut has already been paid")]
+    StreamerAlreadyPaid,
+    #[msg("Invalid challenge")]
+    InvalidChallenge,
+    #[msg("Challenge has already been accepted")]
+    ChallengeAlreadyAccepted,
+    #[msg("Challenge has not been accepted")]
+    ChallengeNotAccepted,
+    #[msg("Stream has neither a winner nor been voided")]
+    ChallengeNotSettleable,
+    #[msg("Account is already on the current layout")]
+    AlreadyMigrated,
+    #[msg("Stream must be migrated with migrate_stream first")]
+    StreamNotMigrated,
+    #[msg("Payout root is already posted")]
+    PayoutRootAlreadySet,
+    #[msg("Payout root must be nonzero and commit no more than the pool")]
+    InvalidPayoutRoot,
+    #[msg("Winners have already been paid from their positions")]
+    WinningsAlreadyPaid,
+    #[msg("Proven payout does not match the position's winnings")]
+    PayoutMismatch,
+    #[msg("Stream has not been voided")]
+    StreamNotVoided,
 }
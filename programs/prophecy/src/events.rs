@@ -1,59 +1,700 @@
 use anchor_lang::prelude::*;
 
+/// Schema version carried by every event.
+/// Bump whenever a field is added, removed or reinterpreted.
+/// v2: version, slot and unix_timestamp on every event; trade_seq and
+/// both team reserves before/after on trades; EmergencyWithdrawn added
+/// v3: volume, bettor and largest-bet stats on StreamEnded
+pub const EVENT_VERSION: u8 = 3;
+
 #[event]
 pub struct StreamInitialized {
+    pub version: u8,
     pub stream_id: u64,
     pub authority: Pubkey,
     pub team_a_name: String,
     pub team_b_name: String,
-    pub initial_liquidity: u64, // NEW: Total virtual liquidity
+    pub initial_liquidity: u64, // Total virtual liquidity
     pub initial_price: u64,     // Price at initialization
     pub end_time: i64,
     pub stream_link: String,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
 pub struct SharesPurchased {
+    pub version: u8,
     pub stream_id: u64,
+    pub trade_seq: u64,
     pub user: Pubkey,
     pub team_id: u8,
-    pub sol_spent: u64,           // NEW: SOL amount spent
-    pub shares_received: u64,     // NEW: Shares received
-    pub price_before: u64,        // NEW: Price before trade
-    pub price_after: u64,         // NEW: Price after trade
-    pub reserve_team_before: u64, // NEW: For analytics
-    pub reserve_team_after: u64,  // NEW: For analytics
+    pub sol_spent: u64,
+    pub shares_received: u64,
+    pub price_before: u64,
+    pub price_after: u64,
+    pub team_a_reserve_before: u64,
+    pub team_b_reserve_before: u64,
+    pub team_a_reserve_after: u64,
+    pub team_b_reserve_after: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
 pub struct SharesSold {
+    pub version: u8,
     pub stream_id: u64,
+    pub trade_seq: u64,
     pub user: Pubkey,
     pub team_id: u8,
     pub shares_sold: u64,
     pub sol_received: u64,
     pub price_before: u64,
     pub price_after: u64,
-    pub reserve_team_before: u64,
-    pub reserve_team_after: u64,
+    pub team_a_reserve_before: u64,
+    pub team_b_reserve_before: u64,
+    pub team_a_reserve_after: u64,
+    pub team_b_reserve_after: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
 pub struct StreamEnded {
+    pub version: u8,
     pub stream_id: u64,
     pub winning_team: u8,
     pub total_pool: u64,
     pub team_a_shares: u64,
     pub team_b_shares: u64,
-    pub final_team_a_price: u64, // NEW: Final price for analytics
-    pub final_team_b_price: u64, // NEW: Final price for analytics
+    pub final_team_a_price: u64,
+    pub final_team_b_price: u64,
+    pub trade_count: u64, // Final trade_seq
+    pub team_a_buy_volume: u64,
+    pub team_b_buy_volume: u64,
+    pub team_a_sell_volume: u64,
+    pub team_b_sell_volume: u64,
+    pub unique_bettors: u32,
+    pub largest_bet: u64,
+    pub last_trade_time: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
 pub struct WinningsClaimed {
+    pub version: u8,
     pub stream_id: u64,
     pub user: Pubkey,
     pub winning_team: u8,
     pub shares: u64,
     pub payout: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct BatchSettled {
+    pub version: u8,
+    pub stream_id: u64,
+    pub keeper: Pubkey,
+    pub positions_settled: u32,
+    pub total_paid: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PayoutRootPosted {
+    pub version: u8,
+    pub stream_id: u64,
+    pub payout_root: [u8; 32],
+    pub payout_total: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct EmergencyWithdrawn {
+    pub version: u8,
+    pub stream_id: u64,
+    pub authority: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ResultRecorded {
+    pub version: u8,
+    pub stream_id: u64,
+    pub user: Pubkey,
+    pub won: bool,
+    pub realized_pnl: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct BetLimitsUpdated {
+    pub version: u8,
+    pub stream_id: u64,
+    pub min_bet: u64,
+    pub max_bet: u64,
+    pub max_position: u64,
+    pub max_price_impact_bps: u16,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct CircuitBreakerTripped {
+    pub version: u8,
+    pub stream_id: u64,
+    pub trade_seq: u64, // Trade that tripped the breaker
+    pub user: Pubkey,
+    pub ref_price_a: u64,
+    pub ref_price_b: u64,
+    pub price_a: u64,
+    pub price_b: u64,
+    pub move_bps: u64,
+    pub window_start_slot: u64,
+    pub halted_until_slot: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct CircuitBreakerUpdated {
+    pub version: u8,
+    pub stream_id: u64,
+    pub threshold_bps: u16,
+    pub window_slots: u64,
+    pub cooldown_slots: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct CommitRevealUpdated {
+    pub version: u8,
+    pub stream_id: u64,
+    pub commit_secs: i64,
+    pub reveal_secs: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct BetCommitted {
+    pub version: u8,
+    pub stream_id: u64,
+    pub user: Pubkey,
+    pub hash: [u8; 32],
+    pub escrow: u64,
+    pub reveal_start: i64,
+    pub reveal_end: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct BetRevealed {
+    pub version: u8,
+    pub stream_id: u64,
+    pub user: Pubkey,
+    pub reveal_end: i64,
+    pub team_id: u8,
+    pub amount: u64,
+    pub refunded: u64, // Escrow above the revealed amount
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct CommitBatchCleared {
+    pub version: u8,
+    pub stream_id: u64,
+    pub reveal_end: i64,
+    pub trade_seq: u64, // trade_seq after the batch's fills
+    pub reveals: u32,
+    pub team_a_sol: u64,
+    pub team_b_sol: u64,
+    pub team_a_shares: u64,
+    pub team_b_shares: u64,
+    pub team_a_reserve_before: u64,
+    pub team_b_reserve_before: u64,
+    pub team_a_reserve_after: u64,
+    pub team_b_reserve_after: u64,
+    pub fill_bps: u16,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct CommitmentSettled {
+    pub version: u8,
+    pub stream_id: u64,
+    pub user: Pubkey,
+    pub reveal_end: i64,
+    pub team_id: u8,
+    pub sol_spent: u64,
+    pub shares_received: u64,
+    pub returned: u64, // Unfilled lamports handed back
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct CommitmentCancelled {
+    pub version: u8,
+    pub stream_id: u64,
+    pub user: Pubkey,
+    pub reveal_end: i64,
+    pub refunded: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct AuctionModeUpdated {
+    pub version: u8,
+    pub stream_id: u64,
+    pub interval_secs: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct OrderSubmitted {
+    pub version: u8,
+    pub stream_id: u64,
+    pub user: Pubkey,
+    pub batch_end: i64,
+    pub team_id: u8,
+    pub is_buy: bool,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct OrderBatchCleared {
+    pub version: u8,
+    pub stream_id: u64,
+    pub batch_end: i64,
+    pub trade_seq: u64, // trade_seq after the batch's fills
+    pub orders: u32,
+    pub team_a_buy_sol: u64,
+    pub team_a_sell_shares: u64,
+    pub team_a_buy_fill: u64,
+    pub team_a_sell_fill: u64,
+    pub team_b_buy_sol: u64,
+    pub team_b_sell_shares: u64,
+    pub team_b_buy_fill: u64,
+    pub team_b_sell_fill: u64,
+    pub team_a_reserve_before: u64,
+    pub team_b_reserve_before: u64,
+    pub team_a_reserve_after: u64,
+    pub team_b_reserve_after: u64,
+    pub fill_bps: u16,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct OrderSettled {
+    pub version: u8,
+    pub stream_id: u64,
+    pub user: Pubkey,
+    pub batch_end: i64,
+    pub team_id: u8,
+    pub is_buy: bool,
+    pub amount: u64,
+    pub filled: u64,   // Shares received for a buy, lamports for a sell
+    pub returned: u64, // Unfilled lamports or shares handed back
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct OrderCancelled {
+    pub version: u8,
+    pub stream_id: u64,
+    pub user: Pubkey,
+    pub batch_end: i64,
+    pub team_id: u8,
+    pub is_buy: bool,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct LimitOrderPlaced {
+    pub version: u8,
+    pub stream_id: u64,
+    pub user: Pubkey,
+    pub order_id: u64,
+    pub team_id: u8,
+    pub is_buy: bool,
+    pub amount: u64,
+    pub limit_price: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct LimitOrderFilled {
+    pub version: u8,
+    pub stream_id: u64,
+    pub trade_seq: u64,
+    pub user: Pubkey,
+    pub keeper: Pubkey,
+    pub order_id: u64,
+    pub team_id: u8,
+    pub is_buy: bool,
+    pub amount: u64,
+    pub filled: u64, // Shares received for a buy, lamports for a sell
+    pub limit_price: u64,
+    pub price_before: u64,
+    pub price_after: u64,
+    pub team_a_reserve_before: u64,
+    pub team_b_reserve_before: u64,
+    pub team_a_reserve_after: u64,
+    pub team_b_reserve_after: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct LimitOrderCancelled {
+    pub version: u8,
+    pub stream_id: u64,
+    pub user: Pubkey,
+    pub order_id: u64,
+    pub team_id: u8,
+    pub is_buy: bool,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct TriggerSet {
+    pub version: u8,
+    pub stream_id: u64,
+    pub user: Pubkey,
+    pub team_id: u8,
+    pub stop_loss_price: u64,
+    pub take_profit_price: u64,
+    pub max_slippage_bps: u16,
+    pub tip: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct TriggerExecuted {
+    pub version: u8,
+    pub stream_id: u64,
+    pub trade_seq: u64,
+    pub user: Pubkey,
+    pub keeper: Pubkey,
+    pub team_id: u8,
+    pub is_stop_loss: bool,
+    pub shares_sold: u64,
+    pub sol_received: u64,
+    pub tip: u64,
+    pub price_before: u64,
+    pub price_after: u64,
+    pub team_a_reserve_before: u64,
+    pub team_b_reserve_before: u64,
+    pub team_a_reserve_after: u64,
+    pub team_b_reserve_after: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct TriggerCancelled {
+    pub version: u8,
+    pub stream_id: u64,
+    pub user: Pubkey,
+    pub team_id: u8,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct SidesSwapped {
+    pub version: u8,
+    pub stream_id: u64,
+    pub trade_seq: u64,
+    pub user: Pubkey,
+    pub from_team: u8,
+    pub to_team: u8,
+    pub shares_in: u64,
+    pub sol_value: u64, // Lamports the sold shares were worth, reinvested in place
+    pub shares_out: u64,
+    pub team_a_price_before: u64,
+    pub team_b_price_before: u64,
+    pub team_a_price_after: u64,
+    pub team_b_price_after: u64,
+    pub team_a_reserve_before: u64,
+    pub team_b_reserve_before: u64,
+    pub team_a_reserve_after: u64,
+    pub team_b_reserve_after: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct CashedOut {
+    pub version: u8,
+    pub stream_id: u64,
+    pub trade_seq: u64,
+    pub user: Pubkey,
+    pub team_id: u8, // Zero when both teams were sold
+    pub pct_bps: u16,
+    pub team_a_shares_sold: u64,
+    pub team_b_shares_sold: u64,
+    pub sol_received: u64,
+    pub position_emptied: bool,
+    pub position_closed: bool, // Rent went back to the owner
+    pub team_a_reserve_before: u64,
+    pub team_b_reserve_before: u64,
+    pub team_a_reserve_after: u64,
+    pub team_b_reserve_after: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct SessionCreated {
+    pub version: u8,
+    pub user: Pubkey,
+    pub session_signer: Pubkey,
+    pub expires_at: i64,
+    pub spend_limit: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct SessionRevoked {
+    pub version: u8,
+    pub user: Pubkey,
+    pub session_signer: Pubkey,
+    pub spent: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct AdminUpdated {
+    pub version: u8,
+    pub admin: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PromoCreditIssued {
+    pub version: u8,
+    pub user: Pubkey,
+    pub issuer: Pubkey,
+    pub stream_id: u64, // Zero for a credit usable on any stream
+    pub amount: u64,
+    pub balance: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PromoCreditSpent {
+    pub version: u8,
+    pub stream_id: u64,
+    pub trade_seq: u64,
+    pub user: Pubkey,
+    pub credit_stream_id: u64,
+    pub team_id: u8,
+    pub amount: u64,
+    pub shares: u64,
+    pub balance: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PromoCreditRevoked {
+    pub version: u8,
+    pub user: Pubkey,
+    pub issuer: Pubkey,
+    pub stream_id: u64,
+    pub balance: u64, // Unspent lamports returned to the issuer
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct FeesUpdated {
+    pub version: u8,
+    pub stream_id: u64,
+    pub fee_bps: u16,
+    pub referral_share_bps: u16,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct FeesWithdrawn {
+    pub version: u8,
+    pub stream_id: u64,
+    pub authority: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct TradingFeeCharged {
+    pub version: u8,
+    pub stream_id: u64,
+    pub trade_seq: u64,
+    pub user: Pubkey,
+    pub fee: u64,
+    pub referrer: Pubkey, // Default when no referrer was paid
+    pub referral_fee: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ReferrerRegistered {
+    pub version: u8,
+    pub referrer: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ReferralFeesClaimed {
+    pub version: u8,
+    pub referrer: Pubkey,
+    pub amount: u64,
+    pub total_claimed: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct StreamerUpdated {
+    pub version: u8,
+    pub stream_id: u64,
+    pub streamer: Pubkey,
+    pub streamer_cut_bps: u16,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct StreamerTipped {
+    pub version: u8,
+    pub stream_id: u64,
+    pub tipper: Pubkey,
+    pub streamer: Pubkey,
+    pub amount: u64,
+    pub total_tips: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct StreamerPaid {
+    pub version: u8,
+    pub stream_id: u64,
+    pub streamer: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ChallengeCreated {
+    pub version: u8,
+    pub stream_id: u64,
+    pub challenge_id: u64,
+    pub creator: Pubkey,
+    pub counterparty: Pubkey,
+    pub team_id: u8,
+    pub creator_stake: u64,
+    pub counterparty_stake: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ChallengeAccepted {
+    pub version: u8,
+    pub stream_id: u64,
+    pub challenge_id: u64,
+    pub creator: Pubkey,
+    pub acceptor: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ChallengeCancelled {
+    pub version: u8,
+    pub stream_id: u64,
+    pub challenge_id: u64,
+    pub creator: Pubkey,
+    pub refunded: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ChallengeSettled {
+    pub version: u8,
+    pub stream_id: u64,
+    pub challenge_id: u64,
+    pub creator: Pubkey,
+    pub acceptor: Pubkey,
+    pub winner: Pubkey, // Default when the stream was voided and both stakes refunded
+    pub payout: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct StreamVoided {
+    pub version: u8,
+    pub stream_id: u64,
+    pub total_pool: u64, // Left for refunds
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct RefundClaimed {
+    pub version: u8,
+    pub stream_id: u64,
+    pub user: Pubkey,
+    pub refund: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct AccountMigrated {
+    pub version: u8,
+    pub account: Pubkey,
+    pub old_len: u64,
+    pub new_len: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
@@ -2,6 +2,7 @@ use crate::context::*;
 use crate::errors::ErrorCode;
 use crate::events::*;
 use crate::helpers::*;
+use crate::state::*;
 use anchor_lang::prelude::*;
 
 pub fn initialize_stream_handler(
@@ -16,7 +17,7 @@ pub fn initialize_stream_handler(
     require!(team_a_name.len() <= 32, ErrorCode::NameTooLong);
     require!(team_b_name.len() <= 32, ErrorCode::NameTooLong);
     require!(initial_liquidity > 0, ErrorCode::InvalidPrice);
-    require!(initial_liquidity % 2 == 0, ErrorCode::InvalidPrice);
+    require!(initial_liquidity.is_multiple_of(2), ErrorCode::InvalidPrice);
     require!(stream_duration > 0, ErrorCode::InvalidDuration);
 
     let stream = &mut ctx.accounts.stream;
@@ -37,16 +38,62 @@ pub fn initialize_stream_handler(
     stream.team_a_shares_sold = 0;
     stream.team_b_shares_sold = 0;
     stream.total_pool = 0;
+    stream.trade_seq = 0;
+    stream.team_a_buy_volume = 0;
+    stream.team_b_buy_volume = 0;
+    stream.team_a_sell_volume = 0;
+    stream.team_b_sell_volume = 0;
+    stream.unique_bettors = 0;
+    stream.largest_bet = 0;
+    stream.last_trade_time = 0;
+    stream.min_bet = 0;
+    stream.max_bet = 0;
+    stream.max_position = 0;
+    stream.max_price_impact_bps = 0;
+    stream.fee_bps = 0;
+    stream.referral_share_bps = 0;
+    stream.fees_collected = 0;
+    stream.breaker_threshold_bps = 0;
+    stream.breaker_window_slots = 0;
+    stream.breaker_cooldown_slots = 0;
+    stream.breaker_window_start_slot = 0;
+    stream.breaker_ref_price_a = 0;
+    stream.breaker_ref_price_b = 0;
+    stream.halted_until_slot = 0;
+    stream.commit_secs = 0;
+    stream.reveal_secs = 0;
+    stream.commit_reveal_start = 0;
+    stream.auction_interval_secs = 0;
+    stream.auction_start = 0;
+    stream.team_a_price_cumulative = 0;
+    stream.team_b_price_cumulative = 0;
+    stream.last_price_update = clock.unix_timestamp;
     stream.start_time = clock.unix_timestamp;
     stream.end_time = clock.unix_timestamp + stream_duration;
     stream.is_active = true;
     stream.winning_team = 0;
     stream.bump = ctx.bumps.stream;
+    stream.layout_version = STREAM_LAYOUT_VERSION;
     stream.stream_link = stream_link;
+    stream.streamer = ctx.accounts.authority.key();
+    stream.streamer_cut_bps = 0;
+    stream.streamer_tips = 0;
+    stream.streamer_paid = false;
+    stream.payout_root = [0u8; 32];
+    stream.payout_root_total = 0;
+    stream.winnings_paid = 0;
+    stream.is_voided = false;
+    stream.streamer_owed = 0;
 
     let initial_price = calculate_price(stream.team_a_reserve, stream.team_b_reserve)?;
 
-    emit!(StreamInitialized {
+    let mut price_history = ctx.accounts.price_history.load_init()?;
+    price_history.stream_id = stream_id;
+    price_history.interval_secs = PRICE_HISTORY_INTERVAL_SECS;
+    drop(price_history);
+
+    emit_cpi!(StreamInitialized {
+        version: EVENT_VERSION,
         stream_id,
         authority: ctx.accounts.authority.key(),
         team_a_name: stream.team_a_name.clone(),
@@ -55,19 +102,95 @@ pub fn initialize_stream_handler(
         initial_price,
         end_time: stream.end_time,
         stream_link: stream.stream_link.clone(),
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
     });
 
     Ok(())
 }
 
-pub fn purchase_shares_handler(
-    ctx: Context<PurchaseShares>,
-    stream_id: u64,
+/// Accounts a curve trade touches, borrowed from the instruction's context
+struct TradeAccounts<'a, 'info> {
+    stream: &'a mut Account<'info, Stream>,
+    user_position: &'a mut Account<'info, UserPosition>,
+    user_profile: &'a mut Account<'info, UserProfile>,
+    stream_vault: AccountInfo<'info>,
+    vault_bump: u8,
+    price_history: &'a AccountLoader<'info, PriceHistory>,
+    user: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+}
+
+/// Where the lamports for a purchase come from
+enum BuyFunding<'a, 'info> {
+    /// The position owner's wallet, which must sign
+    Wallet,
+    /// The budget escrowed on the owner's session token
+    Session(&'a mut Account<'info, SessionToken>),
+    /// A promo credit; the shares it buys are locked until resolution
+    Promo(&'a mut Account<'info, PromoCredit>),
+    /// The lamports escrowed on a resting limit buy
+    Order(AccountInfo<'info>),
+}
+
+/// Events produced by a purchase, in emission order
+struct BuyFill {
+    breaker: Option<CircuitBreakerTripped>,
+    promo: Option<PromoCreditSpent>,
+    fee: Option<TradingFeeCharged>,
+    purchased: SharesPurchased,
+}
+
+/// Events produced by a sale, in emission order
+struct SellFill {
+    breaker: Option<CircuitBreakerTripped>,
+    sold: SharesSold,
+}
+
+/// Breaker event for a trade, if it tripped the circuit breaker
+fn breaker_tripped(
+    stream: &Stream,
+    user: Pubkey,
+    trip: Option<BreakerTrip>,
+    prices_after: (u64, u64),
+    clock: &Clock,
+) -> Option<CircuitBreakerTripped> {
+    trip.map(|trip| CircuitBreakerTripped {
+        version: EVENT_VERSION,
+        stream_id: stream.stream_id,
+        trade_seq: stream.trade_seq,
+        user,
+        ref_price_a: trip.ref_prices.0,
+        ref_price_b: trip.ref_prices.1,
+        price_a: prices_after.0,
+        price_b: prices_after.1,
+        move_bps: trip.move_bps,
+        window_start_slot: trip.window_start_slot,
+        halted_until_slot: stream.halted_until_slot,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    })
+}
+
+/// Buy `sol_amount` of `team_id` on the curve for `accounts.user`, paid from
+/// `funding`. The trading fee comes out of `sol_amount`; `referral` earns its
+/// share when present. `rent_payer` is recorded if the purchase opens the
+/// position
+#[allow(clippy::too_many_arguments)]
+fn execute_buy<'info>(
+    accounts: TradeAccounts<'_, 'info>,
+    funding: BuyFunding<'_, 'info>,
+    mut referral: Option<&mut Account<'info, Referral>>,
     team_id: u8,
     sol_amount: u64,
-) -> Result<()> {
-    let stream = &mut ctx.accounts.stream;
-    let user_position = &mut ctx.accounts.user_position;
+    rent_payer: Pubkey,
+    position_bump: u8,
+    profile_bump: u8,
+) -> Result<BuyFill> {
+    let stream = accounts.stream;
+    let user_position = accounts.user_position;
+    let user_profile = accounts.user_profile;
+    let user = accounts.user.key();
     let clock = Clock::get()?;
 
     require!(stream.is_active, ErrorCode::StreamNotActive);
@@ -77,6 +200,20 @@ pub fn purchase_shares_handler(
     );
     require!(team_id == 1 || team_id == 2, ErrorCode::InvalidTeam);
     require!(sol_amount > 0, ErrorCode::InvalidAmount);
+    require!(stream.commit_secs == 0, ErrorCode::CommitRevealRequired);
+    require!(
+        stream.auction_interval_secs == 0,
+        ErrorCode::AuctionModeActive
+    );
+    check_circuit_breaker(stream, clock.slot)?;
+
+    check_referral(
+        user_position,
+        user,
+        referral.as_ref().map(|referral| referral.referrer),
+    )?;
+    let (fee, referral_fee) = calculate_fees(stream, sol_amount, referral.is_some())?;
+    let net_amount = sol_amount - fee;
 
     let (reserve_team, reserve_opposite) = if team_id == 1 {
         (stream.team_a_reserve, stream.team_b_reserve)
@@ -85,50 +222,99 @@ pub fn purchase_shares_handler(
     };
 
     let price_before = calculate_price(reserve_team, reserve_opposite)?;
+    let team_a_reserve_before = stream.team_a_reserve;
+    let team_b_reserve_before = stream.team_b_reserve;
 
-    let shares_out = calculate_shares_out(sol_amount, reserve_team, reserve_opposite)?;
+    let shares_out = calculate_shares_out(net_amount, reserve_team, reserve_opposite)?;
 
-    let cpi_context = CpiContext::new(
-        ctx.accounts.system_program.to_account_info(),
-        anchor_lang::system_program::Transfer {
-            from: ctx.accounts.user.to_account_info(),
-            to: ctx.accounts.stream_vault.to_account_info(),
-        },
-    );
-    anchor_lang::system_program::transfer(cpi_context, sol_amount)?;
+    let price_impact_bps = calculate_price_impact_bps(
+        price_before,
+        calculate_price(
+            reserve_team
+                .checked_sub(shares_out)
+                .ok_or(ErrorCode::MathOverflow)?,
+            reserve_opposite
+                .checked_add(net_amount)
+                .ok_or(ErrorCode::MathOverflow)?,
+        )?,
+    )?;
+    let position_invested = position_stake(user_position)?
+        .checked_add(sol_amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    check_bet_limits(stream, sol_amount, position_invested, price_impact_bps)?;
 
-    if team_id == 1 {
-        stream.team_a_reserve = stream
-            .team_a_reserve
-            .checked_sub(shares_out)
-            .ok_or(ErrorCode::MathOverflow)?;
-        stream.team_b_reserve = stream
-            .team_b_reserve
-            .checked_add(sol_amount)
-            .ok_or(ErrorCode::MathOverflow)?;
-        stream.team_a_shares_sold = stream
-            .team_a_shares_sold
-            .checked_add(shares_out)
-            .ok_or(ErrorCode::MathOverflow)?;
-    } else {
-        stream.team_b_reserve = stream
-            .team_b_reserve
-            .checked_sub(shares_out)
-            .ok_or(ErrorCode::MathOverflow)?;
-        stream.team_a_reserve = stream
-            .team_a_reserve
-            .checked_add(sol_amount)
-            .ok_or(ErrorCode::MathOverflow)?;
-        stream.team_b_shares_sold = stream
-            .team_b_shares_sold
-            .checked_add(shares_out)
-            .ok_or(ErrorCode::MathOverflow)?;
+    accumulate_prices(stream, clock.unix_timestamp)?;
+
+    let mut promo_credit_spent = None;
+    let escrowed = match funding {
+        BuyFunding::Wallet => {
+            let cpi_context = CpiContext::new(
+                accounts.system_program.clone(),
+                anchor_lang::system_program::Transfer {
+                    from: accounts.user.clone(),
+                    to: accounts.stream_vault.clone(),
+                },
+            );
+            anchor_lang::system_program::transfer(cpi_context, sol_amount - referral_fee)?;
+
+            if let Some(referral) = &referral {
+                if referral_fee > 0 {
+                    let cpi_context = CpiContext::new(
+                        accounts.system_program.clone(),
+                        anchor_lang::system_program::Transfer {
+                            from: accounts.user.clone(),
+                            to: referral.to_account_info(),
+                        },
+                    );
+                    anchor_lang::system_program::transfer(cpi_context, referral_fee)?;
+                }
+            }
+            false
+        }
+        BuyFunding::Session(session_token) => {
+            spend_session(session_token, sol_amount)?;
+            true
+        }
+        BuyFunding::Promo(promo_credit) => {
+            require!(promo_credit.user == user, ErrorCode::InvalidPromoCredit);
+            require!(
+                promo_credit.stream_id == 0 || promo_credit.stream_id == stream.stream_id,
+                ErrorCode::InvalidPromoCredit
+            );
+            promo_credit.balance = promo_credit
+                .balance
+                .checked_sub(sol_amount)
+                .ok_or(ErrorCode::InsufficientPromoCredit)?;
+            promo_credit.sub_lamports(sol_amount)?;
+            promo_credit_spent = Some((promo_credit.stream_id, promo_credit.balance));
+            true
+        }
+        BuyFunding::Order(limit_order) => {
+            limit_order.sub_lamports(sol_amount)?;
+            true
+        }
+    };
+    // Escrowed funding is program-owned, so it moves without a system transfer
+    if escrowed {
+        accounts
+            .stream_vault
+            .add_lamports(sol_amount - referral_fee)?;
+        if let Some(referral) = &referral {
+            referral.add_lamports(referral_fee)?;
+        }
     }
 
-    stream.total_pool = stream
-        .total_pool
-        .checked_add(sol_amount)
-        .ok_or(ErrorCode::MathOverflow)?;
+    book_fees(
+        stream,
+        referral.as_deref_mut().map(|referral| &mut **referral),
+        sol_amount,
+        fee,
+        referral_fee,
+    )?;
+
+    apply_buy(stream, team_id, net_amount, shares_out)?;
+    stream.last_trade_time = clock.unix_timestamp;
+    stream.largest_bet = stream.largest_bet.max(sol_amount);
 
     let (reserve_team_after, reserve_opposite_after) = if team_id == 1 {
         (stream.team_a_reserve, stream.team_b_reserve)
@@ -137,15 +323,40 @@ pub fn purchase_shares_handler(
     };
     let price_after = calculate_price(reserve_team_after, reserve_opposite_after)?;
 
+    let prices_before = team_prices(team_a_reserve_before, team_b_reserve_before)?;
+    let prices_after = team_prices(stream.team_a_reserve, stream.team_b_reserve)?;
+
+    record_candle(
+        &mut *accounts.price_history.load_mut()?,
+        clock.unix_timestamp,
+        team_id,
+        net_amount,
+        prices_before,
+        prices_after,
+    )?;
+
+    let trip = update_circuit_breaker(stream, clock.slot, prices_before, prices_after)?;
+    let breaker = breaker_tripped(stream, user, trip, prices_after, &clock);
+
     if user_position.user == Pubkey::default() {
-        user_position.user = ctx.accounts.user.key();
-        user_position.stream_id = stream_id;
-        user_position.team_a_shares = 0;
-        user_position.team_b_shares = 0;
-        user_position.total_invested = 0;
-        user_position.has_claimed = false;
-        user_position.bump = ctx.bumps.user_position;
+        open_position(
+            stream,
+            user_position,
+            user_profile,
+            user,
+            rent_payer,
+            position_bump,
+            profile_bump,
+        )?;
     }
+    if let Some(referral) = &referral {
+        user_position.referrer = referral.referrer;
+    }
+
+    user_profile.lifetime_volume = user_profile
+        .lifetime_volume
+        .checked_add(sol_amount)
+        .ok_or(ErrorCode::MathOverflow)?;
 
     if team_id == 1 {
         user_position.team_a_shares = user_position
@@ -159,34 +370,99 @@ pub fn purchase_shares_handler(
             .ok_or(ErrorCode::MathOverflow)?;
     }
 
-    user_position.total_invested = user_position
-        .total_invested
-        .checked_add(sol_amount)
-        .ok_or(ErrorCode::MathOverflow)?;
+    // Credit-funded shares are locked in the position and cost the user
+    // nothing, so the credit is tracked apart from their own cost basis
+    let promo = match promo_credit_spent {
+        Some((credit_stream_id, balance)) => {
+            user_position.promo_invested = user_position
+                .promo_invested
+                .checked_add(sol_amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+            if team_id == 1 {
+                user_position.promo_shares_a = user_position
+                    .promo_shares_a
+                    .checked_add(shares_out)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            } else {
+                user_position.promo_shares_b = user_position
+                    .promo_shares_b
+                    .checked_add(shares_out)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
 
-    emit!(SharesPurchased {
-        stream_id,
-        user: ctx.accounts.user.key(),
-        team_id,
-        sol_spent: sol_amount,
-        shares_received: shares_out,
-        price_before,
-        price_after,
-        reserve_team_before: reserve_team,
-        reserve_team_after: reserve_team_after,
+            Some(PromoCreditSpent {
+                version: EVENT_VERSION,
+                stream_id: stream.stream_id,
+                trade_seq: stream.trade_seq,
+                user,
+                credit_stream_id,
+                team_id,
+                amount: sol_amount,
+                shares: shares_out,
+                balance,
+                slot: clock.slot,
+                unix_timestamp: clock.unix_timestamp,
+            })
+        }
+        None => {
+            user_position.total_invested = user_position
+                .total_invested
+                .checked_add(sol_amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+            None
+        }
+    };
+
+    let fee = (fee > 0).then(|| TradingFeeCharged {
+        version: EVENT_VERSION,
+        stream_id: stream.stream_id,
+        trade_seq: stream.trade_seq,
+        user,
+        fee,
+        referrer: referral
+            .as_ref()
+            .map_or(Pubkey::default(), |referral| referral.referrer),
+        referral_fee,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
     });
 
-    Ok(())
+    Ok(BuyFill {
+        breaker,
+        promo,
+        fee,
+        purchased: SharesPurchased {
+            version: EVENT_VERSION,
+            stream_id: stream.stream_id,
+            trade_seq: stream.trade_seq,
+            user,
+            team_id,
+            sol_spent: sol_amount,
+            shares_received: shares_out,
+            price_before,
+            price_after,
+            team_a_reserve_before,
+            team_b_reserve_before,
+            team_a_reserve_after: stream.team_a_reserve,
+            team_b_reserve_after: stream.team_b_reserve,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        },
+    })
 }
 
-pub fn sell_shares_handler(
-    ctx: Context<SellShares>,
-    stream_id: u64,
+/// Sell `shares_amount` of `team_id` from `accounts.user`'s position back to
+/// the curve, paying the proceeds out of the vault to `recipient`
+fn execute_sell<'info>(
+    accounts: TradeAccounts<'_, 'info>,
+    recipient: AccountInfo<'info>,
     team_id: u8,
     shares_amount: u64,
-) -> Result<()> {
-    let stream = &mut ctx.accounts.stream;
-    let user_position = &mut ctx.accounts.user_position;
+) -> Result<SellFill> {
+    let stream = accounts.stream;
+    let user_position = accounts.user_position;
+    let user_profile = accounts.user_profile;
+    let user = accounts.user.key();
     let clock = Clock::get()?;
 
     require!(stream.is_active, ErrorCode::StreamNotActive);
@@ -197,21 +473,16 @@ pub fn sell_shares_handler(
     require!(team_id == 1 || team_id == 2, ErrorCode::InvalidTeam);
     require!(shares_amount > 0, ErrorCode::InvalidAmount);
     require!(
-        ctx.accounts.user.key() == user_position.user,
-        ErrorCode::Unauthorized
+        stream.auction_interval_secs == 0,
+        ErrorCode::AuctionModeActive
     );
+    check_circuit_breaker(stream, clock.slot)?;
+    require!(user == user_position.user, ErrorCode::Unauthorized);
 
-    if team_id == 1 {
-        require!(
-            user_position.team_a_shares >= shares_amount,
-            ErrorCode::InsufficientShares
-        );
-    } else {
-        require!(
-            user_position.team_b_shares >= shares_amount,
-            ErrorCode::InsufficientShares
-        );
-    }
+    require!(
+        sellable_shares(user_position, team_id) >= shares_amount,
+        ErrorCode::InsufficientShares
+    );
 
     let (reserve_team, reserve_opposite) = if team_id == 1 {
         (stream.team_a_reserve, stream.team_b_reserve)
@@ -220,60 +491,24 @@ pub fn sell_shares_handler(
     };
 
     let price_before = calculate_price(reserve_team, reserve_opposite)?;
+    let team_a_reserve_before = stream.team_a_reserve;
+    let team_b_reserve_before = stream.team_b_reserve;
 
     let sol_out = calculate_sol_out(shares_amount, reserve_team, reserve_opposite)?;
 
-    if team_id == 1 {
-        stream.team_a_reserve = stream
-            .team_a_reserve
-            .checked_add(shares_amount)
-            .ok_or(ErrorCode::MathOverflow)?;
-        stream.team_b_reserve = stream
-            .team_b_reserve
-            .checked_sub(sol_out)
-            .ok_or(ErrorCode::MathOverflow)?;
-        stream.team_a_shares_sold = stream
-            .team_a_shares_sold
-            .checked_sub(shares_amount)
-            .ok_or(ErrorCode::MathOverflow)?;
-    } else {
-        stream.team_b_reserve = stream
-            .team_b_reserve
-            .checked_add(shares_amount)
-            .ok_or(ErrorCode::MathOverflow)?;
-        stream.team_a_reserve = stream
-            .team_a_reserve
-            .checked_sub(sol_out)
-            .ok_or(ErrorCode::MathOverflow)?;
-        stream.team_b_shares_sold = stream
-            .team_b_shares_sold
-            .checked_sub(shares_amount)
-            .ok_or(ErrorCode::MathOverflow)?;
-    }
+    accumulate_prices(stream, clock.unix_timestamp)?;
 
-    stream.total_pool = stream
-        .total_pool
-        .checked_sub(sol_out)
-        .ok_or(ErrorCode::MathOverflow)?;
+    apply_sell(stream, team_id, shares_amount, sol_out)?;
+    stream.last_trade_time = clock.unix_timestamp;
 
-    // Transfer SOL from vault to user using PDA seeds for signing
-    let stream_id_bytes = stream_id.to_le_bytes();
-    let seeds = &[
-        b"stream_vault".as_ref(),
-        stream_id_bytes.as_ref(),
-        &[ctx.bumps.stream_vault],
-    ];
-    let signer_seeds = &[&seeds[..]];
-
-    let transfer_ctx = CpiContext::new_with_signer(
-        ctx.accounts.system_program.to_account_info(),
-        anchor_lang::system_program::Transfer {
-            from: ctx.accounts.stream_vault.to_account_info(),
-            to: ctx.accounts.user.to_account_info(),
-        },
-        signer_seeds,
-    );
-    anchor_lang::system_program::transfer(transfer_ctx, sol_out)?;
+    pay_from_vault(
+        &accounts.system_program,
+        &accounts.stream_vault,
+        &recipient,
+        stream.stream_id,
+        accounts.vault_bump,
+        sol_out,
+    )?;
 
     let (reserve_team_after, reserve_opposite_after) = if team_id == 1 {
         (stream.team_a_reserve, stream.team_b_reserve)
@@ -282,6 +517,21 @@ pub fn sell_shares_handler(
     };
     let price_after = calculate_price(reserve_team_after, reserve_opposite_after)?;
 
+    let prices_before = team_prices(team_a_reserve_before, team_b_reserve_before)?;
+    let prices_after = team_prices(stream.team_a_reserve, stream.team_b_reserve)?;
+
+    record_candle(
+        &mut *accounts.price_history.load_mut()?,
+        clock.unix_timestamp,
+        team_id,
+        sol_out,
+        prices_before,
+        prices_after,
+    )?;
+
+    let trip = update_circuit_breaker(stream, clock.slot, prices_before, prices_after)?;
+    let breaker = breaker_tripped(stream, user, trip, prices_after, &clock);
+
     if team_id == 1 {
         user_position.team_a_shares = user_position
             .team_a_shares
@@ -294,162 +544,3382 @@ pub fn sell_shares_handler(
             .ok_or(ErrorCode::MathOverflow)?;
     }
 
-    user_position.total_invested = user_position
-        .total_invested
-        .checked_sub(sol_out)
+    reduce_cost_basis(user_position, sol_out);
+
+    user_profile.lifetime_volume = user_profile
+        .lifetime_volume
+        .checked_add(sol_out)
         .ok_or(ErrorCode::MathOverflow)?;
 
-    emit!(SharesSold {
-        stream_id,
-        user: ctx.accounts.user.key(),
-        team_id,
-        shares_sold: shares_amount,
-        sol_received: sol_out,
-        price_before,
-        price_after,
-        reserve_team_before: reserve_team,
-        reserve_team_after: reserve_team_after,
-    });
+    Ok(SellFill {
+        breaker,
+        sold: SharesSold {
+            version: EVENT_VERSION,
+            stream_id: stream.stream_id,
+            trade_seq: stream.trade_seq,
+            user,
+            team_id,
+            shares_sold: shares_amount,
+            sol_received: sol_out,
+            price_before,
+            price_after,
+            team_a_reserve_before,
+            team_b_reserve_before,
+            team_a_reserve_after: stream.team_a_reserve,
+            team_b_reserve_after: stream.team_b_reserve,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        },
+    })
+}
 
-    Ok(())
+/// Accounts a position's winnings claim touches, borrowed from the
+/// instruction's context
+struct ClaimAccounts<'a, 'info> {
+    stream: &'a mut Account<'info, Stream>,
+    user_position: &'a mut Account<'info, UserPosition>,
+    user_profile: &'a mut Account<'info, UserProfile>,
+    stream_vault: AccountInfo<'info>,
+    vault_bump: u8,
+    user: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
 }
 
-pub fn end_stream_handler(
-    ctx: Context<EndStream>,
-    _stream_id: u64,
-    winning_team: u8,
-) -> Result<()> {
-    let stream = &mut ctx.accounts.stream;
+/// Pay out `accounts.user_position`'s winning shares to the position owner
+/// and record the win on their profile. Whoever signs, the payout only ever
+/// goes to `accounts.user`
+fn pay_winnings(accounts: ClaimAccounts<'_, '_>) -> Result<WinningsClaimed> {
+    let stream = accounts.stream;
+    let user_position = accounts.user_position;
     let clock = Clock::get()?;
 
-    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(!stream.is_active, ErrorCode::StreamStillActive);
+    require!(stream.winning_team != 0, ErrorCode::NoWinnerDeclared);
+    require!(!user_position.has_claimed, ErrorCode::AlreadyClaimed);
     require!(
-        ctx.accounts.authority.key() == stream.authority,
+        user_position.user == accounts.user.key(),
         ErrorCode::Unauthorized
     );
-    require!(
-        clock.unix_timestamp >= stream.end_time,
-        ErrorCode::StreamNotEnded
-    );
-    require!(
-        winning_team == 1 || winning_team == 2,
-        ErrorCode::InvalidTeam
-    );
 
-    stream.is_active = false;
-    stream.winning_team = winning_team;
+    let (user_winning_shares, payout) = winning_payout(stream, user_position)?;
+    require!(user_winning_shares > 0, ErrorCode::NoWinningShares);
+    require!(payout > 0, ErrorCode::NoPayout);
 
-    let final_team_a_price = calculate_price(stream.team_a_reserve, stream.team_b_reserve)?;
-    let final_team_b_price = calculate_price(stream.team_b_reserve, stream.team_a_reserve)?;
+    pay_from_vault(
+        &accounts.system_program,
+        &accounts.stream_vault,
+        &accounts.user,
+        stream.stream_id,
+        accounts.vault_bump,
+        payout,
+    )?;
+
+    stream.winnings_paid = stream
+        .winnings_paid
+        .checked_add(payout)
+        .ok_or(ErrorCode::MathOverflow)?;
+    user_position.has_claimed = true;
+    record_result(accounts.user_profile, user_position, true, payout)?;
 
-    emit!(StreamEnded {
+    Ok(WinningsClaimed {
+        version: EVENT_VERSION,
         stream_id: stream.stream_id,
-        winning_team,
-        total_pool: stream.total_pool,
-        team_a_shares: stream.team_a_shares_sold,
-        team_b_shares: stream.team_b_shares_sold,
-        final_team_a_price,
-        final_team_b_price,
-    });
+        user: accounts.user.key(),
+        winning_team: stream.winning_team,
+        shares: user_winning_shares,
+        payout,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    })
+}
+
+pub fn purchase_shares_handler(
+    ctx: Context<PurchaseShares>,
+    _stream_id: u64,
+    team_id: u8,
+    sol_amount: u64,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    check_trader(
+        ctx.accounts.user.key(),
+        ctx.accounts.signer.key(),
+        ctx.accounts.session_token.as_deref(),
+        clock.unix_timestamp,
+    )?;
+
+    // Bets come from the session budget when a session key trades, otherwise
+    // from a promo credit when one is passed, otherwise from the wallet. A
+    // session key never spends the user's promo credit
+    let funding = match (
+        ctx.accounts.session_token.as_mut(),
+        ctx.accounts.promo_credit.as_mut(),
+    ) {
+        (Some(_), Some(_)) => return err!(ErrorCode::InvalidPromoCredit),
+        (Some(session_token), None) => BuyFunding::Session(session_token),
+        (None, Some(promo_credit)) => BuyFunding::Promo(promo_credit),
+        (None, None) => BuyFunding::Wallet,
+    };
+    let fill = execute_buy(
+        TradeAccounts {
+            stream: &mut ctx.accounts.stream,
+            user_position: &mut ctx.accounts.user_position,
+            user_profile: &mut ctx.accounts.user_profile,
+            stream_vault: ctx.accounts.stream_vault.to_account_info(),
+            vault_bump: ctx.bumps.stream_vault,
+            price_history: &ctx.accounts.price_history,
+            user: ctx.accounts.user.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+        },
+        funding,
+        ctx.accounts.referral.as_mut(),
+        team_id,
+        sol_amount,
+        ctx.accounts.payer.key(),
+        ctx.bumps.user_position,
+        ctx.bumps.user_profile,
+    )?;
+
+    if let Some(event) = fill.breaker {
+        emit_cpi!(event);
+    }
+    if let Some(event) = fill.promo {
+        emit_cpi!(event);
+    }
+    if let Some(event) = fill.fee {
+        emit_cpi!(event);
+    }
+    emit_cpi!(fill.purchased);
 
     Ok(())
 }
 
-pub fn claim_winnings_handler(ctx: Context<ClaimWinnings>, stream_id: u64) -> Result<()> {
-    let stream = &ctx.accounts.stream;
-    let user_position = &mut ctx.accounts.user_position;
+pub fn sell_shares_handler(
+    ctx: Context<SellShares>,
+    _stream_id: u64,
+    team_id: u8,
+    shares_amount: u64,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    check_trader(
+        ctx.accounts.user.key(),
+        ctx.accounts.signer.key(),
+        ctx.accounts.session_token.as_deref(),
+        clock.unix_timestamp,
+    )?;
+
+    // Proceeds always go to the position owner, never a session key
+    let fill = execute_sell(
+        TradeAccounts {
+            stream: &mut ctx.accounts.stream,
+            user_position: &mut ctx.accounts.user_position,
+            user_profile: &mut ctx.accounts.user_profile,
+            stream_vault: ctx.accounts.stream_vault.to_account_info(),
+            vault_bump: ctx.bumps.stream_vault,
+            price_history: &ctx.accounts.price_history,
+            user: ctx.accounts.user.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+        },
+        ctx.accounts.user.to_account_info(),
+        team_id,
+        shares_amount,
+    )?;
+
+    if let Some(event) = fill.breaker {
+        emit_cpi!(event);
+    }
+    emit_cpi!(fill.sold);
+
+    Ok(())
+}
+
+pub fn set_admin_handler(ctx: Context<SetAdmin>, admin: Pubkey) -> Result<()> {
+    let clock = Clock::get()?;
 
-    require!(!stream.is_active, ErrorCode::StreamStillActive);
-    require!(stream.winning_team != 0, ErrorCode::NoWinnerDeclared);
-    require!(!user_position.has_claimed, ErrorCode::AlreadyClaimed);
     require!(
-        user_position.user == ctx.accounts.user.key(),
+        ctx.accounts.program_data.upgrade_authority_address == Some(ctx.accounts.authority.key()),
         ErrorCode::Unauthorized
     );
 
-    let user_winning_shares = if stream.winning_team == 1 {
-        user_position.team_a_shares
-    } else {
-        user_position.team_b_shares
-    };
+    let config = &mut ctx.accounts.config;
+    config.admin = admin;
+    config.bump = ctx.bumps.config;
 
-    require!(user_winning_shares > 0, ErrorCode::NoWinningShares);
+    emit_cpi!(AdminUpdated {
+        version: EVENT_VERSION,
+        admin,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
 
-    let total_winning_shares = if stream.winning_team == 1 {
-        stream.team_a_shares_sold
-    } else {
-        stream.team_b_shares_sold
-    };
+    Ok(())
+}
 
-    let payout = (stream.total_pool as u128)
-        .checked_mul(user_winning_shares as u128)
-        .ok_or(ErrorCode::MathOverflow)?
-        .checked_div(total_winning_shares as u128)
-        .ok_or(ErrorCode::MathOverflow)? as u64;
+pub fn issue_promo_credit_handler(
+    ctx: Context<IssuePromoCredit>,
+    user: Pubkey,
+    stream_id: u64,
+    amount: u64,
+) -> Result<()> {
+    let clock = Clock::get()?;
 
-    require!(payout > 0, ErrorCode::NoPayout);
+    require!(amount > 0, ErrorCode::InvalidAmount);
+    if stream_id != 0 {
+        let stream = ctx
+            .accounts
+            .stream
+            .as_ref()
+            .ok_or(ErrorCode::InvalidPromoCredit)?;
+        require!(
+            stream.authority == ctx.accounts.issuer.key(),
+            ErrorCode::Unauthorized
+        );
+        require!(stream.is_active, ErrorCode::StreamNotActive);
+    } else {
+        // A global credit's PDA is shared by every issuer, so only the admin may fund it
+        let config = ctx
+            .accounts
+            .config
+            .as_ref()
+            .ok_or(ErrorCode::InvalidPromoCredit)?;
+        require!(
+            config.admin == ctx.accounts.issuer.key(),
+            ErrorCode::Unauthorized
+        );
+    }
 
-    // Transfer winnings from vault to user using PDA seeds for signing
-    let stream_id_bytes = stream_id.to_le_bytes();
-    let seeds = &[
-        b"stream_vault".as_ref(),
-        stream_id_bytes.as_ref(),
-        &[ctx.bumps.stream_vault],
-    ];
-    let signer_seeds = &[&seeds[..]];
-
-    let transfer_ctx = CpiContext::new_with_signer(
+    let promo_credit = &ctx.accounts.promo_credit;
+    if promo_credit.user != Pubkey::default() {
+        // Top-ups come from the original issuer, who is refunded on revoke
+        require!(
+            promo_credit.issuer == ctx.accounts.issuer.key(),
+            ErrorCode::Unauthorized
+        );
+    }
+
+    let cpi_context = CpiContext::new(
         ctx.accounts.system_program.to_account_info(),
         anchor_lang::system_program::Transfer {
-            from: ctx.accounts.stream_vault.to_account_info(),
-            to: ctx.accounts.user.to_account_info(),
+            from: ctx.accounts.issuer.to_account_info(),
+            to: ctx.accounts.promo_credit.to_account_info(),
         },
-        signer_seeds,
     );
-    anchor_lang::system_program::transfer(transfer_ctx, payout)?;
+    anchor_lang::system_program::transfer(cpi_context, amount)?;
 
-    user_position.has_claimed = true;
+    let promo_credit = &mut ctx.accounts.promo_credit;
+    promo_credit.user = user;
+    promo_credit.issuer = ctx.accounts.issuer.key();
+    promo_credit.stream_id = stream_id;
+    promo_credit.balance = promo_credit
+        .balance
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    promo_credit.issued = promo_credit
+        .issued
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    promo_credit.bump = ctx.bumps.promo_credit;
 
-    emit!(WinningsClaimed {
+    emit_cpi!(PromoCreditIssued {
+        version: EVENT_VERSION,
+        user,
+        issuer: ctx.accounts.issuer.key(),
         stream_id,
-        user: ctx.accounts.user.key(),
-        winning_team: stream.winning_team,
-        shares: user_winning_shares,
-        payout,
+        amount,
+        balance: promo_credit.balance,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
     });
 
     Ok(())
 }
 
-pub fn emergency_withdraw_handler(ctx: Context<EmergencyWithdraw>, stream_id: u64) -> Result<()> {
-    let stream = &mut ctx.accounts.stream;
+pub fn revoke_promo_credit_handler(
+    ctx: Context<RevokePromoCredit>,
+    user: Pubkey,
+    stream_id: u64,
+) -> Result<()> {
+    let promo_credit = &ctx.accounts.promo_credit;
+    let clock = Clock::get()?;
 
     require!(
-        ctx.accounts.authority.key() == stream.authority,
+        promo_credit.issuer == ctx.accounts.issuer.key(),
         ErrorCode::Unauthorized
     );
-    require!(!stream.is_active, ErrorCode::StreamStillActive);
 
-    let vault_balance = ctx.accounts.stream_vault.to_account_info().lamports();
+    emit_cpi!(PromoCreditRevoked {
+        version: EVENT_VERSION,
+        user,
+        issuer: ctx.accounts.issuer.key(),
+        stream_id,
+        balance: promo_credit.balance,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
 
-    // Transfer all funds from vault to authority using PDA seeds for signing
-    let stream_id_bytes = stream_id.to_le_bytes();
-    let seeds = &[
-        b"stream_vault".as_ref(),
-        stream_id_bytes.as_ref(),
-        &[ctx.bumps.stream_vault],
-    ];
-    let signer_seeds = &[&seeds[..]];
-
-    let transfer_ctx = CpiContext::new_with_signer(
-        ctx.accounts.system_program.to_account_info(),
-        anchor_lang::system_program::Transfer {
-            from: ctx.accounts.stream_vault.to_account_info(),
-            to: ctx.accounts.authority.to_account_info(),
-        },
-        signer_seeds,
-    );
-    anchor_lang::system_program::transfer(transfer_ctx, vault_balance)?;
+    Ok(())
+}
+
+pub fn register_referrer_handler(ctx: Context<RegisterReferrer>) -> Result<()> {
+    let referral = &mut ctx.accounts.referral;
+    let clock = Clock::get()?;
+
+    referral.referrer = ctx.accounts.referrer.key();
+    referral.referred_volume = 0;
+    referral.total_earned = 0;
+    referral.total_claimed = 0;
+    referral.bump = ctx.bumps.referral;
+
+    emit_cpi!(ReferrerRegistered {
+        version: EVENT_VERSION,
+        referrer: referral.referrer,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
 
     Ok(())
-}
\ No newline at end of file
+}
+
+pub fn claim_referral_fees_handler(ctx: Context<ClaimReferralFees>) -> Result<()> {
+    let referral = &mut ctx.accounts.referral;
+    let clock = Clock::get()?;
+
+    let amount = referral
+        .total_earned
+        .checked_sub(referral.total_claimed)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(amount > 0, ErrorCode::NoReferralFees);
+
+    referral.total_claimed = referral.total_earned;
+    referral.sub_lamports(amount)?;
+    ctx.accounts.referrer.add_lamports(amount)?;
+
+    emit_cpi!(ReferralFeesClaimed {
+        version: EVENT_VERSION,
+        referrer: referral.referrer,
+        amount,
+        total_claimed: referral.total_claimed,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn create_session_handler(
+    ctx: Context<CreateSession>,
+    session_signer: Pubkey,
+    expires_at: i64,
+    spend_limit: u64,
+) -> Result<()> {
+    let clock = Clock::get()?;
+
+    require!(expires_at > clock.unix_timestamp, ErrorCode::InvalidSession);
+    require!(
+        session_signer != ctx.accounts.user.key(),
+        ErrorCode::InvalidSession
+    );
+
+    if spend_limit > 0 {
+        let cpi_context = CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            anchor_lang::system_program::Transfer {
+                from: ctx.accounts.user.to_account_info(),
+                to: ctx.accounts.session_token.to_account_info(),
+            },
+        );
+        anchor_lang::system_program::transfer(cpi_context, spend_limit)?;
+    }
+
+    let session_token = &mut ctx.accounts.session_token;
+    session_token.user = ctx.accounts.user.key();
+    session_token.session_signer = session_signer;
+    session_token.expires_at = expires_at;
+    session_token.spend_limit = spend_limit;
+    session_token.spent = 0;
+    session_token.bump = ctx.bumps.session_token;
+
+    emit_cpi!(SessionCreated {
+        version: EVENT_VERSION,
+        user: ctx.accounts.user.key(),
+        session_signer,
+        expires_at,
+        spend_limit,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn revoke_session_handler(ctx: Context<RevokeSession>) -> Result<()> {
+    let session_token = &ctx.accounts.session_token;
+    let clock = Clock::get()?;
+
+    emit_cpi!(SessionRevoked {
+        version: EVENT_VERSION,
+        user: ctx.accounts.user.key(),
+        session_signer: session_token.session_signer,
+        spent: session_token.spent,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn cash_out_handler(
+    ctx: Context<CashOut>,
+    stream_id: u64,
+    team_id: u8,
+    pct_bps: u16,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    check_trader(
+        ctx.accounts.user.key(),
+        ctx.accounts.signer.key(),
+        ctx.accounts.session_token.as_deref(),
+        clock.unix_timestamp,
+    )?;
+
+    require!(team_id <= 2, ErrorCode::InvalidTeam);
+    require!(pct_bps > 0 && pct_bps <= 10_000, ErrorCode::InvalidAmount);
+
+    let user_position = &ctx.accounts.user_position;
+    let share_of = |held: u64| -> Result<u64> {
+        Ok(((held as u128)
+            .checked_mul(pct_bps as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            / 10_000) as u64)
+    };
+    let team_a_shares = if team_id != 2 {
+        share_of(sellable_shares(user_position, 1))?
+    } else {
+        0
+    };
+    let team_b_shares = if team_id != 1 {
+        share_of(sellable_shares(user_position, 2))?
+    } else {
+        0
+    };
+    require!(
+        team_a_shares > 0 || team_b_shares > 0,
+        ErrorCode::InsufficientShares
+    );
+
+    let team_a_reserve_before = ctx.accounts.stream.team_a_reserve;
+    let team_b_reserve_before = ctx.accounts.stream.team_b_reserve;
+
+    // Each side is an ordinary sale, so proceeds, cost basis, candles and the
+    // breaker come out the same as selling the sides one at a time
+    let mut sol_out_total: u64 = 0;
+    for (team, shares_amount) in [(1u8, team_a_shares), (2u8, team_b_shares)] {
+        if shares_amount == 0 {
+            continue;
+        }
+
+        let fill = execute_sell(
+            TradeAccounts {
+                stream: &mut ctx.accounts.stream,
+                user_position: &mut ctx.accounts.user_position,
+                user_profile: &mut ctx.accounts.user_profile,
+                stream_vault: ctx.accounts.stream_vault.to_account_info(),
+                vault_bump: ctx.bumps.stream_vault,
+                price_history: &ctx.accounts.price_history,
+                user: ctx.accounts.user.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+            },
+            ctx.accounts.user.to_account_info(),
+            team,
+            shares_amount,
+        )?;
+
+        if let Some(event) = fill.breaker {
+            emit_cpi!(event);
+        }
+        sol_out_total = sol_out_total
+            .checked_add(fill.sold.sol_received)
+            .ok_or(ErrorCode::MathOverflow)?;
+        emit_cpi!(fill.sold);
+    }
+
+    // An emptied position closes and returns its rent, unless orders or
+    // reveals still settle against it or a sponsor paid for it
+    let user_position = &ctx.accounts.user_position;
+    let position_emptied = user_position.team_a_shares == 0 && user_position.team_b_shares == 0;
+    let position_closed = position_emptied && owner_can_close(user_position);
+
+    let stream = &ctx.accounts.stream;
+    emit_cpi!(CashedOut {
+        version: EVENT_VERSION,
+        stream_id,
+        trade_seq: stream.trade_seq,
+        user: ctx.accounts.user.key(),
+        team_id,
+        pct_bps,
+        team_a_shares_sold: team_a_shares,
+        team_b_shares_sold: team_b_shares,
+        sol_received: sol_out_total,
+        position_emptied,
+        position_closed,
+        team_a_reserve_before,
+        team_b_reserve_before,
+        team_a_reserve_after: stream.team_a_reserve,
+        team_b_reserve_after: stream.team_b_reserve,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    if position_closed {
+        ctx.accounts
+            .user_position
+            .close(ctx.accounts.user.to_account_info())?;
+    }
+
+    Ok(())
+}
+
+pub fn swap_sides_handler(
+    ctx: Context<SwapSides>,
+    stream_id: u64,
+    from_team: u8,
+    shares_amount: u64,
+    min_shares_out: u64,
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let user_position = &mut ctx.accounts.user_position;
+    let clock = Clock::get()?;
+    check_trader(
+        ctx.accounts.user.key(),
+        ctx.accounts.signer.key(),
+        ctx.accounts.session_token.as_deref(),
+        clock.unix_timestamp,
+    )?;
+
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(
+        clock.unix_timestamp < stream.end_time,
+        ErrorCode::StreamEnded
+    );
+    require!(from_team == 1 || from_team == 2, ErrorCode::InvalidTeam);
+    require!(shares_amount > 0, ErrorCode::InvalidAmount);
+    require!(stream.commit_secs == 0, ErrorCode::CommitRevealRequired);
+    require!(
+        stream.auction_interval_secs == 0,
+        ErrorCode::AuctionModeActive
+    );
+    check_circuit_breaker(stream, clock.slot)?;
+    require!(
+        ctx.accounts.user.key() == user_position.user,
+        ErrorCode::Unauthorized
+    );
+
+    check_referral(
+        user_position,
+        ctx.accounts.user.key(),
+        ctx.accounts
+            .referral
+            .as_ref()
+            .map(|referral| referral.referrer),
+    )?;
+
+    let to_team = 3 - from_team;
+    require!(
+        sellable_shares(user_position, from_team) >= shares_amount,
+        ErrorCode::InsufficientShares
+    );
+
+    let (reserve_from, reserve_to) = if from_team == 1 {
+        (stream.team_a_reserve, stream.team_b_reserve)
+    } else {
+        (stream.team_b_reserve, stream.team_a_reserve)
+    };
+    let team_a_reserve_before = stream.team_a_reserve;
+    let team_b_reserve_before = stream.team_b_reserve;
+    let prices_before = team_prices(team_a_reserve_before, team_b_reserve_before)?;
+
+    let (sol_value, fee, shares_out) = calculate_swap_out(stream, from_team, shares_amount)?;
+    require!(shares_out >= min_shares_out, ErrorCode::SlippageExceeded);
+    let (_, referral_fee) = calculate_fees(stream, sol_value, ctx.accounts.referral.is_some())?;
+    let net_value = sol_value - fee;
+
+    // The buy leg is a fresh bet on the other team; net invested is unchanged
+    let reserve_to_mid = reserve_to
+        .checked_sub(sol_value)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let reserve_from_mid = reserve_from
+        .checked_add(shares_amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let price_impact_bps = calculate_price_impact_bps(
+        calculate_price(reserve_to_mid, reserve_from_mid)?,
+        calculate_price(
+            reserve_to_mid
+                .checked_sub(shares_out)
+                .ok_or(ErrorCode::MathOverflow)?,
+            reserve_from_mid
+                .checked_add(net_value)
+                .ok_or(ErrorCode::MathOverflow)?,
+        )?,
+    )?;
+    check_bet_limits(
+        stream,
+        sol_value,
+        position_stake(user_position)?,
+        price_impact_bps,
+    )?;
+
+    accumulate_prices(stream, clock.unix_timestamp)?;
+
+    apply_sell(stream, from_team, shares_amount, sol_value)?;
+    let prices_mid = team_prices(stream.team_a_reserve, stream.team_b_reserve)?;
+    apply_buy(stream, to_team, net_value, shares_out)?;
+    stream.last_trade_time = clock.unix_timestamp;
+    stream.largest_bet = stream.largest_bet.max(sol_value);
+
+    let prices_after = team_prices(stream.team_a_reserve, stream.team_b_reserve)?;
+
+    let mut history = ctx.accounts.price_history.load_mut()?;
+    record_candle(
+        &mut history,
+        clock.unix_timestamp,
+        from_team,
+        sol_value,
+        prices_before,
+        prices_mid,
+    )?;
+    record_candle(
+        &mut history,
+        clock.unix_timestamp,
+        to_team,
+        net_value,
+        prices_mid,
+        prices_after,
+    )?;
+    drop(history);
+
+    // The buy leg's fee never leaves the vault; only the referrer's share does
+    if let Some(referral) = &ctx.accounts.referral {
+        if referral_fee > 0 {
+            pay_from_vault(
+                &ctx.accounts.system_program.to_account_info(),
+                &ctx.accounts.stream_vault.to_account_info(),
+                &referral.to_account_info(),
+                stream_id,
+                ctx.bumps.stream_vault,
+                referral_fee,
+            )?;
+        }
+        user_position.referrer = referral.referrer;
+    }
+    book_fees(
+        stream,
+        ctx.accounts.referral.as_deref_mut(),
+        sol_value,
+        fee,
+        referral_fee,
+    )?;
+
+    let trip = update_circuit_breaker(stream, clock.slot, prices_before, prices_after)?;
+    if let Some(event) =
+        breaker_tripped(stream, ctx.accounts.user.key(), trip, prices_after, &clock)
+    {
+        emit_cpi!(event);
+    }
+
+    if from_team == 1 {
+        user_position.team_a_shares -= shares_amount;
+        user_position.team_b_shares = user_position
+            .team_b_shares
+            .checked_add(shares_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        user_position.team_b_shares -= shares_amount;
+        user_position.team_a_shares = user_position
+            .team_a_shares
+            .checked_add(shares_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+
+    // Counted as a sell plus a buy of the same lamports
+    let user_profile = &mut ctx.accounts.user_profile;
+    user_profile.lifetime_volume = user_profile
+        .lifetime_volume
+        .checked_add(sol_value.checked_mul(2).ok_or(ErrorCode::MathOverflow)?)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    if fee > 0 {
+        emit_cpi!(TradingFeeCharged {
+            version: EVENT_VERSION,
+            stream_id,
+            trade_seq: stream.trade_seq,
+            user: ctx.accounts.user.key(),
+            fee,
+            referrer: user_position.referrer,
+            referral_fee,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+    }
+
+    emit_cpi!(SidesSwapped {
+        version: EVENT_VERSION,
+        stream_id,
+        trade_seq: stream.trade_seq,
+        user: ctx.accounts.user.key(),
+        from_team,
+        to_team,
+        shares_in: shares_amount,
+        sol_value,
+        shares_out,
+        team_a_price_before: prices_before.0,
+        team_b_price_before: prices_before.1,
+        team_a_price_after: prices_after.0,
+        team_b_price_after: prices_after.1,
+        team_a_reserve_before,
+        team_b_reserve_before,
+        team_a_reserve_after: stream.team_a_reserve,
+        team_b_reserve_after: stream.team_b_reserve,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn set_bet_limits_handler(
+    ctx: Context<SetBetLimits>,
+    stream_id: u64,
+    min_bet: u64,
+    max_bet: u64,
+    max_position: u64,
+    max_price_impact_bps: u16,
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.authority.key() == stream.authority,
+        ErrorCode::Unauthorized
+    );
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(max_bet == 0 || min_bet <= max_bet, ErrorCode::InvalidLimits);
+    require!(
+        max_position == 0 || min_bet <= max_position,
+        ErrorCode::InvalidLimits
+    );
+    require!(max_price_impact_bps <= 10_000, ErrorCode::InvalidLimits);
+
+    stream.min_bet = min_bet;
+    stream.max_bet = max_bet;
+    stream.max_position = max_position;
+    stream.max_price_impact_bps = max_price_impact_bps;
+
+    emit_cpi!(BetLimitsUpdated {
+        version: EVENT_VERSION,
+        stream_id,
+        min_bet,
+        max_bet,
+        max_position,
+        max_price_impact_bps,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn migrate_stream_handler(ctx: Context<MigrateStream>, stream_id: u64) -> Result<()> {
+    let stream_info = ctx.accounts.stream.to_account_info();
+    let clock = Clock::get()?;
+
+    require!(stream_info.owner == &crate::ID, ErrorCode::AlreadyMigrated);
+    let old_len = stream_info.data_len();
+    grow_account(
+        &stream_info,
+        &ctx.accounts.payer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+        8 + Stream::INIT_SPACE,
+    )?;
+
+    let mut stream = Stream::try_deserialize(&mut &stream_info.try_borrow_data()?[..])?;
+    require!(
+        stream.layout_version < STREAM_LAYOUT_VERSION,
+        ErrorCode::AlreadyMigrated
+    );
+    stream.layout_version = STREAM_LAYOUT_VERSION;
+    stream.streamer = stream.authority;
+    stream.last_price_update = clock.unix_timestamp;
+    stream.try_serialize(&mut &mut stream_info.try_borrow_mut_data()?[..])?;
+
+    let mut price_history = ctx.accounts.price_history.load_init()?;
+    price_history.stream_id = stream_id;
+    price_history.interval_secs = PRICE_HISTORY_INTERVAL_SECS;
+    drop(price_history);
+
+    emit_cpi!(AccountMigrated {
+        version: EVENT_VERSION,
+        account: stream_info.key(),
+        old_len: old_len as u64,
+        new_len: stream_info.data_len() as u64,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn migrate_position_handler(
+    ctx: Context<MigratePosition>,
+    _stream_id: u64,
+    _user: Pubkey,
+) -> Result<()> {
+    let position_info = ctx.accounts.user_position.to_account_info();
+    let clock = Clock::get()?;
+
+    require!(
+        position_info.owner == &crate::ID,
+        ErrorCode::AlreadyMigrated
+    );
+    let old_len = position_info.data_len();
+    grow_account(
+        &position_info,
+        &ctx.accounts.payer.to_account_info(),
+        &ctx.accounts.system_program.to_account_info(),
+        8 + UserPosition::INIT_SPACE,
+    )?;
+
+    // Confirms the discriminator; the appended fields already read as zero
+    UserPosition::try_deserialize(&mut &position_info.try_borrow_data()?[..])?;
+
+    emit_cpi!(AccountMigrated {
+        version: EVENT_VERSION,
+        account: position_info.key(),
+        old_len: old_len as u64,
+        new_len: position_info.data_len() as u64,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn set_fees_handler(
+    ctx: Context<SetFees>,
+    stream_id: u64,
+    fee_bps: u16,
+    referral_share_bps: u16,
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.authority.key() == stream.authority,
+        ErrorCode::Unauthorized
+    );
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(fee_bps <= MAX_FEE_BPS, ErrorCode::InvalidFees);
+    require!(referral_share_bps <= 10_000, ErrorCode::InvalidFees);
+
+    stream.fee_bps = fee_bps;
+    stream.referral_share_bps = referral_share_bps;
+
+    emit_cpi!(FeesUpdated {
+        version: EVENT_VERSION,
+        stream_id,
+        fee_bps,
+        referral_share_bps,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn set_streamer_handler(
+    ctx: Context<SetStreamer>,
+    stream_id: u64,
+    streamer: Pubkey,
+    streamer_cut_bps: u16,
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.authority.key() == stream.authority,
+        ErrorCode::Unauthorized
+    );
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(streamer != Pubkey::default(), ErrorCode::InvalidStreamer);
+    require!(streamer_cut_bps <= 10_000, ErrorCode::InvalidFees);
+
+    stream.streamer = streamer;
+    stream.streamer_cut_bps = streamer_cut_bps;
+
+    emit_cpi!(StreamerUpdated {
+        version: EVENT_VERSION,
+        stream_id,
+        streamer,
+        streamer_cut_bps,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn tip_streamer_handler(ctx: Context<TipStreamer>, stream_id: u64, amount: u64) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.streamer.key() == stream.streamer,
+        ErrorCode::InvalidStreamer
+    );
+    require!(amount > 0, ErrorCode::InvalidAmount);
+
+    let cpi_context = CpiContext::new(
+        ctx.accounts.system_program.to_account_info(),
+        anchor_lang::system_program::Transfer {
+            from: ctx.accounts.tipper.to_account_info(),
+            to: ctx.accounts.streamer.to_account_info(),
+        },
+    );
+    anchor_lang::system_program::transfer(cpi_context, amount)?;
+
+    stream.streamer_tips = stream
+        .streamer_tips
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    emit_cpi!(StreamerTipped {
+        version: EVENT_VERSION,
+        stream_id,
+        tipper: ctx.accounts.tipper.key(),
+        streamer: stream.streamer,
+        amount,
+        total_tips: stream.streamer_tips,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn pay_streamer_handler(ctx: Context<PayStreamer>, stream_id: u64) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.streamer.key() == stream.streamer,
+        ErrorCode::InvalidStreamer
+    );
+    require!(!stream.is_active, ErrorCode::StreamStillActive);
+    require!(!stream.streamer_paid, ErrorCode::StreamerAlreadyPaid);
+
+    let amount = stream.streamer_owed;
+    require!(amount > 0, ErrorCode::NoPayout);
+
+    stream.streamer_paid = true;
+    stream.streamer_owed = 0;
+
+    pay_from_vault(
+        &ctx.accounts.system_program.to_account_info(),
+        &ctx.accounts.stream_vault.to_account_info(),
+        &ctx.accounts.streamer.to_account_info(),
+        stream_id,
+        ctx.bumps.stream_vault,
+        amount,
+    )?;
+
+    emit_cpi!(StreamerPaid {
+        version: EVENT_VERSION,
+        stream_id,
+        streamer: stream.streamer,
+        amount,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn withdraw_fees_handler(ctx: Context<WithdrawFees>, stream_id: u64) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.authority.key() == stream.authority,
+        ErrorCode::Unauthorized
+    );
+    require!(
+        ctx.accounts.streamer.key() == stream.streamer,
+        ErrorCode::InvalidStreamer
+    );
+    require!(!stream.is_active, ErrorCode::StreamStillActive);
+
+    // The streamer's booked cut is paid first so the authority can never take it
+    let streamer_amount = stream.streamer_owed;
+    let amount = stream.fees_collected;
+    require!(amount > 0, ErrorCode::NoPayout);
+
+    if streamer_amount > 0 {
+        stream.streamer_paid = true;
+        stream.streamer_owed = 0;
+    }
+    stream.fees_collected = 0;
+
+    for (to, lamports) in [
+        (ctx.accounts.streamer.to_account_info(), streamer_amount),
+        (ctx.accounts.authority.to_account_info(), amount),
+    ] {
+        if lamports == 0 {
+            continue;
+        }
+        pay_from_vault(
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.stream_vault.to_account_info(),
+            &to,
+            stream_id,
+            ctx.bumps.stream_vault,
+            lamports,
+        )?;
+    }
+
+    if streamer_amount > 0 {
+        emit_cpi!(StreamerPaid {
+            version: EVENT_VERSION,
+            stream_id,
+            streamer: stream.streamer,
+            amount: streamer_amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+    }
+
+    emit_cpi!(FeesWithdrawn {
+        version: EVENT_VERSION,
+        stream_id,
+        authority: stream.authority,
+        amount,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn set_circuit_breaker_handler(
+    ctx: Context<SetCircuitBreaker>,
+    stream_id: u64,
+    threshold_bps: u16,
+    window_slots: u64,
+    cooldown_slots: u64,
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.authority.key() == stream.authority,
+        ErrorCode::Unauthorized
+    );
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(
+        threshold_bps == 0 || (window_slots > 0 && cooldown_slots > 0),
+        ErrorCode::InvalidCircuitBreaker
+    );
+
+    // Reconfiguring also lifts any halt and opens a fresh window, so this
+    // doubles as the authority's resume switch after reviewing a trip
+    stream.breaker_threshold_bps = threshold_bps;
+    stream.breaker_window_slots = window_slots;
+    stream.breaker_cooldown_slots = cooldown_slots;
+    stream.breaker_window_start_slot = 0;
+    stream.breaker_ref_price_a = 0;
+    stream.breaker_ref_price_b = 0;
+    stream.halted_until_slot = 0;
+
+    emit_cpi!(CircuitBreakerUpdated {
+        version: EVENT_VERSION,
+        stream_id,
+        threshold_bps,
+        window_slots,
+        cooldown_slots,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn set_commit_reveal_handler(
+    ctx: Context<SetCommitReveal>,
+    stream_id: u64,
+    commit_secs: i64,
+    reveal_secs: i64,
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.authority.key() == stream.authority,
+        ErrorCode::Unauthorized
+    );
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(
+        (commit_secs == 0 && reveal_secs == 0) || (commit_secs > 0 && reveal_secs > 0),
+        ErrorCode::InvalidCommitReveal
+    );
+    require!(
+        commit_secs == 0 || stream.auction_interval_secs == 0,
+        ErrorCode::TradingModeConflict
+    );
+
+    // Commitments made under the old schedule keep their own reveal window
+    stream.commit_secs = commit_secs;
+    stream.reveal_secs = reveal_secs;
+    stream.commit_reveal_start = clock.unix_timestamp;
+
+    emit_cpi!(CommitRevealUpdated {
+        version: EVENT_VERSION,
+        stream_id,
+        commit_secs,
+        reveal_secs,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn commit_bet_handler(
+    ctx: Context<CommitBet>,
+    stream_id: u64,
+    reveal_end: i64,
+    hash: [u8; 32],
+    escrow: u64,
+) -> Result<()> {
+    let stream = &ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(escrow > 0, ErrorCode::InvalidAmount);
+    check_circuit_breaker(stream, clock.slot)?;
+
+    let (reveal_start, window_end) = commit_window(stream, clock.unix_timestamp)?;
+    require!(window_end == reveal_end, ErrorCode::NotInCommitPhase);
+    require!(reveal_end <= stream.end_time, ErrorCode::StreamEnded);
+
+    let cpi_context = CpiContext::new(
+        ctx.accounts.system_program.to_account_info(),
+        anchor_lang::system_program::Transfer {
+            from: ctx.accounts.user.to_account_info(),
+            to: ctx.accounts.commitment.to_account_info(),
+        },
+    );
+    anchor_lang::system_program::transfer(cpi_context, escrow)?;
+
+    let commitment = &mut ctx.accounts.commitment;
+    commitment.user = ctx.accounts.user.key();
+    commitment.stream_id = stream_id;
+    commitment.hash = hash;
+    commitment.escrow = escrow;
+    commitment.reveal_start = reveal_start;
+    commitment.reveal_end = reveal_end;
+    commitment.team_id = 0;
+    commitment.bump = ctx.bumps.commitment;
+
+    emit_cpi!(BetCommitted {
+        version: EVENT_VERSION,
+        stream_id,
+        user: ctx.accounts.user.key(),
+        hash,
+        escrow,
+        reveal_start,
+        reveal_end,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn reveal_bet_handler(
+    ctx: Context<RevealBet>,
+    stream_id: u64,
+    reveal_end: i64,
+    team_id: u8,
+    amount: u64,
+    salt: [u8; 32],
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let commitment = &mut ctx.accounts.commitment;
+    let commit_batch = &mut ctx.accounts.commit_batch;
+    let user_position = &mut ctx.accounts.user_position;
+    let clock = Clock::get()?;
+
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(
+        clock.unix_timestamp >= commitment.reveal_start
+            && clock.unix_timestamp < commitment.reveal_end,
+        ErrorCode::NotInRevealPhase
+    );
+    require!(commitment.team_id == 0, ErrorCode::AlreadyRevealed);
+    require!(team_id == 1 || team_id == 2, ErrorCode::InvalidTeam);
+    require!(
+        amount > 0 && amount <= commitment.escrow,
+        ErrorCode::InvalidAmount
+    );
+    require!(
+        commitment_hash(stream_id, &commitment.user, team_id, amount, &salt) == commitment.hash,
+        ErrorCode::CommitmentMismatch
+    );
+
+    if user_position.user == Pubkey::default() {
+        open_position(
+            stream,
+            user_position,
+            &mut ctx.accounts.user_profile,
+            ctx.accounts.user.key(),
+            ctx.accounts.user.key(),
+            ctx.bumps.user_position,
+            ctx.bumps.user_profile,
+        )?;
+    }
+
+    // Bet and position limits apply to each reveal, counting reveals still
+    // waiting to settle. The fill price is unknown until the batch clears, so
+    // price impact is checked there instead
+    let position_invested = position_stake(user_position)?
+        .checked_add(user_position.pending_invested)
+        .and_then(|invested| invested.checked_add(amount))
+        .ok_or(ErrorCode::MathOverflow)?;
+    check_bet_limits(stream, amount, position_invested, 0)?;
+    user_position.pending_invested = user_position
+        .pending_invested
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    // Revealed lamports join the vault now, the rest of the escrow goes back
+    let refunded = commitment.escrow - amount;
+    commitment.sub_lamports(commitment.escrow)?;
+    ctx.accounts.stream_vault.add_lamports(amount)?;
+    ctx.accounts.user.add_lamports(refunded)?;
+
+    commitment.escrow = amount;
+    commitment.team_id = team_id;
+
+    if commit_batch.reveal_end == 0 {
+        commit_batch.stream_id = stream_id;
+        commit_batch.reveal_end = reveal_end;
+        commit_batch.team_a_sol = 0;
+        commit_batch.team_b_sol = 0;
+        commit_batch.team_a_shares = 0;
+        commit_batch.team_b_shares = 0;
+        commit_batch.reveals = 0;
+        commit_batch.cleared = false;
+        commit_batch.bump = ctx.bumps.commit_batch;
+        commit_batch.fill_bps = 0;
+    }
+    if team_id == 1 {
+        commit_batch.team_a_sol = commit_batch
+            .team_a_sol
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        commit_batch.team_b_sol = commit_batch
+            .team_b_sol
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    commit_batch.reveals = commit_batch
+        .reveals
+        .checked_add(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    emit_cpi!(BetRevealed {
+        version: EVENT_VERSION,
+        stream_id,
+        user: ctx.accounts.user.key(),
+        reveal_end,
+        team_id,
+        amount,
+        refunded,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn clear_commit_batch_handler(
+    ctx: Context<ClearCommitBatch>,
+    stream_id: u64,
+    reveal_end: i64,
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let commit_batch = &mut ctx.accounts.commit_batch;
+    let clock = Clock::get()?;
+
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(
+        clock.unix_timestamp >= reveal_end,
+        ErrorCode::RevealWindowOpen
+    );
+    require!(!commit_batch.cleared, ErrorCode::BatchAlreadyCleared);
+    check_circuit_breaker(stream, clock.slot)?;
+
+    accumulate_prices(stream, clock.unix_timestamp)?;
+
+    let team_a_reserve_before = stream.team_a_reserve;
+    let team_b_reserve_before = stream.team_b_reserve;
+
+    // Both teams' reveals cross as one trade on the curve, so everyone on a
+    // side pays the same price. A batch that would move the price past the
+    // impact limit fills the same share of every reveal; settle returns the rest
+    let buy_sol = (commit_batch.team_a_sol, commit_batch.team_b_sol);
+    let fill_bps = fill_within_impact(stream.max_price_impact_bps, |fill_bps| {
+        let fill = clear_batch_flow(stream, buy_sol, (0, 0), fill_bps)?;
+        reserves_impact_bps(
+            (team_a_reserve_before, team_b_reserve_before),
+            (fill.team_a_reserve_after, fill.team_b_reserve_after),
+        )
+    })?;
+    let fill = clear_batch_flow(stream, buy_sol, (0, 0), fill_bps)?;
+    apply_batch_fill(stream, &fill)?;
+
+    commit_batch.team_a_shares = fill.team_a_buy_fill;
+    commit_batch.team_b_shares = fill.team_b_buy_fill;
+    commit_batch.fill_bps = fill_bps;
+
+    let prices_before = team_prices(team_a_reserve_before, team_b_reserve_before)?;
+    let prices_after = team_prices(stream.team_a_reserve, stream.team_b_reserve)?;
+    for (team_id, volume) in fill.volumes() {
+        if volume > 0 {
+            record_candle(
+                &mut *ctx.accounts.price_history.load_mut()?,
+                clock.unix_timestamp,
+                team_id,
+                volume,
+                prices_before,
+                prices_after,
+            )?;
+        }
+    }
+
+    stream.last_trade_time = clock.unix_timestamp;
+    commit_batch.cleared = true;
+
+    let trip = update_circuit_breaker(stream, clock.slot, prices_before, prices_after)?;
+    if let Some(event) = breaker_tripped(
+        stream,
+        ctx.accounts.keeper.key(),
+        trip,
+        prices_after,
+        &clock,
+    ) {
+        emit_cpi!(event);
+    }
+
+    emit_cpi!(CommitBatchCleared {
+        version: EVENT_VERSION,
+        stream_id,
+        reveal_end,
+        trade_seq: stream.trade_seq,
+        reveals: commit_batch.reveals,
+        team_a_sol: commit_batch.team_a_sol,
+        team_b_sol: commit_batch.team_b_sol,
+        team_a_shares: commit_batch.team_a_shares,
+        team_b_shares: commit_batch.team_b_shares,
+        team_a_reserve_before,
+        team_b_reserve_before,
+        team_a_reserve_after: stream.team_a_reserve,
+        team_b_reserve_after: stream.team_b_reserve,
+        fill_bps,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn settle_commitment_handler(
+    ctx: Context<SettleCommitment>,
+    stream_id: u64,
+    reveal_end: i64,
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let commit_batch = &ctx.accounts.commit_batch;
+    let commitment = &ctx.accounts.commitment;
+    let user_position = &mut ctx.accounts.user_position;
+    let user_profile = &mut ctx.accounts.user_profile;
+    let clock = Clock::get()?;
+
+    require!(commitment.team_id != 0, ErrorCode::CommitmentNotRevealed);
+    require!(commit_batch.cleared, ErrorCode::BatchNotCleared);
+
+    // Pro-rata share of the side's fill; rounding dust stays unowned in the pool
+    let team_id = commitment.team_id;
+    let sol_amount = commitment.escrow;
+    let (team_sol, team_shares) = if team_id == 1 {
+        (commit_batch.team_a_sol, commit_batch.team_a_shares)
+    } else {
+        (commit_batch.team_b_sol, commit_batch.team_b_shares)
+    };
+    let shares_out = (team_shares as u128)
+        .checked_mul(sol_amount as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(team_sol as u128)
+        .ok_or(ErrorCode::MathOverflow)? as u64;
+    // Rounded so the returns never add up to more than the batch left unfilled
+    let returned = scale_bps(sol_amount, 10_000 - commit_batch.fill_bps);
+    let sol_spent = sol_amount - returned;
+
+    if user_position.user == Pubkey::default() {
+        open_position(
+            stream,
+            user_position,
+            user_profile,
+            ctx.accounts.user.key(),
+            ctx.accounts.user.key(),
+            ctx.bumps.user_position,
+            ctx.bumps.user_profile,
+        )?;
+    }
+
+    if team_id == 1 {
+        user_position.team_a_shares = user_position
+            .team_a_shares
+            .checked_add(shares_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        user_position.team_b_shares = user_position
+            .team_b_shares
+            .checked_add(shares_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    user_position.total_invested = user_position
+        .total_invested
+        .checked_add(sol_spent)
+        .ok_or(ErrorCode::MathOverflow)?;
+    // Reveals from before pending amounts were tracked were never counted
+    user_position.pending_invested = user_position.pending_invested.saturating_sub(sol_amount);
+    user_profile.lifetime_volume = user_profile
+        .lifetime_volume
+        .checked_add(sol_spent)
+        .ok_or(ErrorCode::MathOverflow)?;
+    stream.largest_bet = stream.largest_bet.max(sol_spent);
+
+    if returned > 0 {
+        pay_from_vault(
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.stream_vault.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            stream_id,
+            ctx.bumps.stream_vault,
+            returned,
+        )?;
+    }
+
+    emit_cpi!(CommitmentSettled {
+        version: EVENT_VERSION,
+        stream_id,
+        user: ctx.accounts.user.key(),
+        reveal_end,
+        team_id,
+        sol_spent,
+        shares_received: shares_out,
+        returned,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn cancel_commitment_handler(
+    ctx: Context<CancelCommitment>,
+    stream_id: u64,
+    reveal_end: i64,
+) -> Result<()> {
+    let commitment = &ctx.accounts.commitment;
+    let clock = Clock::get()?;
+
+    // Unrevealed escrow is still on the commitment and returns when it closes.
+    // Revealed lamports sit in the vault and come back if the stream ended, or
+    // a whole commit-reveal period passed, before anyone cleared the batch
+    let refunded = if commitment.team_id == 0 {
+        require!(
+            clock.unix_timestamp >= commitment.reveal_end,
+            ErrorCode::RevealWindowOpen
+        );
+        commitment.escrow
+    } else {
+        let commit_batch = ctx
+            .accounts
+            .commit_batch
+            .as_ref()
+            .ok_or(ErrorCode::BatchNotCleared)?;
+        let stream = &ctx.accounts.stream;
+        let clear_deadline = commitment
+            .reveal_end
+            .checked_add(stream.commit_secs)
+            .and_then(|deadline| deadline.checked_add(stream.reveal_secs))
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(!commit_batch.cleared, ErrorCode::BatchAlreadyCleared);
+        require!(
+            !stream.is_active || clock.unix_timestamp >= clear_deadline,
+            ErrorCode::StreamStillActive
+        );
+
+        let user_position = ctx
+            .accounts
+            .user_position
+            .as_mut()
+            .ok_or(ErrorCode::InvalidPosition)?;
+        user_position.pending_invested = user_position
+            .pending_invested
+            .saturating_sub(commitment.escrow);
+
+        pay_from_vault(
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.stream_vault.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            stream_id,
+            ctx.bumps.stream_vault,
+            commitment.escrow,
+        )?;
+        commitment.escrow
+    };
+
+    emit_cpi!(CommitmentCancelled {
+        version: EVENT_VERSION,
+        stream_id,
+        user: ctx.accounts.user.key(),
+        reveal_end,
+        refunded,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn set_auction_mode_handler(
+    ctx: Context<SetAuctionMode>,
+    stream_id: u64,
+    interval_secs: i64,
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.authority.key() == stream.authority,
+        ErrorCode::Unauthorized
+    );
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(interval_secs >= 0, ErrorCode::InvalidAmount);
+    require!(
+        interval_secs == 0 || stream.commit_secs == 0,
+        ErrorCode::TradingModeConflict
+    );
+
+    // Orders keep the batch_end they were placed under and still clear
+    stream.auction_interval_secs = interval_secs;
+    stream.auction_start = clock.unix_timestamp;
+
+    emit_cpi!(AuctionModeUpdated {
+        version: EVENT_VERSION,
+        stream_id,
+        interval_secs,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn submit_order_handler(
+    ctx: Context<SubmitOrder>,
+    stream_id: u64,
+    batch_end: i64,
+    team_id: u8,
+    is_buy: bool,
+    amount: u64,
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let order_batch = &mut ctx.accounts.order_batch;
+    let user_position = &mut ctx.accounts.user_position;
+    let clock = Clock::get()?;
+    check_trader(
+        ctx.accounts.user.key(),
+        ctx.accounts.signer.key(),
+        ctx.accounts.session_token.as_deref(),
+        clock.unix_timestamp,
+    )?;
+
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(team_id == 1 || team_id == 2, ErrorCode::InvalidTeam);
+    require!(amount > 0, ErrorCode::InvalidAmount);
+    check_circuit_breaker(stream, clock.slot)?;
+    require!(
+        auction_batch_end(stream, clock.unix_timestamp)? == batch_end,
+        ErrorCode::InvalidBatch
+    );
+    require!(batch_end <= stream.end_time, ErrorCode::StreamEnded);
+
+    if user_position.user == Pubkey::default() {
+        open_position(
+            stream,
+            user_position,
+            &mut ctx.accounts.user_profile,
+            ctx.accounts.user.key(),
+            ctx.accounts.signer.key(),
+            ctx.bumps.user_position,
+            ctx.bumps.user_profile,
+        )?;
+    }
+
+    // Buys escrow lamports in the vault, sells escrow shares off the position
+    if is_buy {
+        let position_invested = position_stake(user_position)?
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        check_bet_limits(stream, amount, position_invested, 0)?;
+
+        pay_from_trader(
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            ctx.accounts.session_token.as_mut(),
+            &ctx.accounts.stream_vault.to_account_info(),
+            amount,
+        )?;
+    } else {
+        require!(
+            sellable_shares(user_position, team_id) >= amount,
+            ErrorCode::InsufficientShares
+        );
+        if team_id == 1 {
+            user_position.team_a_shares -= amount;
+        } else {
+            user_position.team_b_shares -= amount;
+        }
+    }
+    user_position.open_orders = user_position
+        .open_orders
+        .checked_add(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    if order_batch.batch_end == 0 {
+        order_batch.stream_id = stream_id;
+        order_batch.batch_end = batch_end;
+        order_batch.team_a_buy_sol = 0;
+        order_batch.team_a_sell_shares = 0;
+        order_batch.team_b_buy_sol = 0;
+        order_batch.team_b_sell_shares = 0;
+        order_batch.team_a_buy_fill = 0;
+        order_batch.team_a_sell_fill = 0;
+        order_batch.team_b_buy_fill = 0;
+        order_batch.team_b_sell_fill = 0;
+        order_batch.orders = 0;
+        order_batch.cleared = false;
+        order_batch.bump = ctx.bumps.order_batch;
+        order_batch.fill_bps = 0;
+    }
+    let side = match (team_id, is_buy) {
+        (1, true) => &mut order_batch.team_a_buy_sol,
+        (1, false) => &mut order_batch.team_a_sell_shares,
+        (_, true) => &mut order_batch.team_b_buy_sol,
+        (_, false) => &mut order_batch.team_b_sell_shares,
+    };
+    *side = side.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+    order_batch.orders = order_batch
+        .orders
+        .checked_add(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    let order = &mut ctx.accounts.order;
+    order.user = ctx.accounts.user.key();
+    order.stream_id = stream_id;
+    order.batch_end = batch_end;
+    order.team_id = team_id;
+    order.is_buy = is_buy;
+    order.amount = amount;
+    order.bump = ctx.bumps.order;
+
+    emit_cpi!(OrderSubmitted {
+        version: EVENT_VERSION,
+        stream_id,
+        user: ctx.accounts.user.key(),
+        batch_end,
+        team_id,
+        is_buy,
+        amount,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn clear_batch_handler(ctx: Context<ClearBatch>, stream_id: u64, batch_end: i64) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let order_batch = &mut ctx.accounts.order_batch;
+    let clock = Clock::get()?;
+
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(clock.unix_timestamp >= batch_end, ErrorCode::BatchStillOpen);
+    require!(!order_batch.cleared, ErrorCode::BatchAlreadyCleared);
+    check_circuit_breaker(stream, clock.slot)?;
+
+    accumulate_prices(stream, clock.unix_timestamp)?;
+
+    let team_a_reserve_before = stream.team_a_reserve;
+    let team_b_reserve_before = stream.team_b_reserve;
+    let prices_before = team_prices(team_a_reserve_before, team_b_reserve_before)?;
+
+    // A batch that would move the price past the impact limit fills the same
+    // share of every order instead of failing; settle returns the rest
+    let buy_sol = (order_batch.team_a_buy_sol, order_batch.team_b_buy_sol);
+    let sell_shares = (
+        order_batch.team_a_sell_shares,
+        order_batch.team_b_sell_shares,
+    );
+    let fill_bps = fill_within_impact(stream.max_price_impact_bps, |fill_bps| {
+        let fill = clear_batch_flow(stream, buy_sol, sell_shares, fill_bps)?;
+        reserves_impact_bps(
+            (team_a_reserve_before, team_b_reserve_before),
+            (fill.team_a_reserve_after, fill.team_b_reserve_after),
+        )
+    })?;
+    let fill = clear_batch_flow(stream, buy_sol, sell_shares, fill_bps)?;
+
+    apply_batch_fill(stream, &fill)?;
+
+    order_batch.team_a_buy_fill = fill.team_a_buy_fill;
+    order_batch.team_a_sell_fill = fill.team_a_sell_fill;
+    order_batch.team_b_buy_fill = fill.team_b_buy_fill;
+    order_batch.team_b_sell_fill = fill.team_b_sell_fill;
+    order_batch.fill_bps = fill_bps;
+
+    let prices_after = team_prices(stream.team_a_reserve, stream.team_b_reserve)?;
+    for (team_id, volume) in fill.volumes() {
+        if volume > 0 {
+            record_candle(
+                &mut *ctx.accounts.price_history.load_mut()?,
+                clock.unix_timestamp,
+                team_id,
+                volume,
+                prices_before,
+                prices_after,
+            )?;
+        }
+    }
+
+    stream.last_trade_time = clock.unix_timestamp;
+    order_batch.cleared = true;
+
+    let trip = update_circuit_breaker(stream, clock.slot, prices_before, prices_after)?;
+    if let Some(event) = breaker_tripped(
+        stream,
+        ctx.accounts.keeper.key(),
+        trip,
+        prices_after,
+        &clock,
+    ) {
+        emit_cpi!(event);
+    }
+
+    emit_cpi!(OrderBatchCleared {
+        version: EVENT_VERSION,
+        stream_id,
+        batch_end,
+        trade_seq: stream.trade_seq,
+        orders: order_batch.orders,
+        team_a_buy_sol: order_batch.team_a_buy_sol,
+        team_a_sell_shares: order_batch.team_a_sell_shares,
+        team_a_buy_fill: order_batch.team_a_buy_fill,
+        team_a_sell_fill: order_batch.team_a_sell_fill,
+        team_b_buy_sol: order_batch.team_b_buy_sol,
+        team_b_sell_shares: order_batch.team_b_sell_shares,
+        team_b_buy_fill: order_batch.team_b_buy_fill,
+        team_b_sell_fill: order_batch.team_b_sell_fill,
+        team_a_reserve_before,
+        team_b_reserve_before,
+        team_a_reserve_after: stream.team_a_reserve,
+        team_b_reserve_after: stream.team_b_reserve,
+        fill_bps,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn settle_order_handler(
+    ctx: Context<SettleOrder>,
+    stream_id: u64,
+    batch_end: i64,
+) -> Result<()> {
+    let order_batch = &ctx.accounts.order_batch;
+    let order = &ctx.accounts.order;
+    let user_position = &mut ctx.accounts.user_position;
+    let user_profile = &mut ctx.accounts.user_profile;
+    let clock = Clock::get()?;
+
+    require!(order_batch.cleared, ErrorCode::BatchNotCleared);
+
+    // Pro-rata share of the side's fill; rounding dust stays in the vault
+    let (side_total, side_fill) = match (order.team_id, order.is_buy) {
+        (1, true) => (order_batch.team_a_buy_sol, order_batch.team_a_buy_fill),
+        (1, false) => (order_batch.team_a_sell_shares, order_batch.team_a_sell_fill),
+        (_, true) => (order_batch.team_b_buy_sol, order_batch.team_b_buy_fill),
+        (_, false) => (order_batch.team_b_sell_shares, order_batch.team_b_sell_fill),
+    };
+    let filled = (side_fill as u128)
+        .checked_mul(order.amount as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(side_total as u128)
+        .ok_or(ErrorCode::MathOverflow)? as u64;
+    // Rounded so the returns never add up to more than the batch left unfilled
+    let returned = scale_bps(order.amount, 10_000 - order_batch.fill_bps);
+    let used = order.amount - returned;
+
+    if order.is_buy {
+        if order.team_id == 1 {
+            user_position.team_a_shares = user_position
+                .team_a_shares
+                .checked_add(filled)
+                .ok_or(ErrorCode::MathOverflow)?;
+        } else {
+            user_position.team_b_shares = user_position
+                .team_b_shares
+                .checked_add(filled)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+        user_position.total_invested = user_position
+            .total_invested
+            .checked_add(used)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let stream = &mut ctx.accounts.stream;
+        stream.largest_bet = stream.largest_bet.max(used);
+
+        if returned > 0 {
+            pay_from_vault(
+                &ctx.accounts.system_program.to_account_info(),
+                &ctx.accounts.stream_vault.to_account_info(),
+                &ctx.accounts.user.to_account_info(),
+                stream_id,
+                ctx.bumps.stream_vault,
+                returned,
+            )?;
+        }
+    } else {
+        pay_from_vault(
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.stream_vault.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            stream_id,
+            ctx.bumps.stream_vault,
+            filled,
+        )?;
+
+        if order.team_id == 1 {
+            user_position.team_a_shares = user_position
+                .team_a_shares
+                .checked_add(returned)
+                .ok_or(ErrorCode::MathOverflow)?;
+        } else {
+            user_position.team_b_shares = user_position
+                .team_b_shares
+                .checked_add(returned)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+        reduce_cost_basis(user_position, filled);
+    }
+    user_position.open_orders = user_position
+        .open_orders
+        .checked_sub(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    let volume = if order.is_buy { used } else { filled };
+    user_profile.lifetime_volume = user_profile
+        .lifetime_volume
+        .checked_add(volume)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    emit_cpi!(OrderSettled {
+        version: EVENT_VERSION,
+        stream_id,
+        user: order.user,
+        batch_end,
+        team_id: order.team_id,
+        is_buy: order.is_buy,
+        amount: order.amount,
+        filled,
+        returned,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn cancel_order_handler(
+    ctx: Context<CancelOrder>,
+    stream_id: u64,
+    batch_end: i64,
+) -> Result<()> {
+    let order_batch = &mut ctx.accounts.order_batch;
+    let order = &ctx.accounts.order;
+    let user_position = &mut ctx.accounts.user_position;
+    let clock = Clock::get()?;
+    check_trader(
+        ctx.accounts.user.key(),
+        ctx.accounts.signer.key(),
+        ctx.accounts.session_token.as_deref(),
+        clock.unix_timestamp,
+    )?;
+
+    // Orders can be pulled while their batch is open, or refunded once the
+    // stream ends or a whole interval passes without anyone clearing the batch
+    let stream = &ctx.accounts.stream;
+    let clear_deadline = batch_end
+        .checked_add(stream.auction_interval_secs)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(!order_batch.cleared, ErrorCode::BatchAlreadyCleared);
+    require!(
+        clock.unix_timestamp < batch_end
+            || clock.unix_timestamp >= clear_deadline
+            || !stream.is_active,
+        ErrorCode::BatchClosed
+    );
+
+    let side = match (order.team_id, order.is_buy) {
+        (1, true) => &mut order_batch.team_a_buy_sol,
+        (1, false) => &mut order_batch.team_a_sell_shares,
+        (_, true) => &mut order_batch.team_b_buy_sol,
+        (_, false) => &mut order_batch.team_b_sell_shares,
+    };
+    *side = side
+        .checked_sub(order.amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    order_batch.orders = order_batch.orders.saturating_sub(1);
+
+    if order.is_buy {
+        pay_from_vault(
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.stream_vault.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            stream_id,
+            ctx.bumps.stream_vault,
+            order.amount,
+        )?;
+    } else if order.team_id == 1 {
+        user_position.team_a_shares = user_position
+            .team_a_shares
+            .checked_add(order.amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        user_position.team_b_shares = user_position
+            .team_b_shares
+            .checked_add(order.amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    user_position.open_orders = user_position
+        .open_orders
+        .checked_sub(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    emit_cpi!(OrderCancelled {
+        version: EVENT_VERSION,
+        stream_id,
+        user: ctx.accounts.user.key(),
+        batch_end,
+        team_id: order.team_id,
+        is_buy: order.is_buy,
+        amount: order.amount,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn place_limit_order_handler(
+    ctx: Context<PlaceLimitOrder>,
+    stream_id: u64,
+    order_id: u64,
+    team_id: u8,
+    is_buy: bool,
+    amount: u64,
+    limit_price: u64,
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let user_position = &mut ctx.accounts.user_position;
+    let clock = Clock::get()?;
+    check_trader(
+        ctx.accounts.user.key(),
+        ctx.accounts.signer.key(),
+        ctx.accounts.session_token.as_deref(),
+        clock.unix_timestamp,
+    )?;
+
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(
+        clock.unix_timestamp < stream.end_time,
+        ErrorCode::StreamEnded
+    );
+    require!(team_id == 1 || team_id == 2, ErrorCode::InvalidTeam);
+    require!(amount > 0, ErrorCode::InvalidAmount);
+    require!(limit_price > 0, ErrorCode::InvalidPrice);
+    require!(
+        stream.auction_interval_secs == 0,
+        ErrorCode::AuctionModeActive
+    );
+    require!(
+        !is_buy || stream.commit_secs == 0,
+        ErrorCode::CommitRevealRequired
+    );
+
+    if user_position.user == Pubkey::default() {
+        open_position(
+            stream,
+            user_position,
+            &mut ctx.accounts.user_profile,
+            ctx.accounts.user.key(),
+            ctx.accounts.signer.key(),
+            ctx.bumps.user_position,
+            ctx.bumps.user_profile,
+        )?;
+    }
+
+    if is_buy {
+        let position_invested = position_stake(user_position)?
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        check_bet_limits(stream, amount, position_invested, 0)?;
+
+        pay_from_trader(
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            ctx.accounts.session_token.as_mut(),
+            &ctx.accounts.limit_order.to_account_info(),
+            amount,
+        )?;
+    } else {
+        // Sells hold their shares on the position, so a win still pays them
+        require!(
+            sellable_shares(user_position, team_id) >= amount,
+            ErrorCode::InsufficientShares
+        );
+        let reserved = reserved_shares(user_position, team_id);
+        *reserved = reserved
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    user_position.open_orders = user_position
+        .open_orders
+        .checked_add(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    let limit_order = &mut ctx.accounts.limit_order;
+    limit_order.user = ctx.accounts.user.key();
+    limit_order.stream_id = stream_id;
+    limit_order.order_id = order_id;
+    limit_order.team_id = team_id;
+    limit_order.is_buy = is_buy;
+    limit_order.amount = amount;
+    limit_order.limit_price = limit_price;
+    limit_order.created_at = clock.unix_timestamp;
+    limit_order.bump = ctx.bumps.limit_order;
+
+    emit_cpi!(LimitOrderPlaced {
+        version: EVENT_VERSION,
+        stream_id,
+        user: ctx.accounts.user.key(),
+        order_id,
+        team_id,
+        is_buy,
+        amount,
+        limit_price,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn match_orders_handler<'info>(
+    ctx: Context<'_, '_, 'info, 'info, MatchOrders<'info>>,
+    stream_id: u64,
+) -> Result<()> {
+    let clock = Clock::get()?;
+
+    let stream = &ctx.accounts.stream;
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(
+        clock.unix_timestamp < stream.end_time,
+        ErrorCode::StreamEnded
+    );
+    require!(
+        stream.auction_interval_secs == 0,
+        ErrorCode::AuctionModeActive
+    );
+    check_circuit_breaker(stream, clock.slot)?;
+
+    let remaining = ctx.remaining_accounts;
+    require!(
+        !remaining.is_empty() && remaining.len().is_multiple_of(4),
+        ErrorCode::InvalidRemainingAccounts
+    );
+
+    let stream_id_bytes = stream_id.to_le_bytes();
+
+    for accounts in remaining.chunks(4) {
+        // A fill that trips the breaker halts the rest of the crank like any
+        // other trade
+        if check_circuit_breaker(&ctx.accounts.stream, clock.slot).is_err() {
+            break;
+        }
+
+        let order_info = &accounts[0];
+        let position_info = &accounts[1];
+        let profile_info = &accounts[2];
+        let user_info = &accounts[3];
+
+        require!(
+            order_info.is_writable
+                && position_info.is_writable
+                && profile_info.is_writable
+                && user_info.is_writable,
+            ErrorCode::InvalidRemainingAccounts
+        );
+
+        let limit_order = Account::<LimitOrder>::try_from(order_info)?;
+        let mut user_position = Account::<UserPosition>::try_from(position_info)?;
+        let mut user_profile = Account::<UserProfile>::try_from(profile_info)?;
+
+        let expected_order = Pubkey::create_program_address(
+            &[
+                b"limit_order",
+                stream_id_bytes.as_ref(),
+                limit_order.user.as_ref(),
+                limit_order.order_id.to_le_bytes().as_ref(),
+                &[limit_order.bump],
+            ],
+            ctx.program_id,
+        )
+        .map_err(|_| ErrorCode::InvalidOrder)?;
+        require!(expected_order == order_info.key(), ErrorCode::InvalidOrder);
+        let expected_position = Pubkey::create_program_address(
+            &[
+                b"user_position",
+                stream_id_bytes.as_ref(),
+                limit_order.user.as_ref(),
+                &[user_position.bump],
+            ],
+            ctx.program_id,
+        )
+        .map_err(|_| ErrorCode::InvalidPosition)?;
+        require!(
+            expected_position == position_info.key(),
+            ErrorCode::InvalidPosition
+        );
+        let expected_profile = Pubkey::create_program_address(
+            &[
+                b"user_profile",
+                limit_order.user.as_ref(),
+                &[user_profile.bump],
+            ],
+            ctx.program_id,
+        )
+        .map_err(|_| ErrorCode::InvalidRemainingAccounts)?;
+        require!(
+            expected_profile == profile_info.key(),
+            ErrorCode::InvalidRemainingAccounts
+        );
+        require!(limit_order.user == user_info.key(), ErrorCode::Unauthorized);
+
+        // Orders whose limit the price has not crossed, whose own fill would
+        // slip past the limit, or that the stream's limits would now reject
+        // stay on the book without reverting the crank
+        let stream = &ctx.accounts.stream;
+        let team_id = limit_order.team_id;
+        let crossed = if limit_order.is_buy {
+            // The limit bounds the all-in price, fee included. Keeper fills
+            // carry no referral, so the quote's fee is the one charged
+            let quote = quote_buy(stream, team_id, limit_order.amount)?;
+            let position_invested = position_stake(&user_position)?
+                .checked_add(limit_order.amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+            quote.price_before <= limit_order.limit_price
+                && average_fill_price(limit_order.amount, quote.shares_out)?
+                    <= limit_order.limit_price
+                && stream.commit_secs == 0
+                && check_bet_limits(
+                    stream,
+                    limit_order.amount,
+                    position_invested,
+                    quote.price_impact_bps,
+                )
+                .is_ok()
+        } else {
+            let quote = quote_sell(stream, team_id, limit_order.amount)?;
+            quote.price_before >= limit_order.limit_price
+                && average_fill_price(quote.sol_out, limit_order.amount)? >= limit_order.limit_price
+        };
+        if !crossed {
+            continue;
+        }
+
+        let team_a_reserve_before = stream.team_a_reserve;
+        let team_b_reserve_before = stream.team_b_reserve;
+        let (position_bump, profile_bump) = (user_position.bump, user_profile.bump);
+        if !limit_order.is_buy {
+            let reserved = reserved_shares(&mut user_position, team_id);
+            *reserved = reserved
+                .checked_sub(limit_order.amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+        user_position.open_orders = user_position
+            .open_orders
+            .checked_sub(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Fills go through the same paths as a wallet trade, so fees, limits,
+        // the breaker and price history all apply the same way
+        let trade_accounts = TradeAccounts {
+            stream: &mut ctx.accounts.stream,
+            user_position: &mut user_position,
+            user_profile: &mut user_profile,
+            stream_vault: ctx.accounts.stream_vault.to_account_info(),
+            vault_bump: ctx.bumps.stream_vault,
+            price_history: &ctx.accounts.price_history,
+            user: user_info.clone(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+        };
+        let (filled, price_before, price_after) = if limit_order.is_buy {
+            let fill = execute_buy(
+                trade_accounts,
+                BuyFunding::Order(order_info.clone()),
+                None,
+                team_id,
+                limit_order.amount,
+                user_info.key(),
+                position_bump,
+                profile_bump,
+            )?;
+            if let Some(event) = fill.breaker {
+                emit_cpi!(event);
+            }
+            if let Some(event) = fill.fee {
+                emit_cpi!(event);
+            }
+            let purchased = fill.purchased;
+            let filled = (
+                purchased.shares_received,
+                purchased.price_before,
+                purchased.price_after,
+            );
+            emit_cpi!(purchased);
+            filled
+        } else {
+            let fill = execute_sell(
+                trade_accounts,
+                user_info.clone(),
+                team_id,
+                limit_order.amount,
+            )?;
+            if let Some(event) = fill.breaker {
+                emit_cpi!(event);
+            }
+            let sold = fill.sold;
+            let filled = (sold.sol_received, sold.price_before, sold.price_after);
+            emit_cpi!(sold);
+            filled
+        };
+
+        let stream = &ctx.accounts.stream;
+        emit_cpi!(LimitOrderFilled {
+            version: EVENT_VERSION,
+            stream_id,
+            trade_seq: stream.trade_seq,
+            user: limit_order.user,
+            keeper: ctx.accounts.keeper.key(),
+            order_id: limit_order.order_id,
+            team_id,
+            is_buy: limit_order.is_buy,
+            amount: limit_order.amount,
+            filled,
+            limit_price: limit_order.limit_price,
+            price_before,
+            price_after,
+            team_a_reserve_before,
+            team_b_reserve_before,
+            team_a_reserve_after: stream.team_a_reserve,
+            team_b_reserve_after: stream.team_b_reserve,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        user_position.exit(ctx.program_id)?;
+        user_profile.exit(ctx.program_id)?;
+        limit_order.close(user_info.clone())?;
+    }
+
+    Ok(())
+}
+
+pub fn cancel_limit_order_handler(
+    ctx: Context<CancelLimitOrder>,
+    stream_id: u64,
+    order_id: u64,
+) -> Result<()> {
+    let limit_order = &ctx.accounts.limit_order;
+    let user_position = &mut ctx.accounts.user_position;
+    let clock = Clock::get()?;
+    check_trader(
+        ctx.accounts.user.key(),
+        ctx.accounts.signer.key(),
+        ctx.accounts.session_token.as_deref(),
+        clock.unix_timestamp,
+    )?;
+
+    // Buy escrow returns with the order account's lamports when it closes
+    if !limit_order.is_buy {
+        let reserved = reserved_shares(user_position, limit_order.team_id);
+        *reserved = reserved
+            .checked_sub(limit_order.amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    user_position.open_orders = user_position
+        .open_orders
+        .checked_sub(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    emit_cpi!(LimitOrderCancelled {
+        version: EVENT_VERSION,
+        stream_id,
+        user: ctx.accounts.user.key(),
+        order_id,
+        team_id: limit_order.team_id,
+        is_buy: limit_order.is_buy,
+        amount: limit_order.amount,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn create_challenge_handler(
+    ctx: Context<CreateChallenge>,
+    stream_id: u64,
+    challenge_id: u64,
+    team_id: u8,
+    creator_stake: u64,
+    counterparty_stake: u64,
+    counterparty: Pubkey,
+) -> Result<()> {
+    let stream = &ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(
+        clock.unix_timestamp < stream.end_time,
+        ErrorCode::StreamEnded
+    );
+    require!(team_id == 1 || team_id == 2, ErrorCode::InvalidTeam);
+    require!(
+        creator_stake > 0 && counterparty_stake > 0,
+        ErrorCode::InvalidAmount
+    );
+    require!(
+        counterparty != ctx.accounts.creator.key(),
+        ErrorCode::InvalidChallenge
+    );
+
+    let cpi_context = CpiContext::new(
+        ctx.accounts.system_program.to_account_info(),
+        anchor_lang::system_program::Transfer {
+            from: ctx.accounts.creator.to_account_info(),
+            to: ctx.accounts.challenge.to_account_info(),
+        },
+    );
+    anchor_lang::system_program::transfer(cpi_context, creator_stake)?;
+
+    let challenge = &mut ctx.accounts.challenge;
+    challenge.stream_id = stream_id;
+    challenge.challenge_id = challenge_id;
+    challenge.creator = ctx.accounts.creator.key();
+    challenge.counterparty = counterparty;
+    challenge.acceptor = Pubkey::default();
+    challenge.team_id = team_id;
+    challenge.creator_stake = creator_stake;
+    challenge.counterparty_stake = counterparty_stake;
+    challenge.created_at = clock.unix_timestamp;
+    challenge.bump = ctx.bumps.challenge;
+
+    emit_cpi!(ChallengeCreated {
+        version: EVENT_VERSION,
+        stream_id,
+        challenge_id,
+        creator: challenge.creator,
+        counterparty,
+        team_id,
+        creator_stake,
+        counterparty_stake,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn accept_challenge_handler(
+    ctx: Context<AcceptChallenge>,
+    stream_id: u64,
+    challenge_id: u64,
+) -> Result<()> {
+    let stream = &ctx.accounts.stream;
+    let acceptor = ctx.accounts.acceptor.key();
+    let clock = Clock::get()?;
+
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(
+        clock.unix_timestamp < stream.end_time,
+        ErrorCode::StreamEnded
+    );
+    require!(
+        ctx.accounts.challenge.acceptor == Pubkey::default(),
+        ErrorCode::ChallengeAlreadyAccepted
+    );
+    require!(
+        acceptor != ctx.accounts.challenge.creator,
+        ErrorCode::InvalidChallenge
+    );
+    require!(
+        ctx.accounts.challenge.counterparty == Pubkey::default()
+            || ctx.accounts.challenge.counterparty == acceptor,
+        ErrorCode::Unauthorized
+    );
+
+    let cpi_context = CpiContext::new(
+        ctx.accounts.system_program.to_account_info(),
+        anchor_lang::system_program::Transfer {
+            from: ctx.accounts.acceptor.to_account_info(),
+            to: ctx.accounts.challenge.to_account_info(),
+        },
+    );
+    anchor_lang::system_program::transfer(cpi_context, ctx.accounts.challenge.counterparty_stake)?;
+
+    let challenge = &mut ctx.accounts.challenge;
+    challenge.acceptor = acceptor;
+
+    emit_cpi!(ChallengeAccepted {
+        version: EVENT_VERSION,
+        stream_id,
+        challenge_id,
+        creator: challenge.creator,
+        acceptor,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn cancel_challenge_handler(
+    ctx: Context<CancelChallenge>,
+    stream_id: u64,
+    challenge_id: u64,
+) -> Result<()> {
+    let challenge = &ctx.accounts.challenge;
+    let clock = Clock::get()?;
+
+    require!(
+        challenge.acceptor == Pubkey::default(),
+        ErrorCode::ChallengeAlreadyAccepted
+    );
+
+    // The stake rides back to the creator when the account closes
+    emit_cpi!(ChallengeCancelled {
+        version: EVENT_VERSION,
+        stream_id,
+        challenge_id,
+        creator: challenge.creator,
+        refunded: challenge.creator_stake,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn settle_challenge_handler(
+    ctx: Context<SettleChallenge>,
+    stream_id: u64,
+    challenge_id: u64,
+) -> Result<()> {
+    let stream = &ctx.accounts.stream;
+    let challenge = &mut ctx.accounts.challenge;
+    let clock = Clock::get()?;
+
+    require!(
+        challenge.acceptor != Pubkey::default(),
+        ErrorCode::ChallengeNotAccepted
+    );
+    require!(
+        ctx.accounts.acceptor.key() == challenge.acceptor,
+        ErrorCode::InvalidChallenge
+    );
+
+    let (winner, payout) = if stream.winning_team != 0 {
+        let payout = challenge
+            .creator_stake
+            .checked_add(challenge.counterparty_stake)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let winner = if stream.winning_team == challenge.team_id {
+            ctx.accounts.creator.to_account_info()
+        } else {
+            ctx.accounts.acceptor.to_account_info()
+        };
+        challenge.sub_lamports(payout)?;
+        winner.add_lamports(payout)?;
+
+        (winner.key(), payout)
+    } else {
+        require!(stream.is_voided, ErrorCode::ChallengeNotSettleable);
+        challenge.sub_lamports(challenge.counterparty_stake)?;
+        ctx.accounts
+            .acceptor
+            .add_lamports(challenge.counterparty_stake)?;
+
+        // The creator's stake returns with the rent when the account closes
+        (Pubkey::default(), 0)
+    };
+
+    emit_cpi!(ChallengeSettled {
+        version: EVENT_VERSION,
+        stream_id,
+        challenge_id,
+        creator: challenge.creator,
+        acceptor: challenge.acceptor,
+        winner,
+        payout,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn set_trigger_handler(
+    ctx: Context<SetTrigger>,
+    stream_id: u64,
+    team_id: u8,
+    stop_loss_price: u64,
+    take_profit_price: u64,
+    max_slippage_bps: u16,
+    tip: u64,
+) -> Result<()> {
+    let stream = &ctx.accounts.stream;
+    let user_position = &ctx.accounts.user_position;
+    let clock = Clock::get()?;
+    check_trader(
+        ctx.accounts.user.key(),
+        ctx.accounts.signer.key(),
+        ctx.accounts.session_token.as_deref(),
+        clock.unix_timestamp,
+    )?;
+
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(
+        clock.unix_timestamp < stream.end_time,
+        ErrorCode::StreamEnded
+    );
+    require!(team_id == 1 || team_id == 2, ErrorCode::InvalidTeam);
+    require!(
+        stop_loss_price > 0 || take_profit_price > 0,
+        ErrorCode::InvalidTrigger
+    );
+    require!(
+        stop_loss_price == 0 || take_profit_price == 0 || stop_loss_price < take_profit_price,
+        ErrorCode::InvalidTrigger
+    );
+    require!(max_slippage_bps <= 10_000, ErrorCode::InvalidTrigger);
+
+    require!(
+        sellable_shares(user_position, team_id) > 0,
+        ErrorCode::InsufficientShares
+    );
+
+    if tip > 0 {
+        pay_from_trader(
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            ctx.accounts.session_token.as_mut(),
+            &ctx.accounts.position_trigger.to_account_info(),
+            tip,
+        )?;
+    }
+
+    let position_trigger = &mut ctx.accounts.position_trigger;
+    position_trigger.user = ctx.accounts.user.key();
+    position_trigger.stream_id = stream_id;
+    position_trigger.team_id = team_id;
+    position_trigger.stop_loss_price = stop_loss_price;
+    position_trigger.take_profit_price = take_profit_price;
+    position_trigger.max_slippage_bps = max_slippage_bps;
+    position_trigger.tip = tip;
+    position_trigger.created_at = clock.unix_timestamp;
+    position_trigger.bump = ctx.bumps.position_trigger;
+
+    emit_cpi!(TriggerSet {
+        version: EVENT_VERSION,
+        stream_id,
+        user: ctx.accounts.user.key(),
+        team_id,
+        stop_loss_price,
+        take_profit_price,
+        max_slippage_bps,
+        tip,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn execute_trigger_handler(
+    ctx: Context<ExecuteTrigger>,
+    stream_id: u64,
+    team_id: u8,
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let position_trigger = &ctx.accounts.position_trigger;
+    let user_position = &mut ctx.accounts.user_position;
+    let clock = Clock::get()?;
+
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(
+        clock.unix_timestamp < stream.end_time,
+        ErrorCode::StreamEnded
+    );
+    require!(
+        stream.auction_interval_secs == 0,
+        ErrorCode::AuctionModeActive
+    );
+    check_circuit_breaker(stream, clock.slot)?;
+
+    let (reserve_team, reserve_opposite) = if team_id == 1 {
+        (stream.team_a_reserve, stream.team_b_reserve)
+    } else {
+        (stream.team_b_reserve, stream.team_a_reserve)
+    };
+    let price_before = calculate_price(reserve_team, reserve_opposite)?;
+
+    let is_stop_loss =
+        position_trigger.stop_loss_price > 0 && price_before <= position_trigger.stop_loss_price;
+    let is_take_profit = position_trigger.take_profit_price > 0
+        && price_before >= position_trigger.take_profit_price;
+    require!(is_stop_loss || is_take_profit, ErrorCode::TriggerNotCrossed);
+
+    // Closes out whatever is held on that side when the trigger fires
+    let shares_amount = sellable_shares(user_position, team_id);
+    require!(shares_amount > 0, ErrorCode::InsufficientShares);
+
+    let sol_out = calculate_sol_out(shares_amount, reserve_team, reserve_opposite)?;
+    // Slippage is measured from the user's threshold, not the spot price, so a
+    // keeper who pushes the price through the trigger cannot also set the floor
+    let threshold = if is_stop_loss {
+        position_trigger.stop_loss_price
+    } else {
+        position_trigger.take_profit_price
+    };
+    let min_fill_price = (threshold as u128)
+        .checked_mul((10_000 - position_trigger.max_slippage_bps) as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / 10_000;
+    require!(
+        average_fill_price(sol_out, shares_amount)? as u128 >= min_fill_price,
+        ErrorCode::SlippageExceeded
+    );
+
+    let team_a_reserve_before = stream.team_a_reserve;
+    let team_b_reserve_before = stream.team_b_reserve;
+
+    accumulate_prices(stream, clock.unix_timestamp)?;
+
+    apply_sell(stream, team_id, shares_amount, sol_out)?;
+    stream.last_trade_time = clock.unix_timestamp;
+
+    pay_from_vault(
+        &ctx.accounts.system_program.to_account_info(),
+        &ctx.accounts.stream_vault.to_account_info(),
+        &ctx.accounts.user.to_account_info(),
+        stream_id,
+        ctx.bumps.stream_vault,
+        sol_out,
+    )?;
+
+    let (reserve_team_after, reserve_opposite_after) = if team_id == 1 {
+        (stream.team_a_reserve, stream.team_b_reserve)
+    } else {
+        (stream.team_b_reserve, stream.team_a_reserve)
+    };
+    let price_after = calculate_price(reserve_team_after, reserve_opposite_after)?;
+
+    let prices_before = team_prices(team_a_reserve_before, team_b_reserve_before)?;
+    let prices_after = team_prices(stream.team_a_reserve, stream.team_b_reserve)?;
+
+    record_candle(
+        &mut *ctx.accounts.price_history.load_mut()?,
+        clock.unix_timestamp,
+        team_id,
+        sol_out,
+        prices_before,
+        prices_after,
+    )?;
+
+    let trip = update_circuit_breaker(stream, clock.slot, prices_before, prices_after)?;
+    if let Some(event) =
+        breaker_tripped(stream, ctx.accounts.user.key(), trip, prices_after, &clock)
+    {
+        emit_cpi!(event);
+    }
+
+    if team_id == 1 {
+        user_position.team_a_shares -= shares_amount;
+    } else {
+        user_position.team_b_shares -= shares_amount;
+    }
+    reduce_cost_basis(user_position, sol_out);
+
+    let user_profile = &mut ctx.accounts.user_profile;
+    user_profile.lifetime_volume = user_profile
+        .lifetime_volume
+        .checked_add(sol_out)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    // The rest of the trigger's lamports go back to the owner when it closes
+    let tip = position_trigger.tip;
+    if tip > 0 {
+        ctx.accounts.position_trigger.sub_lamports(tip)?;
+        ctx.accounts.keeper.add_lamports(tip)?;
+    }
+
+    emit_cpi!(TriggerExecuted {
+        version: EVENT_VERSION,
+        stream_id,
+        trade_seq: stream.trade_seq,
+        user: ctx.accounts.user.key(),
+        keeper: ctx.accounts.keeper.key(),
+        team_id,
+        is_stop_loss,
+        shares_sold: shares_amount,
+        sol_received: sol_out,
+        tip,
+        price_before,
+        price_after,
+        team_a_reserve_before,
+        team_b_reserve_before,
+        team_a_reserve_after: stream.team_a_reserve,
+        team_b_reserve_after: stream.team_b_reserve,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn cancel_trigger_handler(
+    ctx: Context<CancelTrigger>,
+    stream_id: u64,
+    team_id: u8,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    check_trader(
+        ctx.accounts.user.key(),
+        ctx.accounts.signer.key(),
+        ctx.accounts.session_token.as_deref(),
+        clock.unix_timestamp,
+    )?;
+
+    emit_cpi!(TriggerCancelled {
+        version: EVENT_VERSION,
+        stream_id,
+        user: ctx.accounts.user.key(),
+        team_id,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn end_stream_handler(
+    ctx: Context<EndStream>,
+    _stream_id: u64,
+    winning_team: u8,
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(
+        ctx.accounts.authority.key() == stream.authority,
+        ErrorCode::Unauthorized
+    );
+    require!(
+        clock.unix_timestamp >= stream.end_time,
+        ErrorCode::StreamNotEnded
+    );
+    require!(
+        winning_team == 1 || winning_team == 2,
+        ErrorCode::InvalidTeam
+    );
+
+    stream.is_active = false;
+    stream.winning_team = winning_team;
+
+    // Book the streamer's cut now, before any winnings can be claimed
+    stream.streamer_owed = streamer_cut(stream)?;
+    stream.fees_collected -= stream.streamer_owed;
+
+    let final_team_a_price = calculate_price(stream.team_a_reserve, stream.team_b_reserve)?;
+    let final_team_b_price = calculate_price(stream.team_b_reserve, stream.team_a_reserve)?;
+
+    emit_cpi!(StreamEnded {
+        version: EVENT_VERSION,
+        stream_id: stream.stream_id,
+        winning_team,
+        total_pool: stream.total_pool,
+        team_a_shares: stream.team_a_shares_sold,
+        team_b_shares: stream.team_b_shares_sold,
+        final_team_a_price,
+        final_team_b_price,
+        trade_count: stream.trade_seq,
+        team_a_buy_volume: stream.team_a_buy_volume,
+        team_b_buy_volume: stream.team_b_buy_volume,
+        team_a_sell_volume: stream.team_a_sell_volume,
+        team_b_sell_volume: stream.team_b_sell_volume,
+        unique_bettors: stream.unique_bettors,
+        largest_bet: stream.largest_bet,
+        last_trade_time: stream.last_trade_time,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn void_stream_handler(ctx: Context<EndStream>, stream_id: u64) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(
+        ctx.accounts.authority.key() == stream.authority,
+        ErrorCode::Unauthorized
+    );
+
+    stream.is_active = false;
+    stream.is_voided = true;
+
+    emit_cpi!(StreamVoided {
+        version: EVENT_VERSION,
+        stream_id,
+        total_pool: stream.total_pool,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn claim_refund_handler(ctx: Context<ClaimRefund>, stream_id: u64) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let user_position = &mut ctx.accounts.user_position;
+    let clock = Clock::get()?;
+
+    require!(stream.is_voided, ErrorCode::StreamNotVoided);
+    require!(!user_position.has_claimed, ErrorCode::AlreadyClaimed);
+
+    // Each position gets back its own net invested, out of what the pool
+    // still holds; promo credit spent on it is not refunded
+    let refund = user_position.total_invested.min(stream.total_pool);
+    require!(refund > 0, ErrorCode::NoPayout);
+
+    pay_from_vault(
+        &ctx.accounts.system_program.to_account_info(),
+        &ctx.accounts.stream_vault.to_account_info(),
+        &ctx.accounts.user.to_account_info(),
+        stream_id,
+        ctx.bumps.stream_vault,
+        refund,
+    )?;
+
+    stream.total_pool -= refund;
+    user_position.has_claimed = true;
+
+    emit_cpi!(RefundClaimed {
+        version: EVENT_VERSION,
+        stream_id,
+        user: ctx.accounts.user.key(),
+        refund,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn claim_winnings_handler(ctx: Context<ClaimWinnings>, _stream_id: u64) -> Result<()> {
+    let claimed = pay_winnings(ClaimAccounts {
+        stream: &mut ctx.accounts.stream,
+        user_position: &mut ctx.accounts.user_position,
+        user_profile: &mut ctx.accounts.user_profile,
+        stream_vault: ctx.accounts.stream_vault.to_account_info(),
+        vault_bump: ctx.bumps.stream_vault,
+        user: ctx.accounts.user.to_account_info(),
+        system_program: ctx.accounts.system_program.to_account_info(),
+    })?;
+
+    emit_cpi!(claimed);
+
+    Ok(())
+}
+
+pub fn relay_claim_handler(ctx: Context<RelayClaim>, _stream_id: u64) -> Result<()> {
+    // Winnings go to the position owner whoever relays the claim
+    let claimed = pay_winnings(ClaimAccounts {
+        stream: &mut ctx.accounts.stream,
+        user_position: &mut ctx.accounts.user_position,
+        user_profile: &mut ctx.accounts.user_profile,
+        stream_vault: ctx.accounts.stream_vault.to_account_info(),
+        vault_bump: ctx.bumps.stream_vault,
+        user: ctx.accounts.user.to_account_info(),
+        system_program: ctx.accounts.system_program.to_account_info(),
+    })?;
+
+    emit_cpi!(claimed);
+
+    Ok(())
+}
+
+pub fn settle_batch_handler<'info>(
+    ctx: Context<'_, '_, 'info, 'info, SettleBatch<'info>>,
+    stream_id: u64,
+    close_positions: bool,
+) -> Result<()> {
+    let stream = &ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(!stream.is_active, ErrorCode::StreamStillActive);
+    require!(stream.winning_team != 0, ErrorCode::NoWinnerDeclared);
+
+    let remaining = ctx.remaining_accounts;
+    require!(
+        !remaining.is_empty() && remaining.len().is_multiple_of(3),
+        ErrorCode::InvalidRemainingAccounts
+    );
+
+    let stream_id_bytes = stream_id.to_le_bytes();
+    let mut positions_settled: u32 = 0;
+    let mut total_paid: u64 = 0;
+
+    for accounts in remaining.chunks(3) {
+        let position_info = &accounts[0];
+        let profile_info = &accounts[1];
+        let user_info = &accounts[2];
+
+        require!(
+            position_info.is_writable && profile_info.is_writable && user_info.is_writable,
+            ErrorCode::InvalidRemainingAccounts
+        );
+
+        let mut user_position = Account::<UserPosition>::try_from(position_info)?;
+        let mut user_profile = Account::<UserProfile>::try_from(profile_info)?;
+
+        let expected_position = Pubkey::create_program_address(
+            &[
+                b"user_position",
+                stream_id_bytes.as_ref(),
+                user_position.user.as_ref(),
+                &[user_position.bump],
+            ],
+            ctx.program_id,
+        )
+        .map_err(|_| ErrorCode::InvalidPosition)?;
+        require!(
+            expected_position == position_info.key(),
+            ErrorCode::InvalidPosition
+        );
+        let expected_profile = Pubkey::create_program_address(
+            &[
+                b"user_profile",
+                user_position.user.as_ref(),
+                &[user_profile.bump],
+            ],
+            ctx.program_id,
+        )
+        .map_err(|_| ErrorCode::InvalidRemainingAccounts)?;
+        require!(
+            expected_profile == profile_info.key(),
+            ErrorCode::InvalidRemainingAccounts
+        );
+        require!(
+            user_position.user == user_info.key(),
+            ErrorCode::Unauthorized
+        );
+
+        // Losing and already-settled positions are skipped so one stale entry
+        // does not revert the whole batch
+        if user_position.has_claimed {
+            continue;
+        }
+
+        let (user_winning_shares, payout) = winning_payout(stream, &user_position)?;
+        if payout == 0 {
+            continue;
+        }
+
+        pay_from_vault(
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.stream_vault.to_account_info(),
+            user_info,
+            stream_id,
+            ctx.bumps.stream_vault,
+            payout,
+        )?;
+
+        user_position.has_claimed = true;
+        // Recorded before any close so the position's result is never lost
+        let realized_pnl = if user_position.profile_settled {
+            None
+        } else {
+            Some(record_result(
+                &mut user_profile,
+                &mut user_position,
+                true,
+                payout,
+            )?)
+        };
+
+        emit_cpi!(WinningsClaimed {
+            version: EVENT_VERSION,
+            stream_id,
+            user: user_info.key(),
+            winning_team: stream.winning_team,
+            shares: user_winning_shares,
+            payout,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+        if let Some(realized_pnl) = realized_pnl {
+            emit_cpi!(ResultRecorded {
+                version: EVENT_VERSION,
+                stream_id,
+                user: user_info.key(),
+                won: true,
+                realized_pnl,
+                slot: clock.slot,
+                unix_timestamp: clock.unix_timestamp,
+            });
+        }
+
+        user_profile.exit(ctx.program_id)?;
+        // Positions with orders or reveals still settling against them stay
+        // open, as do positions a sponsor paid for, so rent only ever goes
+        // back to the owner who funded it
+        if close_positions && owner_can_close(&user_position) {
+            user_position.close(user_info.clone())?;
+        } else {
+            user_position.exit(ctx.program_id)?;
+        }
+
+        positions_settled = positions_settled
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        total_paid = total_paid
+            .checked_add(payout)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+
+    let stream = &mut ctx.accounts.stream;
+    stream.winnings_paid = stream
+        .winnings_paid
+        .checked_add(total_paid)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    emit_cpi!(BatchSettled {
+        version: EVENT_VERSION,
+        stream_id,
+        keeper: ctx.accounts.keeper.key(),
+        positions_settled,
+        total_paid,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn post_payout_root_handler(
+    ctx: Context<PostPayoutRoot>,
+    stream_id: u64,
+    payout_root: [u8; 32],
+    payout_total: u64,
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.authority.key() == stream.authority,
+        ErrorCode::Unauthorized
+    );
+    require!(!stream.is_active, ErrorCode::StreamStillActive);
+    require!(stream.winning_team != 0, ErrorCode::NoWinnerDeclared);
+    // Write-once, and only while the whole pool is still in the vault: the root
+    // then decides every payout, bounded by payout_total
+    require!(
+        stream.payout_root == [0u8; 32],
+        ErrorCode::PayoutRootAlreadySet
+    );
+    require!(stream.winnings_paid == 0, ErrorCode::WinningsAlreadyPaid);
+    require!(
+        payout_root != [0u8; 32] && payout_total <= stream.total_pool,
+        ErrorCode::InvalidPayoutRoot
+    );
+
+    stream.payout_root = payout_root;
+    stream.payout_root_total = payout_total;
+
+    emit_cpi!(PayoutRootPosted {
+        version: EVENT_VERSION,
+        stream_id,
+        payout_root,
+        payout_total,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn claim_with_proof_handler(
+    ctx: Context<ClaimWithProof>,
+    stream_id: u64,
+    payout: u64,
+    proof: Vec<[u8; 32]>,
+) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let user_position = &mut ctx.accounts.user_position;
+    let clock = Clock::get()?;
+
+    require!(!stream.is_active, ErrorCode::StreamStillActive);
+    require!(stream.winning_team != 0, ErrorCode::NoWinnerDeclared);
+    require!(stream.payout_root != [0u8; 32], ErrorCode::PayoutRootNotSet);
+    require!(!user_position.has_claimed, ErrorCode::AlreadyClaimed);
+    require!(payout > 0, ErrorCode::NoPayout);
+
+    // The position stays the source of truth: a leaf only pays what the
+    // position would, so a bad root cannot redirect the pool
+    let (shares, position_payout) = winning_payout(stream, user_position)?;
+    require!(payout == position_payout, ErrorCode::PayoutMismatch);
+
+    let leaf = payout_leaf(stream_id, &ctx.accounts.user.key(), payout);
+    require!(
+        verify_merkle_proof(&proof, stream.payout_root, leaf),
+        ErrorCode::InvalidMerkleProof
+    );
+
+    // The receipt stops a leaf paying twice; the committed total stops a bad
+    // root paying out more than it promised
+    let winnings_paid = stream
+        .winnings_paid
+        .checked_add(payout)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(
+        winnings_paid <= stream.payout_root_total,
+        ErrorCode::PayoutExceedsRoot
+    );
+
+    pay_from_vault(
+        &ctx.accounts.system_program.to_account_info(),
+        &ctx.accounts.stream_vault.to_account_info(),
+        &ctx.accounts.user.to_account_info(),
+        stream_id,
+        ctx.bumps.stream_vault,
+        payout,
+    )?;
+    stream.winnings_paid = winnings_paid;
+    user_position.has_claimed = true;
+
+    let payout_receipt = &mut ctx.accounts.payout_receipt;
+    payout_receipt.stream_id = stream_id;
+    payout_receipt.user = ctx.accounts.user.key();
+    payout_receipt.payout = payout;
+    payout_receipt.bump = ctx.bumps.payout_receipt;
+
+    emit_cpi!(WinningsClaimed {
+        version: EVENT_VERSION,
+        stream_id,
+        user: ctx.accounts.user.key(),
+        winning_team: stream.winning_team,
+        shares,
+        payout,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn settle_profile_handler(
+    ctx: Context<SettleProfile>,
+    stream_id: u64,
+    user: Pubkey,
+) -> Result<()> {
+    let stream = &ctx.accounts.stream;
+    let user_position = &mut ctx.accounts.user_position;
+    let clock = Clock::get()?;
+
+    require!(!stream.is_active, ErrorCode::StreamStillActive);
+    require!(stream.winning_team != 0, ErrorCode::NoWinnerDeclared);
+    require!(
+        !user_position.profile_settled,
+        ErrorCode::ProfileAlreadySettled
+    );
+
+    let (winning_shares, total_winning_shares) = if stream.winning_team == 1 {
+        (user_position.team_a_shares, stream.team_a_shares_sold)
+    } else {
+        (user_position.team_b_shares, stream.team_b_shares_sold)
+    };
+    let won = winning_shares > 0;
+
+    // Winners record their result when they are paid from their position; this
+    // path covers losers, who never are, and any paid winner whose result is
+    // still unrecorded, such as one paid through the payout root
+    let payout = if won {
+        require!(user_position.has_claimed, ErrorCode::WinningsNotClaimed);
+        calculate_payout(stream.total_pool, winning_shares, total_winning_shares)?
+    } else {
+        0
+    };
+
+    let realized_pnl = record_result(&mut ctx.accounts.user_profile, user_position, won, payout)?;
+
+    emit_cpi!(ResultRecorded {
+        version: EVENT_VERSION,
+        stream_id,
+        user,
+        won,
+        realized_pnl,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn quote_buy_handler(
+    ctx: Context<QuoteTrade>,
+    _stream_id: u64,
+    team_id: u8,
+    sol_amount: u64,
+) -> Result<BuyQuote> {
+    let stream = &ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(
+        clock.unix_timestamp < stream.end_time,
+        ErrorCode::StreamEnded
+    );
+    check_circuit_breaker(stream, clock.slot)?;
+
+    quote_buy(stream, team_id, sol_amount)
+}
+
+pub fn quote_sell_handler(
+    ctx: Context<QuoteTrade>,
+    _stream_id: u64,
+    team_id: u8,
+    shares_amount: u64,
+) -> Result<SellQuote> {
+    let stream = &ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(stream.is_active, ErrorCode::StreamNotActive);
+    require!(
+        clock.unix_timestamp < stream.end_time,
+        ErrorCode::StreamEnded
+    );
+    check_circuit_breaker(stream, clock.slot)?;
+
+    quote_sell(stream, team_id, shares_amount)
+}
+
+pub fn quote_claim_handler(
+    ctx: Context<QuoteClaim>,
+    _stream_id: u64,
+    _user: Pubkey,
+) -> Result<ClaimQuote> {
+    quote_claim(&ctx.accounts.stream, &ctx.accounts.user_position)
+}
+
+pub fn observe_prices_handler(
+    ctx: Context<QuoteTrade>,
+    _stream_id: u64,
+) -> Result<PriceObservation> {
+    let stream = &ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    // Trading stops at end_time, so the accumulators never extrapolate past it
+    let timestamp = clock.unix_timestamp.min(stream.end_time);
+    let (team_a_price_cumulative, team_b_price_cumulative) =
+        current_cumulative_prices(stream, timestamp)?;
+
+    Ok(PriceObservation {
+        timestamp,
+        team_a_price_cumulative,
+        team_b_price_cumulative,
+    })
+}
+
+pub fn emergency_withdraw_handler(ctx: Context<EmergencyWithdraw>, stream_id: u64) -> Result<()> {
+    let stream = &mut ctx.accounts.stream;
+    let clock = Clock::get()?;
+
+    require!(
+        ctx.accounts.authority.key() == stream.authority,
+        ErrorCode::Unauthorized
+    );
+    require!(!stream.is_active, ErrorCode::StreamStillActive);
+
+    let vault_balance = ctx.accounts.stream_vault.to_account_info().lamports();
+
+    // Drain the whole vault to the authority
+    pay_from_vault(
+        &ctx.accounts.system_program.to_account_info(),
+        &ctx.accounts.stream_vault.to_account_info(),
+        &ctx.accounts.authority.to_account_info(),
+        stream_id,
+        ctx.bumps.stream_vault,
+        vault_balance,
+    )?;
+
+    emit_cpi!(EmergencyWithdrawn {
+        version: EVENT_VERSION,
+        stream_id,
+        authority: ctx.accounts.authority.key(),
+        amount: vault_balance,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
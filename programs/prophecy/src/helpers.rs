@@ -1,5 +1,7 @@
 use crate::errors::ErrorCode;
+use crate::state::*;
 use anchor_lang::prelude::*;
+use solana_sha256_hasher::hashv;
 
 /// Calculate current price using CPMM formula
 /// Price = reserve_opposite / reserve_team
@@ -89,6 +91,587 @@ pub fn calculate_sol_out(shares_in: u64, reserve_team: u64, reserve_opposite: u6
     Ok(sol_out)
 }
 
+/// Value `shares_in` of `from_team` in lamports, then spend them less the
+/// trading fee on the other team. Returns (sol_value, fee, shares_out),
+/// matching a sell followed by a buy on the curve
+pub fn calculate_swap_out(
+    stream: &Stream,
+    from_team: u8,
+    shares_in: u64,
+) -> Result<(u64, u64, u64)> {
+    let (reserve_from, reserve_to) = if from_team == 1 {
+        (stream.team_a_reserve, stream.team_b_reserve)
+    } else {
+        (stream.team_b_reserve, stream.team_a_reserve)
+    };
+    let sol_value = calculate_sol_out(shares_in, reserve_from, reserve_to)?;
+    let (fee, _) = calculate_fees(stream, sol_value, false)?;
+    let shares_out = calculate_shares_out(
+        sol_value - fee,
+        reserve_to
+            .checked_sub(sol_value)
+            .ok_or(ErrorCode::MathOverflow)?,
+        reserve_from
+            .checked_add(shares_in)
+            .ok_or(ErrorCode::MathOverflow)?,
+    )?;
+
+    Ok((sol_value, fee, shares_out))
+}
+
+/// Move a filled buy into the reserves, shares sold and buy volume
+/// Team reserve drops by shares_out, opposite reserve grows by sol_amount
+pub fn apply_buy(stream: &mut Stream, team_id: u8, sol_amount: u64, shares_out: u64) -> Result<()> {
+    if team_id == 1 {
+        stream.team_a_reserve = stream
+            .team_a_reserve
+            .checked_sub(shares_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stream.team_b_reserve = stream
+            .team_b_reserve
+            .checked_add(sol_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stream.team_a_shares_sold = stream
+            .team_a_shares_sold
+            .checked_add(shares_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stream.team_a_buy_volume = stream
+            .team_a_buy_volume
+            .checked_add(sol_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        stream.team_b_reserve = stream
+            .team_b_reserve
+            .checked_sub(shares_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stream.team_a_reserve = stream
+            .team_a_reserve
+            .checked_add(sol_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stream.team_b_shares_sold = stream
+            .team_b_shares_sold
+            .checked_add(shares_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stream.team_b_buy_volume = stream
+            .team_b_buy_volume
+            .checked_add(sol_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+
+    stream.total_pool = stream
+        .total_pool
+        .checked_add(sol_amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    stream.trade_seq = stream
+        .trade_seq
+        .checked_add(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(())
+}
+
+/// Move a filled sell into the reserves, shares sold and sell volume
+/// Team reserve grows by shares_in, opposite reserve drops by sol_out
+pub fn apply_sell(stream: &mut Stream, team_id: u8, shares_in: u64, sol_out: u64) -> Result<()> {
+    if team_id == 1 {
+        stream.team_a_reserve = stream
+            .team_a_reserve
+            .checked_add(shares_in)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stream.team_b_reserve = stream
+            .team_b_reserve
+            .checked_sub(sol_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stream.team_a_shares_sold = stream
+            .team_a_shares_sold
+            .checked_sub(shares_in)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stream.team_a_sell_volume = stream
+            .team_a_sell_volume
+            .checked_add(sol_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        stream.team_b_reserve = stream
+            .team_b_reserve
+            .checked_add(shares_in)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stream.team_a_reserve = stream
+            .team_a_reserve
+            .checked_sub(sol_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stream.team_b_shares_sold = stream
+            .team_b_shares_sold
+            .checked_sub(shares_in)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stream.team_b_sell_volume = stream
+            .team_b_sell_volume
+            .checked_add(sol_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+
+    stream.total_pool = stream
+        .total_pool
+        .checked_sub(sol_out)
+        .ok_or(ErrorCode::MathOverflow)?;
+    stream.trade_seq = stream
+        .trade_seq
+        .checked_add(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(())
+}
+
+/// Fill in a freshly created UserPosition and count the bettor on the
+/// stream and on the owner's profile
+pub fn open_position(
+    stream: &mut Stream,
+    user_position: &mut UserPosition,
+    user_profile: &mut UserProfile,
+    user: Pubkey,
+    rent_payer: Pubkey,
+    position_bump: u8,
+    profile_bump: u8,
+) -> Result<()> {
+    user_position.user = user;
+    user_position.stream_id = stream.stream_id;
+    user_position.team_a_shares = 0;
+    user_position.team_b_shares = 0;
+    user_position.total_invested = 0;
+    user_position.has_claimed = false;
+    user_position.profile_settled = false;
+    user_position.promo_shares_a = 0;
+    user_position.promo_shares_b = 0;
+    user_position.referrer = Pubkey::default();
+    user_position.pending_invested = 0;
+    user_position.open_orders = 0;
+    user_position.reserved_shares_a = 0;
+    user_position.reserved_shares_b = 0;
+    user_position.rent_payer = rent_payer;
+    user_position.promo_invested = 0;
+    user_position.bump = position_bump;
+
+    stream.unique_bettors = stream
+        .unique_bettors
+        .checked_add(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    if user_profile.user == Pubkey::default() {
+        user_profile.user = user;
+        user_profile.lifetime_volume = 0;
+        user_profile.streams_entered = 0;
+        user_profile.wins = 0;
+        user_profile.losses = 0;
+        user_profile.realized_pnl = 0;
+        user_profile.bump = profile_bump;
+    }
+    user_profile.streams_entered = user_profile
+        .streams_entered
+        .checked_add(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(())
+}
+
+/// Shares of `team_id` the position may sell, swap or escrow; shares bought
+/// with promo credit only ever pay out at resolution, and shares held for
+/// resting limit sells are spoken for
+pub fn sellable_shares(user_position: &UserPosition, team_id: u8) -> u64 {
+    if team_id == 1 {
+        user_position
+            .team_a_shares
+            .saturating_sub(user_position.promo_shares_a)
+            .saturating_sub(user_position.reserved_shares_a)
+    } else {
+        user_position
+            .team_b_shares
+            .saturating_sub(user_position.promo_shares_b)
+            .saturating_sub(user_position.reserved_shares_b)
+    }
+}
+
+/// Whether a position may be closed with its rent going to its owner: no
+/// orders or reveals still settle against it, and the owner paid for it
+pub fn owner_can_close(user_position: &UserPosition) -> bool {
+    user_position.open_orders == 0
+        && user_position.pending_invested == 0
+        && user_position.rent_payer == user_position.user
+}
+
+/// The position's count of `team_id` shares held for resting limit sells
+pub fn reserved_shares(user_position: &mut UserPosition, team_id: u8) -> &mut u64 {
+    if team_id == 1 {
+        &mut user_position.reserved_shares_a
+    } else {
+        &mut user_position.reserved_shares_b
+    }
+}
+
+/// Take a sale's proceeds off the position's net invested lamports. A
+/// profitable exit takes it to zero, not below
+pub fn reduce_cost_basis(user_position: &mut UserPosition, sol_out: u64) {
+    user_position.total_invested = user_position.total_invested.saturating_sub(sol_out);
+}
+
+/// Lamports the position holds against the stream's position limit: the
+/// user's own net invested plus the promo credit spent on it
+pub fn position_stake(user_position: &UserPosition) -> Result<u64> {
+    Ok(user_position
+        .total_invested
+        .checked_add(user_position.promo_invested)
+        .ok_or(ErrorCode::MathOverflow)?)
+}
+
+/// Enforce the stream's bet limits on a purchase
+/// `position_invested` is the user's net invested lamports including this bet
+pub fn check_bet_limits(
+    stream: &Stream,
+    sol_amount: u64,
+    position_invested: u64,
+    price_impact_bps: u64,
+) -> Result<()> {
+    require!(sol_amount >= stream.min_bet, ErrorCode::BetBelowMinimum);
+    require!(
+        stream.max_bet == 0 || sol_amount <= stream.max_bet,
+        ErrorCode::BetAboveMaximum
+    );
+    require!(
+        stream.max_position == 0 || position_invested <= stream.max_position,
+        ErrorCode::PositionLimitExceeded
+    );
+    require!(
+        stream.max_price_impact_bps == 0 || price_impact_bps <= stream.max_price_impact_bps as u64,
+        ErrorCode::PriceImpactTooHigh
+    );
+
+    Ok(())
+}
+
+/// Split the trading fee on a `sol_amount` purchase into (fee, referrer's share)
+pub fn calculate_fees(stream: &Stream, sol_amount: u64, referred: bool) -> Result<(u64, u64)> {
+    let fee = (sol_amount as u128)
+        .checked_mul(stream.fee_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / 10_000;
+    let referral_fee = if referred {
+        fee.checked_mul(stream.referral_share_bps as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            / 10_000
+    } else {
+        0
+    };
+
+    Ok((fee as u64, referral_fee as u64))
+}
+
+/// Check the referrer passed with a buy against the position. A position
+/// keeps the referrer it was first referred through, and every later buy
+/// that can carry a referral must carry that one
+pub fn check_referral(
+    user_position: &UserPosition,
+    user: Pubkey,
+    referrer: Option<Pubkey>,
+) -> Result<()> {
+    match referrer {
+        Some(referrer) => {
+            require!(referrer != user, ErrorCode::SelfReferral);
+            require!(
+                user_position.referrer == Pubkey::default() || user_position.referrer == referrer,
+                ErrorCode::ReferrerMismatch
+            );
+        }
+        None => require!(
+            user_position.referrer == Pubkey::default(),
+            ErrorCode::ReferrerMismatch
+        ),
+    }
+
+    Ok(())
+}
+
+/// Book a buy's trading fee. The protocol's share stays in the vault but
+/// outside the pool; the referrer's share is credited to their account
+pub fn book_fees(
+    stream: &mut Stream,
+    referral: Option<&mut Referral>,
+    sol_amount: u64,
+    fee: u64,
+    referral_fee: u64,
+) -> Result<()> {
+    stream.fees_collected = stream
+        .fees_collected
+        .checked_add(fee - referral_fee)
+        .ok_or(ErrorCode::MathOverflow)?;
+    if let Some(referral) = referral {
+        referral.referred_volume = referral
+            .referred_volume
+            .checked_add(sol_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        referral.total_earned = referral
+            .total_earned
+            .checked_add(referral_fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+
+    Ok(())
+}
+
+/// The streamer's cut of the protocol fees collected so far
+pub fn streamer_cut(stream: &Stream) -> Result<u64> {
+    Ok(((stream.fees_collected as u128)
+        .checked_mul(stream.streamer_cut_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / 10_000) as u64)
+}
+
+/// Fail if the circuit breaker is holding trading halted at `slot`
+pub fn check_circuit_breaker(stream: &Stream, slot: u64) -> Result<()> {
+    require!(slot >= stream.halted_until_slot, ErrorCode::TradingHalted);
+
+    Ok(())
+}
+
+/// The breaker window a trade tripped, as it stood before the reset
+pub struct BreakerTrip {
+    pub move_bps: u64,
+    pub ref_prices: (u64, u64),
+    pub window_start_slot: u64,
+}
+
+/// Measure a trade against the breaker window, opening a new window when the
+/// previous one has lapsed. The trade that trips the breaker still executes;
+/// only later trades are halted. A trip also closes the window, so trading
+/// resumes measured from the post-trip prices rather than the stale reference
+pub fn update_circuit_breaker(
+    stream: &mut Stream,
+    slot: u64,
+    prices_before: (u64, u64),
+    prices_after: (u64, u64),
+) -> Result<Option<BreakerTrip>> {
+    if stream.breaker_threshold_bps == 0 {
+        return Ok(None);
+    }
+
+    let window_lapsed = stream.breaker_window_start_slot == 0
+        || slot.saturating_sub(stream.breaker_window_start_slot) > stream.breaker_window_slots;
+    if window_lapsed {
+        stream.breaker_window_start_slot = slot;
+        (stream.breaker_ref_price_a, stream.breaker_ref_price_b) = prices_before;
+    }
+
+    let move_bps = calculate_price_impact_bps(stream.breaker_ref_price_a, prices_after.0)?.max(
+        calculate_price_impact_bps(stream.breaker_ref_price_b, prices_after.1)?,
+    );
+    if move_bps <= stream.breaker_threshold_bps as u64 {
+        return Ok(None);
+    }
+
+    let trip = BreakerTrip {
+        move_bps,
+        ref_prices: (stream.breaker_ref_price_a, stream.breaker_ref_price_b),
+        window_start_slot: stream.breaker_window_start_slot,
+    };
+    stream.halted_until_slot = slot
+        .checked_add(stream.breaker_cooldown_slots)
+        .ok_or(ErrorCode::MathOverflow)?;
+    stream.breaker_window_start_slot = 0;
+    (stream.breaker_ref_price_a, stream.breaker_ref_price_b) = prices_after;
+
+    Ok(Some(trip))
+}
+
+/// Spot prices of (team A, team B) for the given reserves
+pub fn team_prices(team_a_reserve: u64, team_b_reserve: u64) -> Result<(u64, u64)> {
+    Ok((
+        calculate_price(team_a_reserve, team_b_reserve)?,
+        calculate_price(team_b_reserve, team_a_reserve)?,
+    ))
+}
+
+/// Cumulative prices as of `now`, extrapolating the current spot price over the
+/// time since the last trade. TWAP over [t0, t1] = (cumulative(t1) - cumulative(t0)) / (t1 - t0)
+pub fn current_cumulative_prices(stream: &Stream, now: i64) -> Result<(u128, u128)> {
+    let elapsed = now.saturating_sub(stream.last_price_update).max(0) as u128;
+    if elapsed == 0 {
+        return Ok((
+            stream.team_a_price_cumulative,
+            stream.team_b_price_cumulative,
+        ));
+    }
+
+    let (price_a, price_b) = team_prices(stream.team_a_reserve, stream.team_b_reserve)?;
+
+    let team_a_cumulative = (price_a as u128)
+        .checked_mul(elapsed)
+        .and_then(|delta| stream.team_a_price_cumulative.checked_add(delta))
+        .ok_or(ErrorCode::MathOverflow)?;
+    let team_b_cumulative = (price_b as u128)
+        .checked_mul(elapsed)
+        .and_then(|delta| stream.team_b_price_cumulative.checked_add(delta))
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok((team_a_cumulative, team_b_cumulative))
+}
+
+/// Roll the TWAP accumulators forward to `now`
+/// Must run before a trade mutates the reserves
+pub fn accumulate_prices(stream: &mut Stream, now: i64) -> Result<()> {
+    require!(
+        stream.layout_version == STREAM_LAYOUT_VERSION,
+        ErrorCode::StreamNotMigrated
+    );
+    let (team_a_cumulative, team_b_cumulative) = current_cumulative_prices(stream, now)?;
+
+    stream.team_a_price_cumulative = team_a_cumulative;
+    stream.team_b_price_cumulative = team_b_cumulative;
+    stream.last_price_update = now;
+
+    Ok(())
+}
+
+/// A position's winning shares and its pro-rata cut of the pool. Both are
+/// zero for a position on the losing team
+pub fn winning_payout(stream: &Stream, user_position: &UserPosition) -> Result<(u64, u64)> {
+    let (user_winning_shares, total_winning_shares) = if stream.winning_team == 1 {
+        (user_position.team_a_shares, stream.team_a_shares_sold)
+    } else {
+        (user_position.team_b_shares, stream.team_b_shares_sold)
+    };
+    if user_winning_shares == 0 {
+        return Ok((0, 0));
+    }
+
+    let payout = calculate_payout(stream.total_pool, user_winning_shares, total_winning_shares)?;
+    Ok((user_winning_shares, payout))
+}
+
+/// Transfer `amount` out of a stream's system-owned vault to `to`, signing
+/// with the vault's seeds
+pub fn pay_from_vault<'info>(
+    system_program: &AccountInfo<'info>,
+    stream_vault: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    stream_id: u64,
+    vault_bump: u8,
+    amount: u64,
+) -> Result<()> {
+    let stream_id_bytes = stream_id.to_le_bytes();
+    let seeds = &[
+        b"stream_vault".as_ref(),
+        stream_id_bytes.as_ref(),
+        &[vault_bump],
+    ];
+    let signer_seeds = &[&seeds[..]];
+
+    let transfer_ctx = CpiContext::new_with_signer(
+        system_program.clone(),
+        anchor_lang::system_program::Transfer {
+            from: stream_vault.clone(),
+            to: to.clone(),
+        },
+        signer_seeds,
+    );
+    anchor_lang::system_program::transfer(transfer_ctx, amount)
+}
+
+/// Check that `signer` may trade `user`'s position: the owner themselves, or
+/// the session key named on a session token the owner created and has not
+/// let expire
+pub fn check_trader(
+    user: Pubkey,
+    signer: Pubkey,
+    session_token: Option<&SessionToken>,
+    now: i64,
+) -> Result<()> {
+    match session_token {
+        Some(session_token) => {
+            require!(
+                session_token.user == user && session_token.session_signer == signer,
+                ErrorCode::InvalidSession
+            );
+            require!(now < session_token.expires_at, ErrorCode::SessionExpired);
+        }
+        None => require!(signer == user, ErrorCode::Unauthorized),
+    }
+    Ok(())
+}
+
+/// Take `amount` out of the budget escrowed on a session token, counting it
+/// against the spend limit. The caller credits the lamports to their destination
+pub fn spend_session<'info>(
+    session_token: &mut Account<'info, SessionToken>,
+    amount: u64,
+) -> Result<()> {
+    session_token.spent = session_token
+        .spent
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(
+        session_token.spent <= session_token.spend_limit,
+        ErrorCode::SessionLimitExceeded
+    );
+    session_token.sub_lamports(amount)?;
+    Ok(())
+}
+
+/// Pay `amount` to `to` for a trader: from the session budget when a session
+/// key trades, otherwise from the owner's wallet, which must sign
+pub fn pay_from_trader<'info>(
+    system_program: &AccountInfo<'info>,
+    user: &AccountInfo<'info>,
+    session_token: Option<&mut Account<'info, SessionToken>>,
+    to: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    match session_token {
+        Some(session_token) => {
+            spend_session(session_token, amount)?;
+            to.add_lamports(amount)?;
+            Ok(())
+        }
+        None => {
+            let cpi_context = CpiContext::new(
+                system_program.clone(),
+                anchor_lang::system_program::Transfer {
+                    from: user.clone(),
+                    to: to.clone(),
+                },
+            );
+            anchor_lang::system_program::transfer(cpi_context, amount)
+        }
+    }
+}
+
+/// Grow a program-owned account created under an older layout to `new_len`,
+/// topping up rent from `payer`. Appended fields read as zero afterwards
+pub fn grow_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    new_len: usize,
+) -> Result<()> {
+    let old_len = account.data_len();
+    require!(old_len < new_len, ErrorCode::AlreadyMigrated);
+
+    let rent = Rent::get()?
+        .minimum_balance(new_len)
+        .saturating_sub(account.lamports());
+    if rent > 0 {
+        let cpi_context = CpiContext::new(
+            system_program.clone(),
+            anchor_lang::system_program::Transfer {
+                from: payer.clone(),
+                to: account.clone(),
+            },
+        );
+        anchor_lang::system_program::transfer(cpi_context, rent)?;
+    }
+
+    account.resize(new_len)?;
+    account.try_borrow_mut_data()?[old_len..].fill(0);
+
+    Ok(())
+}
+
 /// Calculate the invariant k = reserve_a × reserve_b
 /// Used for validation
 pub fn calculate_invariant(reserve_a: u64, reserve_b: u64) -> Result<u128> {
@@ -96,3 +679,552 @@ pub fn calculate_invariant(reserve_a: u64, reserve_b: u64) -> Result<u128> {
         .checked_mul(reserve_b as u128)
         .ok_or(ErrorCode::MathOverflow.into())
 }
+
+/// Calculate a winner's share of the pool
+/// Formula: payout = total_pool × user_shares / total_winning_shares
+pub fn calculate_payout(
+    total_pool: u64,
+    user_shares: u64,
+    total_winning_shares: u64,
+) -> Result<u64> {
+    let payout = (total_pool as u128)
+        .checked_mul(user_shares as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(total_winning_shares as u128)
+        .ok_or(ErrorCode::MathOverflow)? as u64;
+
+    Ok(payout)
+}
+
+/// Leaf of the payout Merkle tree
+/// Leaf = sha256("prophecy_payout" || stream_id || user || payout)
+pub fn payout_leaf(stream_id: u64, user: &Pubkey, payout: u64) -> [u8; 32] {
+    hashv(&[
+        b"prophecy_payout",
+        stream_id.to_le_bytes().as_ref(),
+        user.as_ref(),
+        payout.to_le_bytes().as_ref(),
+    ])
+    .to_bytes()
+}
+
+/// Hash a bet is committed under before it is revealed
+/// Hash = sha256("prophecy_commit" || stream_id || user || team_id || amount || salt)
+pub fn commitment_hash(
+    stream_id: u64,
+    user: &Pubkey,
+    team_id: u8,
+    amount: u64,
+    salt: &[u8; 32],
+) -> [u8; 32] {
+    hashv(&[
+        b"prophecy_commit",
+        stream_id.to_le_bytes().as_ref(),
+        user.as_ref(),
+        &[team_id],
+        amount.to_le_bytes().as_ref(),
+        salt.as_ref(),
+    ])
+    .to_bytes()
+}
+
+/// Reveal window (start, end) of the batch whose commit phase contains `now`
+/// Batches repeat every commit_secs + reveal_secs from commit_reveal_start
+pub fn commit_window(stream: &Stream, now: i64) -> Result<(i64, i64)> {
+    require!(stream.commit_secs > 0, ErrorCode::CommitRevealDisabled);
+
+    let period = stream
+        .commit_secs
+        .checked_add(stream.reveal_secs)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let elapsed = now
+        .checked_sub(stream.commit_reveal_start)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(elapsed >= 0, ErrorCode::NotInCommitPhase);
+
+    let offset = elapsed % period;
+    require!(offset < stream.commit_secs, ErrorCode::NotInCommitPhase);
+
+    let reveal_start = now - offset + stream.commit_secs;
+    let reveal_end = reveal_start
+        .checked_add(stream.reveal_secs)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok((reveal_start, reveal_end))
+}
+
+/// End of the auction batch collecting orders at `now`
+/// Batches are consecutive auction_interval_secs slices from auction_start
+pub fn auction_batch_end(stream: &Stream, now: i64) -> Result<i64> {
+    require!(
+        stream.auction_interval_secs > 0,
+        ErrorCode::AuctionModeDisabled
+    );
+
+    let elapsed = now
+        .checked_sub(stream.auction_start)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(elapsed >= 0, ErrorCode::InvalidBatch);
+
+    let batches = elapsed / stream.auction_interval_secs + 1;
+    batches
+        .checked_mul(stream.auction_interval_secs)
+        .and_then(|span| span.checked_add(stream.auction_start))
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Fill one team's batched buys and sells at a single uniform price
+/// Orders cross each other at the average price the net flow pays on the curve:
+///   net buy:  N = (R_team × B − S × R_opp) / (S + R_team), curve shares x = N × R_team / (R_opp + N)
+///   net sell: M = (R_opp × S − B × R_team) / (B + R_opp), curve lamports g = M × R_opp / (R_team + M)
+/// where B is lamports bid and S is shares offered.
+/// Returns (shares to buyers, lamports to sellers, team reserve after, opposite reserve after)
+pub fn clear_auction_side(
+    buy_sol: u64,
+    sell_shares: u64,
+    reserve_team: u64,
+    reserve_opposite: u64,
+) -> Result<(u64, u64, u64, u64)> {
+    require!(
+        reserve_team > 0 && reserve_opposite > 0,
+        ErrorCode::InvalidPrice
+    );
+
+    let bid = (reserve_team as u128)
+        .checked_mul(buy_sol as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let offer = (sell_shares as u128)
+        .checked_mul(reserve_opposite as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    if bid > offer {
+        let net_sol = ((bid - offer) / (sell_shares as u128 + reserve_team as u128)) as u64;
+        let curve_shares = ((net_sol as u128 * reserve_team as u128)
+            / (reserve_opposite as u128 + net_sol as u128)) as u64;
+
+        Ok((
+            sell_shares
+                .checked_add(curve_shares)
+                .ok_or(ErrorCode::MathOverflow)?,
+            buy_sol - net_sol,
+            reserve_team - curve_shares,
+            reserve_opposite
+                .checked_add(net_sol)
+                .ok_or(ErrorCode::MathOverflow)?,
+        ))
+    } else {
+        let net_shares = ((offer - bid) / (buy_sol as u128 + reserve_opposite as u128)) as u64;
+        let curve_sol = ((net_shares as u128 * reserve_opposite as u128)
+            / (reserve_team as u128 + net_shares as u128)) as u64;
+
+        Ok((
+            sell_shares - net_shares,
+            buy_sol
+                .checked_add(curve_sol)
+                .ok_or(ErrorCode::MathOverflow)?,
+            reserve_team
+                .checked_add(net_shares)
+                .ok_or(ErrorCode::MathOverflow)?,
+            reserve_opposite - curve_sol,
+        ))
+    }
+}
+
+/// Fills of an auction batch cleared as one trade on the curve
+pub struct BatchFill {
+    pub team_a_fee: u64,
+    pub team_b_fee: u64,
+    pub team_a_buy_sol: u64, // Lamports bought with, after the fee
+    pub team_b_buy_sol: u64,
+    pub team_a_sell_shares: u64, // Shares sold
+    pub team_b_sell_shares: u64,
+    pub team_a_buy_fill: u64,
+    pub team_a_sell_fill: u64,
+    pub team_b_buy_fill: u64,
+    pub team_b_sell_fill: u64,
+    pub team_a_reserve_after: u64,
+    pub team_b_reserve_after: u64,
+}
+
+/// `fill_bps` of `amount`, rounded down
+pub fn scale_bps(amount: u64, fill_bps: u16) -> u64 {
+    (amount as u128 * fill_bps as u128 / 10_000) as u64
+}
+
+/// Clear `fill_bps` of a batch's flow as a single trade on the curve. `buy_sol`
+/// and `sell_shares` are (team A, team B) totals. Team A buys and team B sells
+/// both pay into team B's reserve and take out of team A's, while team B buys
+/// and team A sells do the reverse, so the two directions cross at one price
+/// and only their residual moves the reserves
+pub fn clear_batch_flow(
+    stream: &Stream,
+    buy_sol: (u64, u64),
+    sell_shares: (u64, u64),
+    fill_bps: u16,
+) -> Result<BatchFill> {
+    let team_a_gross = scale_bps(buy_sol.0, fill_bps);
+    let team_b_gross = scale_bps(buy_sol.1, fill_bps);
+    // Buyers on a side pay the trading fee once on their combined lamports;
+    // batched fills carry no referral
+    let (team_a_fee, _) = calculate_fees(stream, team_a_gross, false)?;
+    let (team_b_fee, _) = calculate_fees(stream, team_b_gross, false)?;
+    let team_a_buy_sol = team_a_gross - team_a_fee;
+    let team_b_buy_sol = team_b_gross - team_b_fee;
+    let team_a_sell_shares = scale_bps(sell_shares.0, fill_bps);
+    let team_b_sell_shares = scale_bps(sell_shares.1, fill_bps);
+
+    let into_b = team_a_buy_sol
+        .checked_add(team_b_sell_shares)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let into_a = team_b_buy_sol
+        .checked_add(team_a_sell_shares)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let (out_of_a, out_of_b, team_a_reserve_after, team_b_reserve_after) =
+        clear_auction_side(into_b, into_a, stream.team_a_reserve, stream.team_b_reserve)?;
+
+    // Each direction's output splits pro rata over what went in
+    let split = |out: u64, part: u64, total: u64| -> u64 {
+        if total == 0 {
+            0
+        } else {
+            (out as u128 * part as u128 / total as u128) as u64
+        }
+    };
+    let team_a_buy_fill = split(out_of_a, team_a_buy_sol, into_b);
+    let team_b_buy_fill = split(out_of_b, team_b_buy_sol, into_a);
+
+    Ok(BatchFill {
+        team_a_fee,
+        team_b_fee,
+        team_a_buy_sol,
+        team_b_buy_sol,
+        team_a_sell_shares,
+        team_b_sell_shares,
+        team_a_buy_fill,
+        team_a_sell_fill: split(out_of_b, team_a_sell_shares, into_a),
+        team_b_buy_fill,
+        team_b_sell_fill: split(out_of_a, team_b_sell_shares, into_b),
+        team_a_reserve_after,
+        team_b_reserve_after,
+    })
+}
+
+impl BatchFill {
+    /// Lamports traded on each team, as (team_id, volume) for its candle
+    pub fn volumes(&self) -> [(u8, u64); 2] {
+        [
+            (1, self.team_a_buy_sol.saturating_add(self.team_a_sell_fill)),
+            (2, self.team_b_buy_sol.saturating_add(self.team_b_sell_fill)),
+        ]
+    }
+}
+
+/// Book a cleared batch's fees, reserves, shares sold, volumes and pool as one
+/// trade. Escrowed sell shares are already off positions, so shares sold moves
+/// by what buyers receive less what sellers gave up
+pub fn apply_batch_fill(stream: &mut Stream, fill: &BatchFill) -> Result<()> {
+    let fee = fill
+        .team_a_fee
+        .checked_add(fill.team_b_fee)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let gross_buy = fill
+        .team_a_buy_sol
+        .checked_add(fill.team_b_buy_sol)
+        .and_then(|net| net.checked_add(fee))
+        .ok_or(ErrorCode::MathOverflow)?;
+    book_fees(stream, None, gross_buy, fee, 0)?;
+
+    stream.team_a_reserve = fill.team_a_reserve_after;
+    stream.team_b_reserve = fill.team_b_reserve_after;
+    stream.team_a_shares_sold = stream
+        .team_a_shares_sold
+        .checked_add(fill.team_a_buy_fill)
+        .and_then(|sold| sold.checked_sub(fill.team_a_sell_shares))
+        .ok_or(ErrorCode::MathOverflow)?;
+    stream.team_b_shares_sold = stream
+        .team_b_shares_sold
+        .checked_add(fill.team_b_buy_fill)
+        .and_then(|sold| sold.checked_sub(fill.team_b_sell_shares))
+        .ok_or(ErrorCode::MathOverflow)?;
+    stream.team_a_buy_volume = stream
+        .team_a_buy_volume
+        .checked_add(fill.team_a_buy_sol)
+        .ok_or(ErrorCode::MathOverflow)?;
+    stream.team_b_buy_volume = stream
+        .team_b_buy_volume
+        .checked_add(fill.team_b_buy_sol)
+        .ok_or(ErrorCode::MathOverflow)?;
+    stream.team_a_sell_volume = stream
+        .team_a_sell_volume
+        .checked_add(fill.team_a_sell_fill)
+        .ok_or(ErrorCode::MathOverflow)?;
+    stream.team_b_sell_volume = stream
+        .team_b_sell_volume
+        .checked_add(fill.team_b_sell_fill)
+        .ok_or(ErrorCode::MathOverflow)?;
+    stream.total_pool = stream
+        .total_pool
+        .checked_add(fill.team_a_buy_sol)
+        .and_then(|pool| pool.checked_add(fill.team_b_buy_sol))
+        .and_then(|pool| pool.checked_sub(fill.team_a_sell_fill))
+        .and_then(|pool| pool.checked_sub(fill.team_b_sell_fill))
+        .ok_or(ErrorCode::MathOverflow)?;
+    stream.trade_seq = stream
+        .trade_seq
+        .checked_add(1)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(())
+}
+
+/// Larger of the two teams' price impact moving between two sets of reserves
+pub fn reserves_impact_bps(before: (u64, u64), after: (u64, u64)) -> Result<u64> {
+    let prices_before = team_prices(before.0, before.1)?;
+    let prices_after = team_prices(after.0, after.1)?;
+    Ok(calculate_price_impact_bps(prices_before.0, prices_after.0)?
+        .max(calculate_price_impact_bps(prices_before.1, prices_after.1)?))
+}
+
+/// Largest share of a batch, in bps, whose fill stays within `max_impact_bps`
+/// (zero for no limit). `impact_at` gives the price impact of filling that
+/// share of every order; it grows with the share, so a bisection finds it
+pub fn fill_within_impact(
+    max_impact_bps: u16,
+    impact_at: impl Fn(u16) -> Result<u64>,
+) -> Result<u16> {
+    let max_impact_bps = max_impact_bps as u64;
+    if max_impact_bps == 0 || impact_at(10_000)? <= max_impact_bps {
+        return Ok(10_000);
+    }
+
+    let (mut within, mut over) = (0u16, 10_000u16);
+    while over - within > 1 {
+        let mid = within + (over - within) / 2;
+        if impact_at(mid)? <= max_impact_bps {
+            within = mid;
+        } else {
+            over = mid;
+        }
+    }
+    Ok(within)
+}
+
+/// Average price of a fill, on the calculate_price scale
+/// Formula: price = lamports × 1e9 / shares
+pub fn average_fill_price(lamports: u64, shares: u64) -> Result<u64> {
+    require!(shares > 0, ErrorCode::InvalidPrice);
+
+    let price = (lamports as u128)
+        .checked_mul(1_000_000_000)
+        .ok_or(ErrorCode::MathOverflow)?
+        / shares as u128;
+
+    u64::try_from(price).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+/// Verify a Merkle proof against a root
+/// Sibling pairs are hashed in sorted order so proofs carry no direction bits
+pub fn verify_merkle_proof(proof: &[[u8; 32]], root: [u8; 32], leaf: [u8; 32]) -> bool {
+    let computed = proof.iter().fold(leaf, |node, sibling| {
+        if node <= *sibling {
+            hashv(&[node.as_ref(), sibling.as_ref()]).to_bytes()
+        } else {
+            hashv(&[sibling.as_ref(), node.as_ref()]).to_bytes()
+        }
+    });
+
+    computed == root
+}
+
+/// Calculate how far a trade moves the price, in basis points of the price before
+/// Formula: impact_bps = |price_after - price_before| × 10_000 / price_before
+pub fn calculate_price_impact_bps(price_before: u64, price_after: u64) -> Result<u64> {
+    require!(price_before > 0, ErrorCode::InvalidPrice);
+
+    let delta = price_before.abs_diff(price_after);
+
+    let impact_bps = (delta as u128)
+        .checked_mul(10_000)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(price_before as u128)
+        .ok_or(ErrorCode::MathOverflow)? as u64;
+
+    Ok(impact_bps)
+}
+
+/// Quote a purchase against the current reserves without mutating the stream
+pub fn quote_buy(stream: &Stream, team_id: u8, sol_amount: u64) -> Result<BuyQuote> {
+    require!(team_id == 1 || team_id == 2, ErrorCode::InvalidTeam);
+    require!(sol_amount > 0, ErrorCode::InvalidAmount);
+
+    let (reserve_team, reserve_opposite) = if team_id == 1 {
+        (stream.team_a_reserve, stream.team_b_reserve)
+    } else {
+        (stream.team_b_reserve, stream.team_a_reserve)
+    };
+
+    let (fee, _) = calculate_fees(stream, sol_amount, false)?;
+    let net_amount = sol_amount - fee;
+
+    let price_before = calculate_price(reserve_team, reserve_opposite)?;
+    let shares_out = calculate_shares_out(net_amount, reserve_team, reserve_opposite)?;
+
+    let reserve_team_after = reserve_team
+        .checked_sub(shares_out)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let reserve_opposite_after = reserve_opposite
+        .checked_add(net_amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let price_after = calculate_price(reserve_team_after, reserve_opposite_after)?;
+
+    Ok(BuyQuote {
+        shares_out,
+        price_before,
+        price_after,
+        price_impact_bps: calculate_price_impact_bps(price_before, price_after)?,
+        fee,
+    })
+}
+
+/// Quote a sale against the current reserves without mutating the stream
+pub fn quote_sell(stream: &Stream, team_id: u8, shares_amount: u64) -> Result<SellQuote> {
+    require!(team_id == 1 || team_id == 2, ErrorCode::InvalidTeam);
+    require!(shares_amount > 0, ErrorCode::InvalidAmount);
+
+    let (reserve_team, reserve_opposite) = if team_id == 1 {
+        (stream.team_a_reserve, stream.team_b_reserve)
+    } else {
+        (stream.team_b_reserve, stream.team_a_reserve)
+    };
+
+    let price_before = calculate_price(reserve_team, reserve_opposite)?;
+    let sol_out = calculate_sol_out(shares_amount, reserve_team, reserve_opposite)?;
+
+    let reserve_team_after = reserve_team
+        .checked_add(shares_amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let reserve_opposite_after = reserve_opposite
+        .checked_sub(sol_out)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let price_after = calculate_price(reserve_team_after, reserve_opposite_after)?;
+
+    Ok(SellQuote {
+        sol_out,
+        price_before,
+        price_after,
+        price_impact_bps: calculate_price_impact_bps(price_before, price_after)?,
+        fee: 0,
+    })
+}
+
+/// Quote what a position would receive from claim_winnings. A posted payout
+/// root does not change the amount: a proof pays the same pro-rata cut
+pub fn quote_claim(stream: &Stream, user_position: &UserPosition) -> Result<ClaimQuote> {
+    require!(!stream.is_active, ErrorCode::StreamStillActive);
+    require!(stream.winning_team != 0, ErrorCode::NoWinnerDeclared);
+
+    let (winning_shares, payout) = winning_payout(stream, user_position)?;
+
+    Ok(ClaimQuote {
+        winning_team: stream.winning_team,
+        winning_shares,
+        payout: if user_position.has_claimed { 0 } else { payout },
+        has_claimed: user_position.has_claimed,
+        root_posted: stream.payout_root != [0u8; 32],
+    })
+}
+
+/// Fold a trade into the stream's candle history
+/// Opens a new candle when the trade falls in a later interval than the head,
+/// seeding its open from the prices before the trade
+pub fn record_candle(
+    history: &mut PriceHistory,
+    now: i64,
+    team_id: u8,
+    volume: u64,
+    prices_before: (u64, u64),
+    prices_after: (u64, u64),
+) -> Result<()> {
+    require!(history.interval_secs > 0, ErrorCode::InvalidDuration);
+
+    let bucket_start = now
+        .checked_sub(now.rem_euclid(history.interval_secs))
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    let head = history.head as usize;
+    if history.count == 0 || history.candles[head].start_time != bucket_start {
+        let next = if history.count == 0 {
+            0
+        } else {
+            (head + 1) % PRICE_HISTORY_LEN
+        };
+        let (open_a, open_b) = prices_before;
+        history.candles[next] = Candle {
+            start_time: bucket_start,
+            team_a: Ohlcv {
+                open: open_a,
+                high: open_a,
+                low: open_a,
+                close: open_a,
+                volume: 0,
+            },
+            team_b: Ohlcv {
+                open: open_b,
+                high: open_b,
+                low: open_b,
+                close: open_b,
+                volume: 0,
+            },
+        };
+        history.head = next as u32;
+        history.count = (history.count + 1).min(PRICE_HISTORY_LEN as u32);
+    }
+
+    let candle = &mut history.candles[history.head as usize];
+    let (price_a, price_b) = prices_after;
+    for (ohlcv, price) in [(&mut candle.team_a, price_a), (&mut candle.team_b, price_b)] {
+        ohlcv.high = ohlcv.high.max(price);
+        ohlcv.low = ohlcv.low.min(price);
+        ohlcv.close = price;
+    }
+
+    let traded = if team_id == 1 {
+        &mut candle.team_a
+    } else {
+        &mut candle.team_b
+    };
+    traded.volume = traded
+        .volume
+        .checked_add(volume)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(())
+}
+
+/// Fold a finished position into the owner's profile
+/// PnL is the payout minus what the position still had invested after sells
+pub fn record_result(
+    profile: &mut UserProfile,
+    position: &mut UserPosition,
+    won: bool,
+    payout: u64,
+) -> Result<i64> {
+    let pnl = i64::try_from(payout as i128 - position.total_invested as i128)
+        .map_err(|_| ErrorCode::MathOverflow)?;
+
+    if won {
+        profile.wins = profile.wins.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        profile.losses = profile
+            .losses
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    profile.realized_pnl = profile
+        .realized_pnl
+        .checked_add(pnl)
+        .ok_or(ErrorCode::MathOverflow)?;
+    position.profile_settled = true;
+
+    Ok(pnl)
+}
@@ -55,16 +55,488 @@ pub mod prediction_market {
         handlers::sell_shares_handler(ctx, stream_id, team_id, shares_amount)
     }
 
+    /// Name the admin allowed to issue global promo credits (upgrade authority only)
+    pub fn set_admin(ctx: Context<SetAdmin>, admin: Pubkey) -> Result<()> {
+        handlers::set_admin_handler(ctx, admin)
+    }
+
+    /// Fund a free-bet credit for `user`, scoped to `stream_id` or to every stream when zero
+    pub fn issue_promo_credit(
+        ctx: Context<IssuePromoCredit>,
+        user: Pubkey,
+        stream_id: u64,
+        amount: u64,
+    ) -> Result<()> {
+        handlers::issue_promo_credit_handler(ctx, user, stream_id, amount)
+    }
+
+    /// Close a promo credit and return its unspent balance to the issuer
+    pub fn revoke_promo_credit(
+        ctx: Context<RevokePromoCredit>,
+        user: Pubkey,
+        stream_id: u64,
+    ) -> Result<()> {
+        handlers::revoke_promo_credit_handler(ctx, user, stream_id)
+    }
+
+    /// Create the account that collects a referrer's share of trading fees
+    pub fn register_referrer(ctx: Context<RegisterReferrer>) -> Result<()> {
+        handlers::register_referrer_handler(ctx)
+    }
+
+    /// Withdraw the referral fees earned so far
+    pub fn claim_referral_fees(ctx: Context<ClaimReferralFees>) -> Result<()> {
+        handlers::claim_referral_fees_handler(ctx)
+    }
+
+    /// Authorize an ephemeral key to trade until `expires_at`, escrowing `spend_limit` lamports.
+    /// Trading instructions accept the key as their signer when the session token is passed
+    pub fn create_session(
+        ctx: Context<CreateSession>,
+        session_signer: Pubkey,
+        expires_at: i64,
+        spend_limit: u64,
+    ) -> Result<()> {
+        handlers::create_session_handler(ctx, session_signer, expires_at, spend_limit)
+    }
+
+    /// Revoke the session key and reclaim the unspent budget
+    pub fn revoke_session(ctx: Context<RevokeSession>) -> Result<()> {
+        handlers::revoke_session_handler(ctx)
+    }
+
+    /// Sell `pct_bps` of the shares held in one team (or both when `team_id` is 0).
+    /// Closes the position once it is empty and nothing else settles against it
+    pub fn cash_out(
+        ctx: Context<CashOut>,
+        stream_id: u64,
+        team_id: u8,
+        pct_bps: u16,
+    ) -> Result<()> {
+        handlers::cash_out_handler(ctx, stream_id, team_id, pct_bps)
+    }
+
+    /// Move shares from one team to the other in place; lamports stay in the vault
+    pub fn swap_sides(
+        ctx: Context<SwapSides>,
+        stream_id: u64,
+        from_team: u8,
+        shares_amount: u64,
+        min_shares_out: u64,
+    ) -> Result<()> {
+        handlers::swap_sides_handler(ctx, stream_id, from_team, shares_amount, min_shares_out)
+    }
+
+    /// Configure per-bet and per-user limits, zero disables a limit (authority only)
+    pub fn set_bet_limits(
+        ctx: Context<SetBetLimits>,
+        stream_id: u64,
+        min_bet: u64,
+        max_bet: u64,
+        max_position: u64,
+        max_price_impact_bps: u16,
+    ) -> Result<()> {
+        handlers::set_bet_limits_handler(
+            ctx,
+            stream_id,
+            min_bet,
+            max_bet,
+            max_position,
+            max_price_impact_bps,
+        )
+    }
+
+    /// Grow a stream created under an older layout and fill in the appended fields
+    pub fn migrate_stream(ctx: Context<MigrateStream>, stream_id: u64) -> Result<()> {
+        handlers::migrate_stream_handler(ctx, stream_id)
+    }
+
+    /// Grow a position created under an older layout; appended fields start at zero
+    pub fn migrate_position(
+        ctx: Context<MigratePosition>,
+        stream_id: u64,
+        user: Pubkey,
+    ) -> Result<()> {
+        handlers::migrate_position_handler(ctx, stream_id, user)
+    }
+
+    /// Set the purchase fee and the referrer's share of it, in bps (authority only)
+    pub fn set_fees(
+        ctx: Context<SetFees>,
+        stream_id: u64,
+        fee_bps: u16,
+        referral_share_bps: u16,
+    ) -> Result<()> {
+        handlers::set_fees_handler(ctx, stream_id, fee_bps, referral_share_bps)
+    }
+
+    /// Set the streamer payout key and their cut of protocol fees, in bps (authority only)
+    pub fn set_streamer(
+        ctx: Context<SetStreamer>,
+        stream_id: u64,
+        streamer: Pubkey,
+        streamer_cut_bps: u16,
+    ) -> Result<()> {
+        handlers::set_streamer_handler(ctx, stream_id, streamer, streamer_cut_bps)
+    }
+
+    /// Send a tip straight to the stream's streamer
+    pub fn tip_streamer(ctx: Context<TipStreamer>, stream_id: u64, amount: u64) -> Result<()> {
+        handlers::tip_streamer_handler(ctx, stream_id, amount)
+    }
+
+    /// Pay the streamer their cut of protocol fees once the stream has ended
+    pub fn pay_streamer(ctx: Context<PayStreamer>, stream_id: u64) -> Result<()> {
+        handlers::pay_streamer_handler(ctx, stream_id)
+    }
+
+    /// Withdraw the protocol's share of fees once the stream has ended, paying
+    /// the streamer's cut first if it is still owed (authority only)
+    pub fn withdraw_fees(ctx: Context<WithdrawFees>, stream_id: u64) -> Result<()> {
+        handlers::withdraw_fees_handler(ctx, stream_id)
+    }
+
+    /// Configure the circuit breaker, zero threshold disables it; also lifts any halt (authority only).
+    /// The trade that trips the breaker executes and only later trades are halted
+    pub fn set_circuit_breaker(
+        ctx: Context<SetCircuitBreaker>,
+        stream_id: u64,
+        threshold_bps: u16,
+        window_slots: u64,
+        cooldown_slots: u64,
+    ) -> Result<()> {
+        handlers::set_circuit_breaker_handler(
+            ctx,
+            stream_id,
+            threshold_bps,
+            window_slots,
+            cooldown_slots,
+        )
+    }
+
+    /// Enable commit-reveal betting with the given phase lengths, zeros disable it (authority only)
+    pub fn set_commit_reveal(
+        ctx: Context<SetCommitReveal>,
+        stream_id: u64,
+        commit_secs: i64,
+        reveal_secs: i64,
+    ) -> Result<()> {
+        handlers::set_commit_reveal_handler(ctx, stream_id, commit_secs, reveal_secs)
+    }
+
+    /// Commit to a hidden bet for the batch revealing until `reveal_end`, escrowing lamports
+    pub fn commit_bet(
+        ctx: Context<CommitBet>,
+        stream_id: u64,
+        reveal_end: i64,
+        hash: [u8; 32],
+        escrow: u64,
+    ) -> Result<()> {
+        handlers::commit_bet_handler(ctx, stream_id, reveal_end, hash, escrow)
+    }
+
+    /// Reveal a committed bet into its batch, refunding unused escrow
+    pub fn reveal_bet(
+        ctx: Context<RevealBet>,
+        stream_id: u64,
+        reveal_end: i64,
+        team_id: u8,
+        amount: u64,
+        salt: [u8; 32],
+    ) -> Result<()> {
+        handlers::reveal_bet_handler(ctx, stream_id, reveal_end, team_id, amount, salt)
+    }
+
+    /// Fill a closed batch's reveals at one price per team; past the price impact
+    /// limit each reveal fills in part (permissionless)
+    pub fn clear_commit_batch(
+        ctx: Context<ClearCommitBatch>,
+        stream_id: u64,
+        reveal_end: i64,
+    ) -> Result<()> {
+        handlers::clear_commit_batch_handler(ctx, stream_id, reveal_end)
+    }
+
+    /// Credit a cleared commitment's shares to the user's position, returning any
+    /// unfilled lamports
+    pub fn settle_commitment(
+        ctx: Context<SettleCommitment>,
+        stream_id: u64,
+        reveal_end: i64,
+    ) -> Result<()> {
+        handlers::settle_commitment_handler(ctx, stream_id, reveal_end)
+    }
+
+    /// Refund an unrevealed commitment, or a revealed one whose batch went a whole
+    /// commit-reveal period uncleared or outlived the stream
+    pub fn cancel_commitment(
+        ctx: Context<CancelCommitment>,
+        stream_id: u64,
+        reveal_end: i64,
+    ) -> Result<()> {
+        handlers::cancel_commitment_handler(ctx, stream_id, reveal_end)
+    }
+
+    /// Switch to frequent batch auctions of `interval_secs`, zero restores continuous trading (authority only)
+    pub fn set_auction_mode(
+        ctx: Context<SetAuctionMode>,
+        stream_id: u64,
+        interval_secs: i64,
+    ) -> Result<()> {
+        handlers::set_auction_mode_handler(ctx, stream_id, interval_secs)
+    }
+
+    /// Place a buy (lamports) or sell (shares) order into the open auction batch
+    pub fn submit_order(
+        ctx: Context<SubmitOrder>,
+        stream_id: u64,
+        batch_end: i64,
+        team_id: u8,
+        is_buy: bool,
+        amount: u64,
+    ) -> Result<()> {
+        handlers::submit_order_handler(ctx, stream_id, batch_end, team_id, is_buy, amount)
+    }
+
+    /// Fill every order in a closed batch at one price per team; past the price impact
+    /// limit each order fills in part (permissionless)
+    pub fn clear_batch(ctx: Context<ClearBatch>, stream_id: u64, batch_end: i64) -> Result<()> {
+        handlers::clear_batch_handler(ctx, stream_id, batch_end)
+    }
+
+    /// Pay out a cleared order to its owner, returning any unfilled part (permissionless)
+    pub fn settle_order(ctx: Context<SettleOrder>, stream_id: u64, batch_end: i64) -> Result<()> {
+        handlers::settle_order_handler(ctx, stream_id, batch_end)
+    }
+
+    /// Withdraw an order from an open batch, or from one left uncleared for a whole
+    /// interval or past the stream's end
+    pub fn cancel_order(ctx: Context<CancelOrder>, stream_id: u64, batch_end: i64) -> Result<()> {
+        handlers::cancel_order_handler(ctx, stream_id, batch_end)
+    }
+
+    /// Rest a buy (lamports) or sell (shares) order until the price crosses `limit_price`.
+    /// Sold shares stay reserved on the position, so they still pay out on a win
+    pub fn place_limit_order(
+        ctx: Context<PlaceLimitOrder>,
+        stream_id: u64,
+        order_id: u64,
+        team_id: u8,
+        is_buy: bool,
+        amount: u64,
+        limit_price: u64,
+    ) -> Result<()> {
+        handlers::place_limit_order_handler(
+            ctx,
+            stream_id,
+            order_id,
+            team_id,
+            is_buy,
+            amount,
+            limit_price,
+        )
+    }
+
+    /// Fill crossed limit orders passed as (order, position, profile, owner) quads (permissionless).
+    /// Each fill is an ordinary trade; orders the stream's limits would reject stay resting
+    pub fn match_orders<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MatchOrders<'info>>,
+        stream_id: u64,
+    ) -> Result<()> {
+        handlers::match_orders_handler(ctx, stream_id)
+    }
+
+    /// Cancel a limit order and return its escrow
+    pub fn cancel_limit_order(
+        ctx: Context<CancelLimitOrder>,
+        stream_id: u64,
+        order_id: u64,
+    ) -> Result<()> {
+        handlers::cancel_limit_order_handler(ctx, stream_id, order_id)
+    }
+
+    /// Offer a head-to-head bet at fixed odds, escrowing the creator's stake.
+    /// A default `counterparty` leaves the challenge open to anyone
+    pub fn create_challenge(
+        ctx: Context<CreateChallenge>,
+        stream_id: u64,
+        challenge_id: u64,
+        team_id: u8,
+        creator_stake: u64,
+        counterparty_stake: u64,
+        counterparty: Pubkey,
+    ) -> Result<()> {
+        handlers::create_challenge_handler(
+            ctx,
+            stream_id,
+            challenge_id,
+            team_id,
+            creator_stake,
+            counterparty_stake,
+            counterparty,
+        )
+    }
+
+    /// Take the other side of a challenge, escrowing the counterparty stake
+    pub fn accept_challenge(
+        ctx: Context<AcceptChallenge>,
+        stream_id: u64,
+        challenge_id: u64,
+    ) -> Result<()> {
+        handlers::accept_challenge_handler(ctx, stream_id, challenge_id)
+    }
+
+    /// Withdraw a challenge nobody has accepted yet
+    pub fn cancel_challenge(
+        ctx: Context<CancelChallenge>,
+        stream_id: u64,
+        challenge_id: u64,
+    ) -> Result<()> {
+        handlers::cancel_challenge_handler(ctx, stream_id, challenge_id)
+    }
+
+    /// Pay an accepted challenge to the winner, or refund both sides once the stream is voided
+    pub fn settle_challenge(
+        ctx: Context<SettleChallenge>,
+        stream_id: u64,
+        challenge_id: u64,
+    ) -> Result<()> {
+        handlers::settle_challenge_handler(ctx, stream_id, challenge_id)
+    }
+
+    /// Attach a stop-loss / take-profit to one team's shares, escrowing a keeper tip
+    pub fn set_trigger(
+        ctx: Context<SetTrigger>,
+        stream_id: u64,
+        team_id: u8,
+        stop_loss_price: u64,
+        take_profit_price: u64,
+        max_slippage_bps: u16,
+        tip: u64,
+    ) -> Result<()> {
+        handlers::set_trigger_handler(
+            ctx,
+            stream_id,
+            team_id,
+            stop_loss_price,
+            take_profit_price,
+            max_slippage_bps,
+            tip,
+        )
+    }
+
+    /// Sell a position whose trigger price has been crossed (permissionless, tipped)
+    pub fn execute_trigger(
+        ctx: Context<ExecuteTrigger>,
+        stream_id: u64,
+        team_id: u8,
+    ) -> Result<()> {
+        handlers::execute_trigger_handler(ctx, stream_id, team_id)
+    }
+
+    /// Remove a trigger and reclaim its tip
+    pub fn cancel_trigger(ctx: Context<CancelTrigger>, stream_id: u64, team_id: u8) -> Result<()> {
+        handlers::cancel_trigger_handler(ctx, stream_id, team_id)
+    }
+
     /// End the stream and declare a winner
     pub fn end_stream(ctx: Context<EndStream>, stream_id: u64, winning_team: u8) -> Result<()> {
         handlers::end_stream_handler(ctx, stream_id, winning_team)
     }
 
+    /// Call the stream off instead of declaring a winner (authority only). Trading
+    /// stops, accepted challenges refund both sides and positions refund with claim_refund
+    pub fn void_stream(ctx: Context<EndStream>, stream_id: u64) -> Result<()> {
+        handlers::void_stream_handler(ctx, stream_id)
+    }
+
+    /// Reclaim the position's net invested lamports from a voided stream
+    pub fn claim_refund(ctx: Context<ClaimRefund>, stream_id: u64) -> Result<()> {
+        handlers::claim_refund_handler(ctx, stream_id)
+    }
+
     /// Claim winnings after stream has ended
     pub fn claim_winnings(ctx: Context<ClaimWinnings>, stream_id: u64) -> Result<()> {
         handlers::claim_winnings_handler(ctx, stream_id)
     }
 
+    /// Claim winnings on a user's behalf; the relayer pays the fees, the user gets the payout
+    pub fn relay_claim(ctx: Context<RelayClaim>, stream_id: u64) -> Result<()> {
+        handlers::relay_claim_handler(ctx, stream_id)
+    }
+
+    /// Push winnings to many positions at once (permissionless). Remaining accounts
+    /// are (position, profile, owner) triples; results are recorded before any close. Only
+    /// positions the owner paid for, with nothing left settling against them, are closed
+    pub fn settle_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SettleBatch<'info>>,
+        stream_id: u64,
+        close_positions: bool,
+    ) -> Result<()> {
+        handlers::settle_batch_handler(ctx, stream_id, close_positions)
+    }
+
+    /// Post the Merkle root of off-chain computed payouts, committing `payout_total`
+    /// lamports in all (authority only, once, before any winner is paid)
+    pub fn post_payout_root(
+        ctx: Context<PostPayoutRoot>,
+        stream_id: u64,
+        payout_root: [u8; 32],
+        payout_total: u64,
+    ) -> Result<()> {
+        handlers::post_payout_root_handler(ctx, stream_id, payout_root, payout_total)
+    }
+
+    /// Claim winnings with a Merkle proof against the posted payout root; the proven
+    /// payout must match what the claimant's position would receive
+    pub fn claim_with_proof(
+        ctx: Context<ClaimWithProof>,
+        stream_id: u64,
+        payout: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        handlers::claim_with_proof_handler(ctx, stream_id, payout, proof)
+    }
+
+    /// Record a finished position's result in its owner's profile (permissionless)
+    pub fn settle_profile(ctx: Context<SettleProfile>, stream_id: u64, user: Pubkey) -> Result<()> {
+        handlers::settle_profile_handler(ctx, stream_id, user)
+    }
+
+    /// Simulate a purchase; the quote is returned via return data
+    pub fn quote_buy(
+        ctx: Context<QuoteTrade>,
+        stream_id: u64,
+        team_id: u8,
+        sol_amount: u64,
+    ) -> Result<BuyQuote> {
+        handlers::quote_buy_handler(ctx, stream_id, team_id, sol_amount)
+    }
+
+    /// Simulate a sale; the quote is returned via return data
+    pub fn quote_sell(
+        ctx: Context<QuoteTrade>,
+        stream_id: u64,
+        team_id: u8,
+        shares_amount: u64,
+    ) -> Result<SellQuote> {
+        handlers::quote_sell_handler(ctx, stream_id, team_id, shares_amount)
+    }
+
+    /// Simulate a claim; the quote is returned via return data
+    pub fn quote_claim(
+        ctx: Context<QuoteClaim>,
+        stream_id: u64,
+        user: Pubkey,
+    ) -> Result<ClaimQuote> {
+        handlers::quote_claim_handler(ctx, stream_id, user)
+    }
+
+    /// Read the TWAP accumulators as of now; the observation is returned via return data
+    pub fn observe_prices(ctx: Context<QuoteTrade>, stream_id: u64) -> Result<PriceObservation> {
+        handlers::observe_prices_handler(ctx, stream_id)
+    }
+
     /// Emergency withdraw (authority only)
     pub fn emergency_withdraw(ctx: Context<EmergencyWithdraw>, stream_id: u64) -> Result<()> {
         handlers::emergency_withdraw_handler(ctx, stream_id)
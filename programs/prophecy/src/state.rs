@@ -1,6 +1,10 @@
-
 use anchor_lang::prelude::*;
 
+pub const PRICE_HISTORY_LEN: usize = 96; // Keeps the account under the 10KB CPI init limit
+pub const PRICE_HISTORY_INTERVAL_SECS: i64 = 60;
+pub const MAX_FEE_BPS: u16 = 1_000;
+pub const STREAM_LAYOUT_VERSION: u8 = 1;
+
 #[account]
 #[derive(InitSpace)]
 pub struct Stream {
@@ -10,14 +14,14 @@ pub struct Stream {
     pub team_a_name: String,
     #[max_len(32)]
     pub team_b_name: String,
-    
-    pub team_a_reserve: u64,  // Virtual liquidity for Team A
-    pub team_b_reserve: u64,  // Virtual liquidity for Team B
-    
+
+    pub team_a_reserve: u64, // Virtual liquidity for Team A
+    pub team_b_reserve: u64, // Virtual liquidity for Team B
+
     pub team_a_shares_sold: u64,
     pub team_b_shares_sold: u64,
-    
-    pub total_pool: u64,        
+
+    pub total_pool: u64,
     pub start_time: i64,
     pub end_time: i64,
     pub is_active: bool,
@@ -25,6 +29,77 @@ pub struct Stream {
     #[max_len(256)]
     pub stream_link: String,
     pub bump: u8,
+
+    // Everything below was appended after launch. Streams created before it
+    // read layout_version 0 and are brought up to date by migrate_stream
+    pub layout_version: u8,
+    pub trade_seq: u64, // Incremented on every buy and sell
+
+    // Uniswap v2 style TWAP accumulators: Σ price × seconds held
+    pub team_a_price_cumulative: u128,
+    pub team_b_price_cumulative: u128,
+    pub last_price_update: i64,
+
+    // Activity stats, in lamports unless noted
+    pub team_a_buy_volume: u64,
+    pub team_b_buy_volume: u64,
+    pub team_a_sell_volume: u64,
+    pub team_b_sell_volume: u64,
+    pub unique_bettors: u32, // Count of UserPositions created
+    pub largest_bet: u64,
+    pub last_trade_time: i64,
+
+    // Bet limits set by the authority, zero disables a limit
+    pub min_bet: u64,
+    pub max_bet: u64,
+    pub max_position: u64, // Cap on a user's net lamports invested
+    pub max_price_impact_bps: u16,
+
+    // Trading fee on purchase_shares, taken out of the bet before it reaches the
+    // curve; referral_share_bps of it goes to the buyer's referrer
+    pub fee_bps: u16,
+    pub referral_share_bps: u16,
+    pub fees_collected: u64, // Protocol share, held in the vault outside total_pool
+
+    // Circuit breaker: halts trading when either team's price moves more than
+    // breaker_threshold_bps within breaker_window_slots; zero threshold disables it.
+    // The tripping trade executes, and the window closes so trading resumes
+    // measured from the post-trip prices
+    pub breaker_threshold_bps: u16,
+    pub breaker_window_slots: u64,
+    pub breaker_cooldown_slots: u64,
+    pub breaker_window_start_slot: u64, // Zero when no window is open
+    pub breaker_ref_price_a: u64,       // Team A price when the window opened
+    pub breaker_ref_price_b: u64,       // Team B price when the window opened
+    pub halted_until_slot: u64,
+
+    // Commit-reveal mode: bets are committed as hashes, revealed in the
+    // following window and filled together per batch; zero commit_secs disables it
+    pub commit_secs: i64,
+    pub reveal_secs: i64,
+    pub commit_reveal_start: i64, // Anchor of the commit/reveal schedule
+
+    // Frequent batch auction mode: orders collect per interval and clear at one
+    // price per team; zero interval keeps continuous trading
+    pub auction_interval_secs: i64,
+    pub auction_start: i64, // Anchor of the auction schedule
+
+    // Streamer behind stream_link: receives tips directly and, once the stream
+    // ends, streamer_cut_bps of the protocol's fees_collected, booked into
+    // streamer_owed by end_stream before any winnings can be claimed
+    pub streamer: Pubkey,
+    pub streamer_cut_bps: u16,
+    pub streamer_tips: u64, // Lifetime lamports tipped
+    pub streamer_paid: bool,
+
+    // Merkle root of (user, payout) pairs, zero if unset. A proof only pays what
+    // the claimant's position would, position claims stay open alongside it,
+    // and proofs may pay out at most payout_root_total
+    pub payout_root: [u8; 32],
+    pub payout_root_total: u64,
+    pub winnings_paid: u64, // Lamports paid to winners through any claim path
+    pub is_voided: bool,    // Called off by the authority instead of resolved; bets are refunded
+    pub streamer_owed: u64, // Streamer's cut taken out of fees_collected at end_stream, until paid
 }
 
 #[account]
@@ -37,4 +112,264 @@ pub struct UserPosition {
     pub total_invested: u64,
     pub has_claimed: bool,
     pub bump: u8,
-}
\ No newline at end of file
+
+    // Appended after launch; migrate_position grows older positions, which
+    // then read these as zero
+    pub profile_settled: bool, // Result folded into the owner's UserProfile
+    pub promo_shares_a: u64,   // Bought with promo credit; paid out on a win, never sold
+    pub promo_shares_b: u64,
+    pub referrer: Pubkey, // Set on the first referred purchase, default if none
+    pub pending_invested: u64, // Revealed commit-reveal lamports not yet settled
+    pub open_orders: u16, // Resting limit orders and unsettled batch orders
+    pub reserved_shares_a: u64, // Held for resting limit sells; still paid out on a win
+    pub reserved_shares_b: u64,
+    pub rent_payer: Pubkey, // Funded the account; default for positions opened before it was recorded
+    pub promo_invested: u64, // Promo credit spent here; counts toward max_position, not the cost basis
+}
+
+/// Lifetime stats for one wallet across every stream
+#[account]
+#[derive(InitSpace)]
+pub struct UserProfile {
+    pub user: Pubkey,
+    pub lifetime_volume: u64, // Lamports bought plus lamports sold
+    pub streams_entered: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub realized_pnl: i64, // Payouts minus net invested, over settled streams
+    pub bump: u8,
+}
+
+/// A hidden bet and its escrow, keyed by the reveal window it belongs to
+#[account]
+#[derive(InitSpace)]
+pub struct Commitment {
+    pub user: Pubkey,
+    pub stream_id: u64,
+    pub hash: [u8; 32], // See helpers::commitment_hash
+    pub escrow: u64,    // Lamports escrowed; the revealed amount once revealed
+    pub reveal_start: i64,
+    pub reveal_end: i64, // Also identifies the CommitBatch
+    pub team_id: u8,     // Zero until revealed
+    pub bump: u8,
+}
+
+/// Bets revealed in one window, filled together at one price per team
+#[account]
+#[derive(InitSpace)]
+pub struct CommitBatch {
+    pub stream_id: u64,
+    pub reveal_end: i64,
+    pub team_a_sol: u64,
+    pub team_b_sol: u64,
+    pub team_a_shares: u64, // Set when the batch clears
+    pub team_b_shares: u64,
+    pub reveals: u32,
+    pub cleared: bool,
+    pub bump: u8,
+    pub fill_bps: u16, // Share of every reveal filled; the rest is returned at settle
+}
+
+/// Orders collected for one auction interval, cleared together.
+/// Buy sides hold lamports, sell sides hold shares; fills are set on clearing
+#[account]
+#[derive(InitSpace)]
+pub struct OrderBatch {
+    pub stream_id: u64,
+    pub batch_end: i64,
+    pub team_a_buy_sol: u64,
+    pub team_a_sell_shares: u64,
+    pub team_b_buy_sol: u64,
+    pub team_b_sell_shares: u64,
+    pub team_a_buy_fill: u64,  // Shares owed to team A buyers
+    pub team_a_sell_fill: u64, // Lamports owed to team A sellers
+    pub team_b_buy_fill: u64,
+    pub team_b_sell_fill: u64,
+    pub orders: u32,
+    pub cleared: bool,
+    pub bump: u8,
+    pub fill_bps: u16, // Share of every order filled; the rest is returned at settle
+}
+
+/// One user's order in an auction batch, escrowed until settled or cancelled
+#[account]
+#[derive(InitSpace)]
+pub struct BatchOrder {
+    pub user: Pubkey,
+    pub stream_id: u64,
+    pub batch_end: i64,
+    pub team_id: u8,
+    pub is_buy: bool,
+    pub amount: u64, // Lamports for a buy, shares for a sell
+    pub bump: u8,
+}
+
+/// Program-wide settings, written by the upgrade authority. `admin` is the
+/// only wallet that may issue promo credits valid on every stream
+#[account]
+#[derive(InitSpace)]
+pub struct Config {
+    pub admin: Pubkey,
+    pub bump: u8,
+}
+
+/// Marks a winner paid through a payout proof, so each leaf pays out once
+#[account]
+#[derive(InitSpace)]
+pub struct PayoutReceipt {
+    pub stream_id: u64,
+    pub user: Pubkey,
+    pub payout: u64,
+    pub bump: u8,
+}
+
+/// Free-bet lamports escrowed by `issuer` for one user, spendable only on
+/// purchases in `stream_id`, or in any stream when it is zero
+#[account]
+#[derive(InitSpace)]
+pub struct PromoCredit {
+    pub user: Pubkey,
+    pub issuer: Pubkey,
+    pub stream_id: u64,
+    pub balance: u64, // Unspent lamports
+    pub issued: u64,  // Lifetime lamports issued
+    pub bump: u8,
+}
+
+/// A referrer's share of trading fees across every stream. Earned fees are
+/// escrowed here as lamports until the referrer claims them
+#[account]
+#[derive(InitSpace)]
+pub struct Referral {
+    pub referrer: Pubkey,
+    pub referred_volume: u64, // Lamports bet by referred users
+    pub total_earned: u64,
+    pub total_claimed: u64,
+    pub bump: u8,
+}
+
+/// A head-to-head bet outside the AMM. The creator escrows `creator_stake` on
+/// `team_id`; the counterparty escrows `counterparty_stake` on the other team,
+/// and the winner takes both
+#[account]
+#[derive(InitSpace)]
+pub struct Challenge {
+    pub stream_id: u64,
+    pub challenge_id: u64, // Chosen by the creator, unique per creator and stream
+    pub creator: Pubkey,
+    pub counterparty: Pubkey, // Only this wallet may accept, default if open to anyone
+    pub acceptor: Pubkey,     // Default until accepted
+    pub team_id: u8,          // Creator's team
+    pub creator_stake: u64,
+    pub counterparty_stake: u64,
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+/// Lets an ephemeral key trade on a user's behalf until `expires_at`. Bets
+/// are paid from `spend_limit` lamports escrowed here; payouts go to `user`
+#[account]
+#[derive(InitSpace)]
+pub struct SessionToken {
+    pub user: Pubkey,
+    pub session_signer: Pubkey,
+    pub expires_at: i64,
+    pub spend_limit: u64,
+    pub spent: u64,
+    pub bump: u8,
+}
+
+/// A resting order that fills against the curve once the price crosses
+/// `limit_price`. Buys escrow lamports on this account, sells escrow shares
+#[account]
+#[derive(InitSpace)]
+pub struct LimitOrder {
+    pub user: Pubkey,
+    pub stream_id: u64,
+    pub order_id: u64, // Chosen by the user, unique per user and stream
+    pub team_id: u8,
+    pub is_buy: bool,
+    pub amount: u64,      // Lamports for a buy, shares for a sell
+    pub limit_price: u64, // Same scale as calculate_price
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+/// Stop-loss / take-profit on one team's side of a position. Any keeper may
+/// sell the whole side once the price crosses a threshold; the tip rides on this account
+#[account]
+#[derive(InitSpace)]
+pub struct PositionTrigger {
+    pub user: Pubkey,
+    pub stream_id: u64,
+    pub team_id: u8,
+    pub stop_loss_price: u64,   // Sell at or below, zero disables
+    pub take_profit_price: u64, // Sell at or above, zero disables
+    pub max_slippage_bps: u16,  // Worst average fill versus the crossed threshold
+    pub tip: u64,               // Lamports paid to the executing keeper
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+#[zero_copy]
+#[derive(Default, Debug)]
+pub struct Ohlcv {
+    pub open: u64,
+    pub high: u64,
+    pub low: u64,
+    pub close: u64,
+    pub volume: u64, // Lamports traded on this team during the interval
+}
+
+#[zero_copy]
+#[derive(Default, Debug)]
+pub struct Candle {
+    pub start_time: i64,
+    pub team_a: Ohlcv,
+    pub team_b: Ohlcv,
+}
+
+/// Ring buffer of per-interval candles for one stream.
+/// `head` is the slot of the most recent candle; `count` stops at PRICE_HISTORY_LEN
+#[account(zero_copy)]
+pub struct PriceHistory {
+    pub stream_id: u64,
+    pub interval_secs: i64,
+    pub head: u32,
+    pub count: u32,
+    pub candles: [Candle; PRICE_HISTORY_LEN],
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct BuyQuote {
+    pub shares_out: u64,
+    pub price_before: u64,
+    pub price_after: u64,
+    pub price_impact_bps: u64,
+    pub fee: u64, // Trading fee taken out of sol_amount
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct SellQuote {
+    pub sol_out: u64,
+    pub price_before: u64,
+    pub price_after: u64,
+    pub price_impact_bps: u64,
+    pub fee: u64, // Sales are not charged a fee
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct PriceObservation {
+    pub timestamp: i64,
+    pub team_a_price_cumulative: u128,
+    pub team_b_price_cumulative: u128,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct ClaimQuote {
+    pub winning_team: u8,
+    pub winning_shares: u64,
+    pub payout: u64,
+    pub has_claimed: bool,
+    pub root_posted: bool, // Also claimable with a proof against the stream's payout root
+}
@@ -0,0 +1,101 @@
+use std::mem::size_of;
+
+use anchor_lang::error::ErrorCode;
+use anchor_lang::{AccountDeserialize, Discriminator, Result};
+
+use crate::{
+    BatchOrder, Challenge, CommitBatch, Commitment, LimitOrder, OrderBatch, PositionTrigger,
+    PriceHistory, PromoCredit, Referral, SessionToken, Stream, UserPosition, UserProfile,
+};
+
+/// Deserialize raw `Stream` account data, checking the discriminator
+pub fn deserialize_stream(data: &[u8]) -> Result<Stream> {
+    let mut data = data;
+    Stream::try_deserialize(&mut data)
+}
+
+/// Deserialize raw `UserPosition` account data, checking the discriminator
+pub fn deserialize_user_position(data: &[u8]) -> Result<UserPosition> {
+    let mut data = data;
+    UserPosition::try_deserialize(&mut data)
+}
+
+/// Deserialize raw `UserProfile` account data, checking the discriminator
+pub fn deserialize_user_profile(data: &[u8]) -> Result<UserProfile> {
+    let mut data = data;
+    UserProfile::try_deserialize(&mut data)
+}
+
+/// Deserialize raw `Challenge` account data, checking the discriminator
+pub fn deserialize_challenge(data: &[u8]) -> Result<Challenge> {
+    let mut data = data;
+    Challenge::try_deserialize(&mut data)
+}
+
+/// Deserialize raw `Commitment` account data, checking the discriminator
+pub fn deserialize_commitment(data: &[u8]) -> Result<Commitment> {
+    let mut data = data;
+    Commitment::try_deserialize(&mut data)
+}
+
+/// Deserialize raw `CommitBatch` account data, checking the discriminator
+pub fn deserialize_commit_batch(data: &[u8]) -> Result<CommitBatch> {
+    let mut data = data;
+    CommitBatch::try_deserialize(&mut data)
+}
+
+/// Deserialize raw `OrderBatch` account data, checking the discriminator
+pub fn deserialize_order_batch(data: &[u8]) -> Result<OrderBatch> {
+    let mut data = data;
+    OrderBatch::try_deserialize(&mut data)
+}
+
+/// Deserialize raw `BatchOrder` account data, checking the discriminator
+pub fn deserialize_batch_order(data: &[u8]) -> Result<BatchOrder> {
+    let mut data = data;
+    BatchOrder::try_deserialize(&mut data)
+}
+
+/// Deserialize raw `LimitOrder` account data, checking the discriminator
+pub fn deserialize_limit_order(data: &[u8]) -> Result<LimitOrder> {
+    let mut data = data;
+    LimitOrder::try_deserialize(&mut data)
+}
+
+/// Deserialize raw `PositionTrigger` account data, checking the discriminator
+pub fn deserialize_position_trigger(data: &[u8]) -> Result<PositionTrigger> {
+    let mut data = data;
+    PositionTrigger::try_deserialize(&mut data)
+}
+
+/// Deserialize raw `PromoCredit` account data, checking the discriminator
+pub fn deserialize_promo_credit(data: &[u8]) -> Result<PromoCredit> {
+    let mut data = data;
+    PromoCredit::try_deserialize(&mut data)
+}
+
+/// Deserialize raw `Referral` account data, checking the discriminator
+pub fn deserialize_referral(data: &[u8]) -> Result<Referral> {
+    let mut data = data;
+    Referral::try_deserialize(&mut data)
+}
+
+/// Deserialize raw `SessionToken` account data, checking the discriminator
+pub fn deserialize_session_token(data: &[u8]) -> Result<SessionToken> {
+    let mut data = data;
+    SessionToken::try_deserialize(&mut data)
+}
+
+/// Deserialize raw `PriceHistory` account data, checking the discriminator.
+/// RPC buffers carry no alignment guarantee, so the zero-copy body is copied out unaligned
+pub fn deserialize_price_history(data: &[u8]) -> Result<PriceHistory> {
+    let disc = PriceHistory::DISCRIMINATOR;
+    if data.len() < disc.len() + size_of::<PriceHistory>() {
+        return Err(ErrorCode::AccountDidNotDeserialize.into());
+    }
+    if &data[..disc.len()] != disc {
+        return Err(ErrorCode::AccountDiscriminatorMismatch.into());
+    }
+    let body = &data[disc.len()..disc.len() + size_of::<PriceHistory>()];
+    bytemuck::try_pod_read_unaligned(body).map_err(|_| ErrorCode::AccountDidNotDeserialize.into())
+}
@@ -0,0 +1,1345 @@
+//! Typed builders for every `prediction_market` instruction.
+
+use anchor_lang::prelude::{AccountMeta, Pubkey};
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::system_program;
+use anchor_lang::{InstructionData, ToAccountMetas};
+use prophecy::{accounts, instruction};
+
+use crate::pda::{
+    batch_order_pda, challenge_pda, commit_batch_pda, commitment_pda, config_pda,
+    event_authority_pda, limit_order_pda, order_batch_pda, payout_receipt_pda,
+    position_trigger_pda, price_history_pda, program_data_pda, promo_credit_pda, referral_pda,
+    session_token_pda, stream_pda, stream_vault_pda, user_position_pda, user_profile_pda,
+};
+use crate::PROGRAM_ID;
+
+fn build(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: PROGRAM_ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+/// Who signs a trade on a user's position
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trader {
+    /// The position owner, signing with their own wallet
+    Owner(Pubkey),
+    /// `session_signer` trading for `user` under the session token `user`
+    /// created; bets and escrow come out of the session budget
+    Session {
+        user: Pubkey,
+        session_signer: Pubkey,
+    },
+}
+
+impl Trader {
+    /// The position owner
+    pub fn user(&self) -> Pubkey {
+        match *self {
+            Trader::Owner(user) | Trader::Session { user, .. } => user,
+        }
+    }
+
+    /// The key that signs the instruction
+    pub fn signer(&self) -> Pubkey {
+        match *self {
+            Trader::Owner(user) => user,
+            Trader::Session { session_signer, .. } => session_signer,
+        }
+    }
+
+    /// The session token authorizing the signer, when a session key trades
+    pub fn session_token(&self) -> Option<Pubkey> {
+        match self {
+            Trader::Owner(_) => None,
+            Trader::Session { user, .. } => Some(session_token_pda(user).0),
+        }
+    }
+}
+
+impl From<Pubkey> for Trader {
+    fn from(user: Pubkey) -> Self {
+        Trader::Owner(user)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_stream(
+    authority: Pubkey,
+    stream_id: u64,
+    team_a_name: String,
+    team_b_name: String,
+    initial_liquidity: u64,
+    stream_duration: i64,
+    stream_link: String,
+) -> Instruction {
+    build(
+        accounts::InitializeStream {
+            stream: stream_pda(stream_id).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            price_history: price_history_pda(stream_id).0,
+            authority,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::InitializeStream {
+            stream_id,
+            team_a_name,
+            team_b_name,
+            initial_liquidity,
+            stream_duration,
+            stream_link,
+        },
+    )
+}
+
+/// Bring a stream created under an older account layout up to date
+pub fn migrate_stream(payer: Pubkey, stream_id: u64) -> Instruction {
+    build(
+        accounts::MigrateStream {
+            stream: stream_pda(stream_id).0,
+            price_history: price_history_pda(stream_id).0,
+            payer,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::MigrateStream { stream_id },
+    )
+}
+
+/// Bring `user`'s position created under an older account layout up to date
+pub fn migrate_position(payer: Pubkey, stream_id: u64, user: Pubkey) -> Instruction {
+    build(
+        accounts::MigratePosition {
+            user_position: user_position_pda(stream_id, &user).0,
+            payer,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::MigratePosition { stream_id, user },
+    )
+}
+
+pub fn purchase_shares(
+    trader: impl Into<Trader>,
+    stream_id: u64,
+    team_id: u8,
+    sol_amount: u64,
+) -> Instruction {
+    let trader = trader.into();
+    sponsored_purchase_shares(trader.signer(), trader, stream_id, team_id, sol_amount)
+}
+
+/// Buy for `trader` while `payer` covers rent for a new position or profile.
+/// Both must sign
+pub fn sponsored_purchase_shares(
+    payer: Pubkey,
+    trader: impl Into<Trader>,
+    stream_id: u64,
+    team_id: u8,
+    sol_amount: u64,
+) -> Instruction {
+    purchase(
+        payer,
+        trader.into(),
+        stream_id,
+        team_id,
+        sol_amount,
+        None,
+        None,
+    )
+}
+
+/// Buy through `referrer`, who earns a share of the trading fee. The first
+/// referred purchase ties the position to that referrer, and every later
+/// buy on it must go through them too
+pub fn referred_purchase_shares(
+    trader: impl Into<Trader>,
+    referrer: Pubkey,
+    stream_id: u64,
+    team_id: u8,
+    sol_amount: u64,
+) -> Instruction {
+    let trader = trader.into();
+    purchase(
+        trader.signer(),
+        trader,
+        stream_id,
+        team_id,
+        sol_amount,
+        None,
+        Some(referral_pda(&referrer).0),
+    )
+}
+
+/// Buy with the promo credit scoped to `credit_stream_id` (zero for a global credit).
+/// The shares pay out on a win but can never be sold. `referrer` must be the
+/// position's referrer once it has one
+pub fn purchase_with_promo_credit(
+    user: Pubkey,
+    stream_id: u64,
+    team_id: u8,
+    sol_amount: u64,
+    credit_stream_id: u64,
+    referrer: Option<Pubkey>,
+) -> Instruction {
+    purchase(
+        user,
+        Trader::Owner(user),
+        stream_id,
+        team_id,
+        sol_amount,
+        Some(promo_credit_pda(&user, credit_stream_id).0),
+        referrer.map(|referrer| referral_pda(&referrer).0),
+    )
+}
+
+fn purchase(
+    payer: Pubkey,
+    trader: Trader,
+    stream_id: u64,
+    team_id: u8,
+    sol_amount: u64,
+    promo_credit: Option<Pubkey>,
+    referral: Option<Pubkey>,
+) -> Instruction {
+    let user = trader.user();
+    build(
+        accounts::PurchaseShares {
+            stream: stream_pda(stream_id).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            user_profile: user_profile_pda(&user).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            price_history: price_history_pda(stream_id).0,
+            promo_credit,
+            referral,
+            payer,
+            user,
+            signer: trader.signer(),
+            session_token: trader.session_token(),
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::PurchaseShares {
+            stream_id,
+            team_id,
+            sol_amount,
+        },
+    )
+}
+
+pub fn sell_shares(
+    trader: impl Into<Trader>,
+    stream_id: u64,
+    team_id: u8,
+    shares_amount: u64,
+) -> Instruction {
+    let trader = trader.into();
+    let user = trader.user();
+    build(
+        accounts::SellShares {
+            stream: stream_pda(stream_id).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            user_profile: user_profile_pda(&user).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            price_history: price_history_pda(stream_id).0,
+            user,
+            signer: trader.signer(),
+            session_token: trader.session_token(),
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SellShares {
+            stream_id,
+            team_id,
+            shares_amount,
+        },
+    )
+}
+
+/// Name the wallet allowed to issue global promo credits. `authority` must be
+/// the program's upgrade authority
+pub fn set_admin(authority: Pubkey, admin: Pubkey) -> Instruction {
+    build(
+        accounts::SetAdmin {
+            config: config_pda().0,
+            program_data: program_data_pda().0,
+            authority,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SetAdmin { admin },
+    )
+}
+
+/// Fund `amount` lamports of free bets for `user`; `stream_id` zero makes the
+/// credit usable on any stream and `issuer` must be the program admin,
+/// otherwise `issuer` must be that stream's authority
+pub fn issue_promo_credit(
+    issuer: Pubkey,
+    user: Pubkey,
+    stream_id: u64,
+    amount: u64,
+) -> Instruction {
+    build(
+        accounts::IssuePromoCredit {
+            promo_credit: promo_credit_pda(&user, stream_id).0,
+            stream: (stream_id != 0).then(|| stream_pda(stream_id).0),
+            config: (stream_id == 0).then(|| config_pda().0),
+            issuer,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::IssuePromoCredit {
+            user,
+            stream_id,
+            amount,
+        },
+    )
+}
+
+pub fn revoke_promo_credit(issuer: Pubkey, user: Pubkey, stream_id: u64) -> Instruction {
+    build(
+        accounts::RevokePromoCredit {
+            promo_credit: promo_credit_pda(&user, stream_id).0,
+            issuer,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::RevokePromoCredit { user, stream_id },
+    )
+}
+
+pub fn register_referrer(referrer: Pubkey) -> Instruction {
+    build(
+        accounts::RegisterReferrer {
+            referral: referral_pda(&referrer).0,
+            referrer,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::RegisterReferrer {},
+    )
+}
+
+pub fn claim_referral_fees(referrer: Pubkey) -> Instruction {
+    build(
+        accounts::ClaimReferralFees {
+            referral: referral_pda(&referrer).0,
+            referrer,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::ClaimReferralFees {},
+    )
+}
+
+/// Offer a head-to-head bet on `team_id` at fixed odds. Only `counterparty`
+/// may accept it, or anyone when `None`
+pub fn create_challenge(
+    creator: Pubkey,
+    stream_id: u64,
+    challenge_id: u64,
+    team_id: u8,
+    creator_stake: u64,
+    counterparty_stake: u64,
+    counterparty: Option<Pubkey>,
+) -> Instruction {
+    build(
+        accounts::CreateChallenge {
+            stream: stream_pda(stream_id).0,
+            challenge: challenge_pda(stream_id, &creator, challenge_id).0,
+            creator,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::CreateChallenge {
+            stream_id,
+            challenge_id,
+            team_id,
+            creator_stake,
+            counterparty_stake,
+            counterparty: counterparty.unwrap_or_default(),
+        },
+    )
+}
+
+pub fn accept_challenge(
+    acceptor: Pubkey,
+    stream_id: u64,
+    creator: Pubkey,
+    challenge_id: u64,
+) -> Instruction {
+    build(
+        accounts::AcceptChallenge {
+            stream: stream_pda(stream_id).0,
+            challenge: challenge_pda(stream_id, &creator, challenge_id).0,
+            acceptor,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::AcceptChallenge {
+            stream_id,
+            challenge_id,
+        },
+    )
+}
+
+pub fn cancel_challenge(creator: Pubkey, stream_id: u64, challenge_id: u64) -> Instruction {
+    build(
+        accounts::CancelChallenge {
+            challenge: challenge_pda(stream_id, &creator, challenge_id).0,
+            creator,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::CancelChallenge {
+            stream_id,
+            challenge_id,
+        },
+    )
+}
+
+/// Settle an accepted challenge; any `keeper` may send it
+pub fn settle_challenge(
+    keeper: Pubkey,
+    stream_id: u64,
+    creator: Pubkey,
+    challenge_id: u64,
+    acceptor: Pubkey,
+) -> Instruction {
+    build(
+        accounts::SettleChallenge {
+            stream: stream_pda(stream_id).0,
+            challenge: challenge_pda(stream_id, &creator, challenge_id).0,
+            creator,
+            acceptor,
+            keeper,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SettleChallenge {
+            stream_id,
+            challenge_id,
+        },
+    )
+}
+
+/// Authorize `session_signer` to trade for `user` until `expires_at`,
+/// escrowing `spend_limit` lamports for its bets
+pub fn create_session(
+    user: Pubkey,
+    session_signer: Pubkey,
+    expires_at: i64,
+    spend_limit: u64,
+) -> Instruction {
+    build(
+        accounts::CreateSession {
+            session_token: session_token_pda(&user).0,
+            user,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::CreateSession {
+            session_signer,
+            expires_at,
+            spend_limit,
+        },
+    )
+}
+
+pub fn revoke_session(user: Pubkey) -> Instruction {
+    build(
+        accounts::RevokeSession {
+            session_token: session_token_pda(&user).0,
+            user,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::RevokeSession {},
+    )
+}
+
+/// Sell `pct_bps` of the shares held in `team_id`, or in both teams when it is 0
+pub fn cash_out(
+    trader: impl Into<Trader>,
+    stream_id: u64,
+    team_id: u8,
+    pct_bps: u16,
+) -> Instruction {
+    let trader = trader.into();
+    let user = trader.user();
+    build(
+        accounts::CashOut {
+            stream: stream_pda(stream_id).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            user_profile: user_profile_pda(&user).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            price_history: price_history_pda(stream_id).0,
+            user,
+            signer: trader.signer(),
+            session_token: trader.session_token(),
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::CashOut {
+            stream_id,
+            team_id,
+            pct_bps,
+        },
+    )
+}
+
+/// Move `shares_amount` shares of `from_team` onto the other team, failing
+/// if fewer than `min_shares_out` come back. `referrer` must be the
+/// position's referrer once it has one
+pub fn swap_sides(
+    trader: impl Into<Trader>,
+    stream_id: u64,
+    from_team: u8,
+    shares_amount: u64,
+    min_shares_out: u64,
+    referrer: Option<Pubkey>,
+) -> Instruction {
+    let trader = trader.into();
+    let user = trader.user();
+    build(
+        accounts::SwapSides {
+            stream: stream_pda(stream_id).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            user_profile: user_profile_pda(&user).0,
+            price_history: price_history_pda(stream_id).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            referral: referrer.map(|referrer| referral_pda(&referrer).0),
+            user,
+            signer: trader.signer(),
+            session_token: trader.session_token(),
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SwapSides {
+            stream_id,
+            from_team,
+            shares_amount,
+            min_shares_out,
+        },
+    )
+}
+
+/// Configure bet limits; pass zero to disable any one of them
+pub fn set_bet_limits(
+    authority: Pubkey,
+    stream_id: u64,
+    min_bet: u64,
+    max_bet: u64,
+    max_position: u64,
+    max_price_impact_bps: u16,
+) -> Instruction {
+    build(
+        accounts::SetBetLimits {
+            stream: stream_pda(stream_id).0,
+            authority,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SetBetLimits {
+            stream_id,
+            min_bet,
+            max_bet,
+            max_position,
+            max_price_impact_bps,
+        },
+    )
+}
+
+pub fn set_fees(
+    authority: Pubkey,
+    stream_id: u64,
+    fee_bps: u16,
+    referral_share_bps: u16,
+) -> Instruction {
+    build(
+        accounts::SetFees {
+            stream: stream_pda(stream_id).0,
+            authority,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SetFees {
+            stream_id,
+            fee_bps,
+            referral_share_bps,
+        },
+    )
+}
+
+pub fn set_streamer(
+    authority: Pubkey,
+    stream_id: u64,
+    streamer: Pubkey,
+    streamer_cut_bps: u16,
+) -> Instruction {
+    build(
+        accounts::SetStreamer {
+            stream: stream_pda(stream_id).0,
+            authority,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SetStreamer {
+            stream_id,
+            streamer,
+            streamer_cut_bps,
+        },
+    )
+}
+
+/// Tip `streamer`, which must be the stream's current payout key
+pub fn tip_streamer(tipper: Pubkey, stream_id: u64, streamer: Pubkey, amount: u64) -> Instruction {
+    build(
+        accounts::TipStreamer {
+            stream: stream_pda(stream_id).0,
+            streamer,
+            tipper,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::TipStreamer { stream_id, amount },
+    )
+}
+
+/// Pay an ended stream's streamer their cut of protocol fees; any `keeper` may send it
+pub fn pay_streamer(keeper: Pubkey, stream_id: u64, streamer: Pubkey) -> Instruction {
+    build(
+        accounts::PayStreamer {
+            stream: stream_pda(stream_id).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            streamer,
+            keeper,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::PayStreamer { stream_id },
+    )
+}
+
+/// Withdraw the protocol's fees from an ended stream, paying `streamer`
+/// their cut first if it is still owed
+pub fn withdraw_fees(authority: Pubkey, stream_id: u64, streamer: Pubkey) -> Instruction {
+    build(
+        accounts::WithdrawFees {
+            stream: stream_pda(stream_id).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            streamer,
+            authority,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::WithdrawFees { stream_id },
+    )
+}
+
+pub fn set_circuit_breaker(
+    authority: Pubkey,
+    stream_id: u64,
+    threshold_bps: u16,
+    window_slots: u64,
+    cooldown_slots: u64,
+) -> Instruction {
+    build(
+        accounts::SetCircuitBreaker {
+            stream: stream_pda(stream_id).0,
+            authority,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SetCircuitBreaker {
+            stream_id,
+            threshold_bps,
+            window_slots,
+            cooldown_slots,
+        },
+    )
+}
+
+pub fn set_commit_reveal(
+    authority: Pubkey,
+    stream_id: u64,
+    commit_secs: i64,
+    reveal_secs: i64,
+) -> Instruction {
+    build(
+        accounts::SetCommitReveal {
+            stream: stream_pda(stream_id).0,
+            authority,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SetCommitReveal {
+            stream_id,
+            commit_secs,
+            reveal_secs,
+        },
+    )
+}
+
+/// Commit to a bet hashed with [`crate::commitment_hash`]; `reveal_end` comes
+/// from [`crate::commit_window`]
+pub fn commit_bet(
+    user: Pubkey,
+    stream_id: u64,
+    reveal_end: i64,
+    hash: [u8; 32],
+    escrow: u64,
+) -> Instruction {
+    build(
+        accounts::CommitBet {
+            stream: stream_pda(stream_id).0,
+            commitment: commitment_pda(stream_id, &user, reveal_end).0,
+            user,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::CommitBet {
+            stream_id,
+            reveal_end,
+            hash,
+            escrow,
+        },
+    )
+}
+
+pub fn reveal_bet(
+    user: Pubkey,
+    stream_id: u64,
+    reveal_end: i64,
+    team_id: u8,
+    amount: u64,
+    salt: [u8; 32],
+) -> Instruction {
+    build(
+        accounts::RevealBet {
+            stream: stream_pda(stream_id).0,
+            commitment: commitment_pda(stream_id, &user, reveal_end).0,
+            commit_batch: commit_batch_pda(stream_id, reveal_end).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            user_profile: user_profile_pda(&user).0,
+            user,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::RevealBet {
+            stream_id,
+            reveal_end,
+            team_id,
+            amount,
+            salt,
+        },
+    )
+}
+
+/// Clear a closed commit batch; any keeper may sign
+pub fn clear_commit_batch(keeper: Pubkey, stream_id: u64, reveal_end: i64) -> Instruction {
+    build(
+        accounts::ClearCommitBatch {
+            stream: stream_pda(stream_id).0,
+            commit_batch: commit_batch_pda(stream_id, reveal_end).0,
+            price_history: price_history_pda(stream_id).0,
+            keeper,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::ClearCommitBatch {
+            stream_id,
+            reveal_end,
+        },
+    )
+}
+
+pub fn settle_commitment(user: Pubkey, stream_id: u64, reveal_end: i64) -> Instruction {
+    build(
+        accounts::SettleCommitment {
+            stream: stream_pda(stream_id).0,
+            commit_batch: commit_batch_pda(stream_id, reveal_end).0,
+            commitment: commitment_pda(stream_id, &user, reveal_end).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            user_profile: user_profile_pda(&user).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            user,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SettleCommitment {
+            stream_id,
+            reveal_end,
+        },
+    )
+}
+
+/// Refund a commitment; pass `revealed` when it was revealed, which adds the
+/// batch account the program needs to check it never cleared and the position
+/// holding the pending reveal
+pub fn cancel_commitment(
+    user: Pubkey,
+    stream_id: u64,
+    reveal_end: i64,
+    revealed: bool,
+) -> Instruction {
+    build(
+        accounts::CancelCommitment {
+            stream: stream_pda(stream_id).0,
+            commitment: commitment_pda(stream_id, &user, reveal_end).0,
+            commit_batch: revealed.then(|| commit_batch_pda(stream_id, reveal_end).0),
+            user_position: revealed.then(|| user_position_pda(stream_id, &user).0),
+            stream_vault: stream_vault_pda(stream_id).0,
+            user,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::CancelCommitment {
+            stream_id,
+            reveal_end,
+        },
+    )
+}
+
+pub fn set_auction_mode(authority: Pubkey, stream_id: u64, interval_secs: i64) -> Instruction {
+    build(
+        accounts::SetAuctionMode {
+            stream: stream_pda(stream_id).0,
+            authority,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SetAuctionMode {
+            stream_id,
+            interval_secs,
+        },
+    )
+}
+
+/// Place an auction order; `batch_end` comes from [`crate::auction_batch_end`]
+pub fn submit_order(
+    trader: impl Into<Trader>,
+    stream_id: u64,
+    batch_end: i64,
+    team_id: u8,
+    is_buy: bool,
+    amount: u64,
+) -> Instruction {
+    let trader = trader.into();
+    let user = trader.user();
+    build(
+        accounts::SubmitOrder {
+            stream: stream_pda(stream_id).0,
+            order_batch: order_batch_pda(stream_id, batch_end).0,
+            order: batch_order_pda(stream_id, &user, batch_end).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            user_profile: user_profile_pda(&user).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            user,
+            signer: trader.signer(),
+            session_token: trader.session_token(),
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SubmitOrder {
+            stream_id,
+            batch_end,
+            team_id,
+            is_buy,
+            amount,
+        },
+    )
+}
+
+/// Clear a closed auction batch; any keeper may sign
+pub fn clear_batch(keeper: Pubkey, stream_id: u64, batch_end: i64) -> Instruction {
+    build(
+        accounts::ClearBatch {
+            stream: stream_pda(stream_id).0,
+            order_batch: order_batch_pda(stream_id, batch_end).0,
+            price_history: price_history_pda(stream_id).0,
+            keeper,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::ClearBatch {
+            stream_id,
+            batch_end,
+        },
+    )
+}
+
+/// Pay out `user`'s cleared order; any keeper may sign
+pub fn settle_order(keeper: Pubkey, stream_id: u64, batch_end: i64, user: Pubkey) -> Instruction {
+    build(
+        accounts::SettleOrder {
+            stream: stream_pda(stream_id).0,
+            order_batch: order_batch_pda(stream_id, batch_end).0,
+            order: batch_order_pda(stream_id, &user, batch_end).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            user_profile: user_profile_pda(&user).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            user,
+            keeper,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SettleOrder {
+            stream_id,
+            batch_end,
+        },
+    )
+}
+
+pub fn cancel_order(trader: impl Into<Trader>, stream_id: u64, batch_end: i64) -> Instruction {
+    let trader = trader.into();
+    let user = trader.user();
+    build(
+        accounts::CancelOrder {
+            stream: stream_pda(stream_id).0,
+            order_batch: order_batch_pda(stream_id, batch_end).0,
+            order: batch_order_pda(stream_id, &user, batch_end).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            user,
+            signer: trader.signer(),
+            session_token: trader.session_token(),
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::CancelOrder {
+            stream_id,
+            batch_end,
+        },
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn place_limit_order(
+    trader: impl Into<Trader>,
+    stream_id: u64,
+    order_id: u64,
+    team_id: u8,
+    is_buy: bool,
+    amount: u64,
+    limit_price: u64,
+) -> Instruction {
+    let trader = trader.into();
+    let user = trader.user();
+    build(
+        accounts::PlaceLimitOrder {
+            stream: stream_pda(stream_id).0,
+            limit_order: limit_order_pda(stream_id, &user, order_id).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            user_profile: user_profile_pda(&user).0,
+            user,
+            signer: trader.signer(),
+            session_token: trader.session_token(),
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::PlaceLimitOrder {
+            stream_id,
+            order_id,
+            team_id,
+            is_buy,
+            amount,
+            limit_price,
+        },
+    )
+}
+
+/// Try to fill `(owner, order_id)` limit orders; any keeper may sign.
+/// Orders whose limit has not been crossed are left open
+pub fn match_orders(keeper: Pubkey, stream_id: u64, orders: &[(Pubkey, u64)]) -> Instruction {
+    let mut ix = build(
+        accounts::MatchOrders {
+            stream: stream_pda(stream_id).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            price_history: price_history_pda(stream_id).0,
+            keeper,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::MatchOrders { stream_id },
+    );
+    for (owner, order_id) in orders {
+        ix.accounts.push(AccountMeta::new(
+            limit_order_pda(stream_id, owner, *order_id).0,
+            false,
+        ));
+        ix.accounts.push(AccountMeta::new(
+            user_position_pda(stream_id, owner).0,
+            false,
+        ));
+        ix.accounts
+            .push(AccountMeta::new(user_profile_pda(owner).0, false));
+        ix.accounts.push(AccountMeta::new(*owner, false));
+    }
+    ix
+}
+
+pub fn cancel_limit_order(trader: impl Into<Trader>, stream_id: u64, order_id: u64) -> Instruction {
+    let trader = trader.into();
+    let user = trader.user();
+    build(
+        accounts::CancelLimitOrder {
+            limit_order: limit_order_pda(stream_id, &user, order_id).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            user,
+            signer: trader.signer(),
+            session_token: trader.session_token(),
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::CancelLimitOrder {
+            stream_id,
+            order_id,
+        },
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn set_trigger(
+    trader: impl Into<Trader>,
+    stream_id: u64,
+    team_id: u8,
+    stop_loss_price: u64,
+    take_profit_price: u64,
+    max_slippage_bps: u16,
+    tip: u64,
+) -> Instruction {
+    let trader = trader.into();
+    let user = trader.user();
+    build(
+        accounts::SetTrigger {
+            stream: stream_pda(stream_id).0,
+            position_trigger: position_trigger_pda(stream_id, &user, team_id).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            user,
+            signer: trader.signer(),
+            session_token: trader.session_token(),
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SetTrigger {
+            stream_id,
+            team_id,
+            stop_loss_price,
+            take_profit_price,
+            max_slippage_bps,
+            tip,
+        },
+    )
+}
+
+/// Fire `user`'s trigger on `team_id`; any keeper may sign and collects the tip
+pub fn execute_trigger(keeper: Pubkey, stream_id: u64, user: Pubkey, team_id: u8) -> Instruction {
+    build(
+        accounts::ExecuteTrigger {
+            stream: stream_pda(stream_id).0,
+            position_trigger: position_trigger_pda(stream_id, &user, team_id).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            user_profile: user_profile_pda(&user).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            price_history: price_history_pda(stream_id).0,
+            user,
+            keeper,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::ExecuteTrigger { stream_id, team_id },
+    )
+}
+
+pub fn cancel_trigger(trader: impl Into<Trader>, stream_id: u64, team_id: u8) -> Instruction {
+    let trader = trader.into();
+    let user = trader.user();
+    build(
+        accounts::CancelTrigger {
+            position_trigger: position_trigger_pda(stream_id, &user, team_id).0,
+            user,
+            signer: trader.signer(),
+            session_token: trader.session_token(),
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::CancelTrigger { stream_id, team_id },
+    )
+}
+
+pub fn end_stream(authority: Pubkey, stream_id: u64, winning_team: u8) -> Instruction {
+    build(
+        accounts::EndStream {
+            stream: stream_pda(stream_id).0,
+            authority,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::EndStream {
+            stream_id,
+            winning_team,
+        },
+    )
+}
+
+/// Call the stream off instead of declaring a winner. `authority` must be the
+/// stream's authority
+pub fn void_stream(authority: Pubkey, stream_id: u64) -> Instruction {
+    build(
+        accounts::EndStream {
+            stream: stream_pda(stream_id).0,
+            authority,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::VoidStream { stream_id },
+    )
+}
+
+/// Reclaim `user`'s net invested lamports from a voided stream
+pub fn claim_refund(user: Pubkey, stream_id: u64) -> Instruction {
+    build(
+        accounts::ClaimRefund {
+            stream: stream_pda(stream_id).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            user,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::ClaimRefund { stream_id },
+    )
+}
+
+pub fn claim_winnings(user: Pubkey, stream_id: u64) -> Instruction {
+    build(
+        accounts::ClaimWinnings {
+            stream: stream_pda(stream_id).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            user_profile: user_profile_pda(&user).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            user,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::ClaimWinnings { stream_id },
+    )
+}
+
+/// Claim `user`'s winnings with `relayer` signing and paying fees; the payout goes to `user`
+pub fn relay_claim(relayer: Pubkey, stream_id: u64, user: Pubkey) -> Instruction {
+    build(
+        accounts::RelayClaim {
+            stream: stream_pda(stream_id).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            user_profile: user_profile_pda(&user).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            user,
+            relayer,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::RelayClaim { stream_id },
+    )
+}
+
+/// Settle every position owned by `users`; positions that did not win are skipped on-chain
+pub fn settle_batch(
+    keeper: Pubkey,
+    stream_id: u64,
+    close_positions: bool,
+    users: &[Pubkey],
+) -> Instruction {
+    let mut ix = build(
+        accounts::SettleBatch {
+            stream: stream_pda(stream_id).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            keeper,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SettleBatch {
+            stream_id,
+            close_positions,
+        },
+    );
+    for user in users {
+        ix.accounts.push(AccountMeta::new(
+            user_position_pda(stream_id, user).0,
+            false,
+        ));
+        ix.accounts
+            .push(AccountMeta::new(user_profile_pda(user).0, false));
+        ix.accounts.push(AccountMeta::new(*user, false));
+    }
+    ix
+}
+
+/// Commit the stream's payouts to a Merkle root paying `payout_total` in all.
+/// Write-once, and only before any winner is paid from a position
+pub fn post_payout_root(
+    authority: Pubkey,
+    stream_id: u64,
+    payout_root: [u8; 32],
+    payout_total: u64,
+) -> Instruction {
+    build(
+        accounts::PostPayoutRoot {
+            stream: stream_pda(stream_id).0,
+            authority,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::PostPayoutRoot {
+            stream_id,
+            payout_root,
+            payout_total,
+        },
+    )
+}
+
+/// Claim `payout` with a proof against the posted root; `user` needs no position
+pub fn claim_with_proof(
+    user: Pubkey,
+    stream_id: u64,
+    payout: u64,
+    proof: Vec<[u8; 32]>,
+) -> Instruction {
+    build(
+        accounts::ClaimWithProof {
+            stream: stream_pda(stream_id).0,
+            payout_receipt: payout_receipt_pda(stream_id, &user).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            user,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::ClaimWithProof {
+            stream_id,
+            payout,
+            proof,
+        },
+    )
+}
+
+/// Record a finished position's result in `user`'s profile; any keeper may sign
+pub fn settle_profile(keeper: Pubkey, stream_id: u64, user: Pubkey) -> Instruction {
+    build(
+        accounts::SettleProfile {
+            stream: stream_pda(stream_id).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            user_profile: user_profile_pda(&user).0,
+            keeper,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::SettleProfile { stream_id, user },
+    )
+}
+
+pub fn quote_buy(stream_id: u64, team_id: u8, sol_amount: u64) -> Instruction {
+    build(
+        accounts::QuoteTrade {
+            stream: stream_pda(stream_id).0,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::QuoteBuy {
+            stream_id,
+            team_id,
+            sol_amount,
+        },
+    )
+}
+
+pub fn quote_sell(stream_id: u64, team_id: u8, shares_amount: u64) -> Instruction {
+    build(
+        accounts::QuoteTrade {
+            stream: stream_pda(stream_id).0,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::QuoteSell {
+            stream_id,
+            team_id,
+            shares_amount,
+        },
+    )
+}
+
+pub fn quote_claim(stream_id: u64, user: Pubkey) -> Instruction {
+    build(
+        accounts::QuoteClaim {
+            stream: stream_pda(stream_id).0,
+            user_position: user_position_pda(stream_id, &user).0,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::QuoteClaim { stream_id, user },
+    )
+}
+
+pub fn observe_prices(stream_id: u64) -> Instruction {
+    build(
+        accounts::QuoteTrade {
+            stream: stream_pda(stream_id).0,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::ObservePrices { stream_id },
+    )
+}
+
+pub fn emergency_withdraw(authority: Pubkey, stream_id: u64) -> Instruction {
+    build(
+        accounts::EmergencyWithdraw {
+            stream: stream_pda(stream_id).0,
+            stream_vault: stream_vault_pda(stream_id).0,
+            authority,
+            system_program: system_program::ID,
+            event_authority: event_authority_pda().0,
+            program: PROGRAM_ID,
+        },
+        instruction::EmergencyWithdraw { stream_id },
+    )
+}
@@ -0,0 +1,15 @@
+//! Host-side client for the Prophecy prediction market program.
+//!
+//! Provides PDA derivation, typed instruction builders, account
+//! deserializers and an off-chain quote engine that runs the same
+//! curve math as the on-chain program.
+
+pub mod accounts;
+pub mod instructions;
+pub mod pda;
+pub mod quote;
+
+pub use prophecy::errors::ErrorCode;
+pub use prophecy::helpers::{auction_batch_end, commit_window, commitment_hash};
+pub use prophecy::state::*;
+pub use prophecy::ID as PROGRAM_ID;
@@ -0,0 +1,272 @@
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::bpf_loader_upgradeable;
+
+use crate::PROGRAM_ID;
+
+pub const STREAM_SEED: &[u8] = b"stream";
+pub const STREAM_VAULT_SEED: &[u8] = b"stream_vault";
+pub const USER_POSITION_SEED: &[u8] = b"user_position";
+pub const PRICE_HISTORY_SEED: &[u8] = b"price_history";
+pub const USER_PROFILE_SEED: &[u8] = b"user_profile";
+pub const COMMITMENT_SEED: &[u8] = b"commitment";
+pub const COMMIT_BATCH_SEED: &[u8] = b"commit_batch";
+pub const ORDER_BATCH_SEED: &[u8] = b"order_batch";
+pub const BATCH_ORDER_SEED: &[u8] = b"batch_order";
+pub const LIMIT_ORDER_SEED: &[u8] = b"limit_order";
+pub const POSITION_TRIGGER_SEED: &[u8] = b"position_trigger";
+pub const SESSION_TOKEN_SEED: &[u8] = b"session_token";
+pub const PROMO_CREDIT_SEED: &[u8] = b"promo_credit";
+pub const REFERRAL_SEED: &[u8] = b"referral";
+pub const CHALLENGE_SEED: &[u8] = b"challenge";
+pub const PAYOUT_RECEIPT_SEED: &[u8] = b"payout_receipt";
+pub const CONFIG_SEED: &[u8] = b"config";
+pub const EVENT_AUTHORITY_SEED: &[u8] = b"__event_authority";
+
+/// Derive the program-wide `Config` address
+pub fn config_pda() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CONFIG_SEED], &PROGRAM_ID)
+}
+
+/// Derive this program's ProgramData, which names its upgrade authority
+pub fn program_data_pda() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[PROGRAM_ID.as_ref()], &bpf_loader_upgradeable::ID)
+}
+
+/// Derive the `Stream` account address
+pub fn stream_pda(stream_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[STREAM_SEED, &stream_id.to_le_bytes()], &PROGRAM_ID)
+}
+
+/// Derive the lamport vault holding a stream's pool
+pub fn stream_vault_pda(stream_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[STREAM_VAULT_SEED, &stream_id.to_le_bytes()], &PROGRAM_ID)
+}
+
+/// Derive the candle history account for a stream
+pub fn price_history_pda(stream_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[PRICE_HISTORY_SEED, &stream_id.to_le_bytes()], &PROGRAM_ID)
+}
+
+/// Derive a user's `UserPosition` address for a stream
+pub fn user_position_pda(stream_id: u64, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[USER_POSITION_SEED, &stream_id.to_le_bytes(), user.as_ref()],
+        &PROGRAM_ID,
+    )
+}
+
+/// Derive a wallet's cross-stream `UserProfile` address
+pub fn user_profile_pda(user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[USER_PROFILE_SEED, user.as_ref()], &PROGRAM_ID)
+}
+
+/// Derive a user's `Commitment` address for the batch revealing until `reveal_end`
+pub fn commitment_pda(stream_id: u64, user: &Pubkey, reveal_end: i64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            COMMITMENT_SEED,
+            &stream_id.to_le_bytes(),
+            user.as_ref(),
+            &reveal_end.to_le_bytes(),
+        ],
+        &PROGRAM_ID,
+    )
+}
+
+/// Derive the `CommitBatch` address for the batch revealing until `reveal_end`
+pub fn commit_batch_pda(stream_id: u64, reveal_end: i64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            COMMIT_BATCH_SEED,
+            &stream_id.to_le_bytes(),
+            &reveal_end.to_le_bytes(),
+        ],
+        &PROGRAM_ID,
+    )
+}
+
+/// Derive the `OrderBatch` address for the auction batch ending at `batch_end`
+pub fn order_batch_pda(stream_id: u64, batch_end: i64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            ORDER_BATCH_SEED,
+            &stream_id.to_le_bytes(),
+            &batch_end.to_le_bytes(),
+        ],
+        &PROGRAM_ID,
+    )
+}
+
+/// Derive a user's `BatchOrder` address for the auction batch ending at `batch_end`
+pub fn batch_order_pda(stream_id: u64, user: &Pubkey, batch_end: i64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            BATCH_ORDER_SEED,
+            &stream_id.to_le_bytes(),
+            user.as_ref(),
+            &batch_end.to_le_bytes(),
+        ],
+        &PROGRAM_ID,
+    )
+}
+
+/// Derive a user's `LimitOrder` address
+pub fn limit_order_pda(stream_id: u64, user: &Pubkey, order_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            LIMIT_ORDER_SEED,
+            &stream_id.to_le_bytes(),
+            user.as_ref(),
+            &order_id.to_le_bytes(),
+        ],
+        &PROGRAM_ID,
+    )
+}
+
+/// Derive the stop-loss / take-profit trigger on one team's side of a position
+pub fn position_trigger_pda(stream_id: u64, user: &Pubkey, team_id: u8) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            POSITION_TRIGGER_SEED,
+            &stream_id.to_le_bytes(),
+            user.as_ref(),
+            &[team_id],
+        ],
+        &PROGRAM_ID,
+    )
+}
+
+/// Derive a user's `SessionToken` address
+pub fn session_token_pda(user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[SESSION_TOKEN_SEED, user.as_ref()], &PROGRAM_ID)
+}
+
+/// Derive a user's `PromoCredit` for `stream_id`, or the global one when it is zero
+pub fn promo_credit_pda(user: &Pubkey, stream_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[PROMO_CREDIT_SEED, user.as_ref(), &stream_id.to_le_bytes()],
+        &PROGRAM_ID,
+    )
+}
+
+/// Derive the `Referral` account collecting `referrer`'s fee share
+pub fn referral_pda(referrer: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[REFERRAL_SEED, referrer.as_ref()], &PROGRAM_ID)
+}
+
+/// Derive a creator's `Challenge` address
+pub fn challenge_pda(stream_id: u64, creator: &Pubkey, challenge_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            CHALLENGE_SEED,
+            &stream_id.to_le_bytes(),
+            creator.as_ref(),
+            &challenge_id.to_le_bytes(),
+        ],
+        &PROGRAM_ID,
+    )
+}
+
+/// Derive the receipt marking `user` paid through a payout proof
+pub fn payout_receipt_pda(stream_id: u64, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[PAYOUT_RECEIPT_SEED, &stream_id.to_le_bytes(), user.as_ref()],
+        &PROGRAM_ID,
+    )
+}
+
+/// Derive the authority Anchor uses to sign self-CPI event emission
+pub fn event_authority_pda() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[EVENT_AUTHORITY_SEED], &PROGRAM_ID)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Seeds as spelled out in the program's account constraints
+    fn derive(seeds: &[&[u8]]) -> (Pubkey, u8) {
+        Pubkey::find_program_address(seeds, &prophecy::ID)
+    }
+
+    #[test]
+    fn stream_addresses_match_the_program_seeds() {
+        let id = 7u64.to_le_bytes();
+        assert_eq!(stream_pda(7), derive(&[b"stream", &id]));
+        assert_eq!(stream_vault_pda(7), derive(&[b"stream_vault", &id]));
+        assert_eq!(price_history_pda(7), derive(&[b"price_history", &id]));
+        assert_ne!(stream_pda(7).0, stream_pda(8).0);
+    }
+
+    #[test]
+    fn user_addresses_match_the_program_seeds() {
+        let user = Pubkey::new_unique();
+        let id = 7u64.to_le_bytes();
+        assert_eq!(
+            user_position_pda(7, &user),
+            derive(&[b"user_position", &id, user.as_ref()])
+        );
+        assert_eq!(
+            user_profile_pda(&user),
+            derive(&[b"user_profile", user.as_ref()])
+        );
+        assert_eq!(
+            session_token_pda(&user),
+            derive(&[b"session_token", user.as_ref()])
+        );
+        assert_eq!(
+            promo_credit_pda(&user, 0),
+            derive(&[b"promo_credit", user.as_ref(), &0u64.to_le_bytes()])
+        );
+        assert_eq!(referral_pda(&user), derive(&[b"referral", user.as_ref()]));
+        assert_eq!(
+            payout_receipt_pda(7, &user),
+            derive(&[b"payout_receipt", &id, user.as_ref()])
+        );
+    }
+
+    #[test]
+    fn order_addresses_match_the_program_seeds() {
+        let user = Pubkey::new_unique();
+        let id = 7u64.to_le_bytes();
+        let end = 1_700_000_000i64.to_le_bytes();
+        assert_eq!(
+            commitment_pda(7, &user, 1_700_000_000),
+            derive(&[b"commitment", &id, user.as_ref(), &end])
+        );
+        assert_eq!(
+            commit_batch_pda(7, 1_700_000_000),
+            derive(&[b"commit_batch", &id, &end])
+        );
+        assert_eq!(
+            order_batch_pda(7, 1_700_000_000),
+            derive(&[b"order_batch", &id, &end])
+        );
+        assert_eq!(
+            batch_order_pda(7, &user, 1_700_000_000),
+            derive(&[b"batch_order", &id, user.as_ref(), &end])
+        );
+        assert_eq!(
+            limit_order_pda(7, &user, 3),
+            derive(&[b"limit_order", &id, user.as_ref(), &3u64.to_le_bytes()])
+        );
+        assert_eq!(
+            position_trigger_pda(7, &user, 2),
+            derive(&[b"position_trigger", &id, user.as_ref(), &[2]])
+        );
+        assert_eq!(
+            challenge_pda(7, &user, 3),
+            derive(&[b"challenge", &id, user.as_ref(), &3u64.to_le_bytes()])
+        );
+    }
+
+    #[test]
+    fn program_addresses_match_the_program_seeds() {
+        assert_eq!(config_pda(), derive(&[b"config"]));
+        assert_eq!(event_authority_pda(), derive(&[b"__event_authority"]));
+        assert_eq!(
+            program_data_pda(),
+            Pubkey::find_program_address(&[prophecy::ID.as_ref()], &bpf_loader_upgradeable::ID)
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Off-chain quotes. These call straight into the program's `helpers`
+//! module, so they cannot drift from what the chain executes.
+
+use anchor_lang::Result;
+
+use crate::{BuyQuote, ClaimQuote, SellQuote, Stream, UserPosition};
+
+pub use prophecy::helpers::{
+    average_fill_price, calculate_payout, calculate_price, calculate_price_impact_bps,
+    calculate_shares_out, calculate_sol_out, calculate_swap_out, clear_auction_side,
+    current_cumulative_prices,
+};
+
+/// Quote a purchase of `team_id` shares for `sol_amount` lamports
+pub fn quote_buy(stream: &Stream, team_id: u8, sol_amount: u64) -> Result<BuyQuote> {
+    prophecy::helpers::quote_buy(stream, team_id, sol_amount)
+}
+
+/// Quote selling `shares_amount` shares of `team_id`
+pub fn quote_sell(stream: &Stream, team_id: u8, shares_amount: u64) -> Result<SellQuote> {
+    prophecy::helpers::quote_sell(stream, team_id, shares_amount)
+}
+
+/// Quote what `claim_winnings` would pay a position
+pub fn quote_claim(stream: &Stream, user_position: &UserPosition) -> Result<ClaimQuote> {
+    prophecy::helpers::quote_claim(stream, user_position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::{AnchorDeserialize, Space};
+
+    use crate::ErrorCode;
+
+    const SOL: u64 = 1_000_000_000;
+
+    fn stream() -> Stream {
+        let mut stream = Stream::deserialize(&mut &[0u8; Stream::INIT_SPACE][..]).unwrap();
+        stream.team_a_reserve = 120 * SOL;
+        stream.team_b_reserve = 80 * SOL;
+        stream.fee_bps = 100;
+        stream
+    }
+
+    fn position(team_a_shares: u64, team_b_shares: u64) -> UserPosition {
+        UserPosition {
+            user: Default::default(),
+            stream_id: 0,
+            team_a_shares,
+            team_b_shares,
+            total_invested: 0,
+            has_claimed: false,
+            bump: 0,
+            profile_settled: false,
+            promo_shares_a: 0,
+            promo_shares_b: 0,
+            referrer: Default::default(),
+            pending_invested: 0,
+            open_orders: 0,
+            reserved_shares_a: 0,
+            reserved_shares_b: 0,
+            rent_payer: Default::default(),
+            promo_invested: 0,
+        }
+    }
+
+    #[test]
+    fn buy_quote_follows_the_curve_after_the_fee() {
+        let stream = stream();
+        let quote = quote_buy(&stream, 1, 2 * SOL).unwrap();
+
+        let fee = 2 * SOL / 100;
+        let net = 2 * SOL - fee;
+        let shares_out = calculate_shares_out(net, 120 * SOL, 80 * SOL).unwrap();
+        assert_eq!(quote.fee, fee);
+        assert_eq!(quote.shares_out, shares_out);
+        assert_eq!(
+            quote.price_before,
+            calculate_price(120 * SOL, 80 * SOL).unwrap()
+        );
+        assert_eq!(
+            quote.price_after,
+            calculate_price(120 * SOL - shares_out, 80 * SOL + net).unwrap()
+        );
+        assert_eq!(
+            quote.price_impact_bps,
+            calculate_price_impact_bps(quote.price_before, quote.price_after).unwrap()
+        );
+    }
+
+    #[test]
+    fn buy_quote_prices_team_b_from_its_own_reserve() {
+        let stream = stream();
+        let quote = quote_buy(&stream, 2, SOL).unwrap();
+
+        let net = SOL - SOL / 100;
+        assert_eq!(
+            quote.shares_out,
+            calculate_shares_out(net, 80 * SOL, 120 * SOL).unwrap()
+        );
+        assert_eq!(
+            quote.price_before,
+            calculate_price(80 * SOL, 120 * SOL).unwrap()
+        );
+    }
+
+    #[test]
+    fn sell_quote_follows_the_curve_without_a_fee() {
+        let stream = stream();
+        let quote = quote_sell(&stream, 1, 5 * SOL).unwrap();
+
+        let sol_out = calculate_sol_out(5 * SOL, 120 * SOL, 80 * SOL).unwrap();
+        assert_eq!(quote.sol_out, sol_out);
+        assert_eq!(quote.fee, 0);
+        assert_eq!(
+            quote.price_after,
+            calculate_price(125 * SOL, 80 * SOL - sol_out).unwrap()
+        );
+    }
+
+    #[test]
+    fn quotes_reject_bad_input() {
+        let stream = stream();
+        assert_eq!(
+            quote_buy(&stream, 3, SOL).unwrap_err(),
+            ErrorCode::InvalidTeam.into()
+        );
+        assert_eq!(
+            quote_sell(&stream, 1, 0).unwrap_err(),
+            ErrorCode::InvalidAmount.into()
+        );
+    }
+
+    #[test]
+    fn claim_quote_pays_a_pro_rata_share_of_the_pool() {
+        let mut stream = stream();
+        assert_eq!(
+            quote_claim(&stream, &position(1, 0)).unwrap_err(),
+            ErrorCode::NoWinnerDeclared.into()
+        );
+
+        stream.winning_team = 1;
+        stream.total_pool = 30 * SOL;
+        stream.team_a_shares_sold = 40 * SOL;
+        let quote = quote_claim(&stream, &position(10 * SOL, 3 * SOL)).unwrap();
+        assert_eq!(quote.winning_team, 1);
+        assert_eq!(quote.winning_shares, 10 * SOL);
+        assert_eq!(
+            quote.payout,
+            calculate_payout(30 * SOL, 10 * SOL, 40 * SOL).unwrap()
+        );
+        assert_eq!(quote.payout, 30 * SOL / 4);
+        assert!(!quote.root_posted);
+
+        stream.payout_root = [1; 32];
+        let quote = quote_claim(&stream, &position(10 * SOL, 3 * SOL)).unwrap();
+        assert!(quote.root_posted);
+        assert_eq!(quote.payout, 30 * SOL / 4);
+
+        assert_eq!(
+            quote_claim(&stream, &position(0, 3 * SOL)).unwrap().payout,
+            0
+        );
+        let mut claimed = position(10 * SOL, 0);
+        claimed.has_claimed = true;
+        let quote = quote_claim(&stream, &claimed).unwrap();
+        assert!(quote.has_claimed);
+        assert_eq!(quote.payout, 0);
+    }
+}